@@ -0,0 +1,31 @@
+use std::path::Path;
+
+use color_eyre::owo_colors::OwoColorize;
+use gulfi_ingest::{purge_embedding_cache, spawn_vec_connection};
+use gulfi_server::configuration::get_configuration;
+
+use crate::CliError;
+
+pub fn purge<P: AsRef<Path>>(
+    db_path: P,
+    model: Option<&str>,
+    expired: bool,
+) -> Result<(), CliError> {
+    let conn = spawn_vec_connection(db_path)?;
+    let configuration = get_configuration()?;
+
+    let removed = purge_embedding_cache(
+        &conn,
+        model,
+        expired,
+        configuration.embedding_cache.ttl_secs,
+    )?;
+
+    eprintln!(
+        "{} cached embeddings were removed from {}.",
+        removed.bright_cyan(),
+        "embedding_cache".bright_purple().bold()
+    );
+
+    Ok(())
+}
@@ -14,8 +14,51 @@ app_settings:
     host: "127.0.0.1"
     meta_file_path: "./meta.json"
 embedding_provider:
+    # One of: openai, ollama, http, local-hash
+    kind: openai
     endpoint_url: "https://api.openai.com/v1/embeddings"
     auth_token: "your-secret-token-here"
+    model: "text-embedding-3-small"
+    dimensions: 1536
+    # float or base64. base64 responses are smaller and faster to decode, but not every
+    # compatible service supports them. Defaults to float.
+    encoding_format: base64
+    # Used when the provider doesn't send x-ratelimit-* headers.
+    rate_limits:
+        requests_per_minute: 3000
+        tokens_per_minute: 1000000
+        max_concurrency: 6
+    # Query and document templates of models trained with instructions. `{text}` is
+    # replaced by the input, otherwise the template is used as a prefix.
+    # prompts:
+    #     nomic-embed-text:
+    #         query: "search_query: "
+    #         document: "search_document: "
+    # USD per million tokens, used to estimate costs.
+    pricing:
+        text-embedding-3-small: 0.02
+        text-embedding-3-large: 0.13
+# Query embeddings are cached in memory and in the `embedding_cache` table.
+embedding_cache:
+    memory_ttl_secs: 300
+    memory_tti_secs: 60
+    memory_max_entries: 10000
+    ttl_secs: 2592000
+    max_entries: 100000
+    max_size_mb: 512
+# Stops calling the embedding provider after `failure_threshold` consecutive failures, for
+# `cooldown_secs`. With `fts_fallback`, searches are answered with FTS results meanwhile.
+circuit_breaker:
+    failure_threshold: 5
+    cooldown_secs: 30
+    fts_fallback: true
+# Cohere or Jina compatible endpoint that reorders the first `top_n` results of the searches
+# sent with `rerank=true`.
+# reranker:
+#     endpoint_url: "https://api.jina.ai/v1/rerank"
+#     auth_token: "secret-api-key"
+#     model: jina-reranker-v2-base-multilingual
+#     top_n: 50
 db_settings:
     pool_size: "10"
     db_path: "./gulfi.db"
@@ -0,0 +1,78 @@
+use std::path::Path;
+
+use color_eyre::owo_colors::OwoColorize;
+use eyre::eyre;
+use gulfi_ingest::{
+    Document, import_embeddings, read_embeddings, setup_sqlite, spawn_vec_connection,
+};
+use gulfi_server::configuration::get_configuration;
+
+use crate::CliError;
+
+pub fn handle<P: AsRef<Path>>(
+    db_path: P,
+    docs: &[Document],
+    doc: &str,
+    file: &Path,
+    ids: Option<&Path>,
+    match_field: Option<&str>,
+    model: Option<&str>,
+) -> Result<(), CliError> {
+    let Some(doc) = docs.iter().find(|d| d.name == doc) else {
+        let available = docs.iter().map(|d| &d.name).collect::<Vec<_>>();
+        return Err(CliError::Other(eyre!(
+            "{} is not one of the available documents: {:#?}",
+            doc.bright_red(),
+            available
+        )));
+    };
+
+    let configuration = get_configuration()?;
+    let provider = &configuration.embedding_provider;
+    let model = model.unwrap_or(&provider.model);
+
+    let conn = spawn_vec_connection(db_path)?;
+    setup_sqlite(&conn, doc)?;
+
+    eprintln!("📁 Reading embeddings from {}...", file.display());
+    let records = read_embeddings(file, ids, match_field, doc.dimension())?;
+    let total = records.len();
+
+    let report = import_embeddings(
+        &conn,
+        doc,
+        records,
+        match_field,
+        model,
+        &provider.prompts_for(model),
+    )?;
+
+    eprintln!(
+        "{}/{total} embeddings were imported into {}.",
+        report.inserted,
+        format!("vec_{}", doc.name).bright_purple().bold(),
+    );
+
+    if !report.unmatched.is_empty() {
+        let shown = report
+            .unmatched
+            .iter()
+            .take(10)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        eprintln!(
+            "{} {} entries didn't match any row: {}{}",
+            "⚠️".bright_yellow(),
+            report.unmatched.len(),
+            shown.join(", "),
+            if report.unmatched.len() > shown.len() {
+                ", ..."
+            } else {
+                ""
+            }
+        );
+    }
+
+    Ok(())
+}
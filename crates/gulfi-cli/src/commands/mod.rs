@@ -1,7 +1,10 @@
+pub mod cache;
 pub mod configuration;
 pub mod documents;
+pub mod import;
 pub mod list;
 pub mod server;
 pub mod setup_db;
 pub mod sync;
+pub mod usage;
 pub mod users;
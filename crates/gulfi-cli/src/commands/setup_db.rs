@@ -31,15 +31,7 @@ where
     }
 
     let conn = spawn_vec_connection(db_path)?;
-
-    let Some(doc) = docs.iter().find(|d| d.name == doc) else {
-        let available = docs.iter().map(|d| &d.name).collect::<Vec<_>>();
-        return Err(CliError::Other(eyre!(
-            "{} is not one of the available documents: {:#?}",
-            doc.bright_red(),
-            available
-        )));
-    };
+    let doc = find_document(docs, doc)?;
 
     let doc_name = doc.name.clone();
     if force {
@@ -61,3 +53,15 @@ where
 
     Ok(doc.clone())
 }
+
+/// Looks up the document named `doc` in the meta file.
+pub fn find_document<'a>(docs: &'a [Document], doc: &str) -> Result<&'a Document, CliError> {
+    docs.iter().find(|d| d.name == doc).ok_or_else(|| {
+        let available = docs.iter().map(|d| &d.name).collect::<Vec<_>>();
+        CliError::Other(eyre!(
+            "{} is not one of the available documents: {:#?}",
+            doc.bright_red(),
+            available
+        ))
+    })
+}
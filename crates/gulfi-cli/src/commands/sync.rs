@@ -1,11 +1,18 @@
-use std::{path::Path, time::Instant};
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use color_eyre::owo_colors::OwoColorize;
-use gulfi_ingest::{Document, create_indexes, spawn_vec_connection, sync_fts_data, sync_vec_data};
-use gulfi_openai::OpenAIClient;
+use gulfi_ingest::{
+    Document, OverflowStrategy, SyncOptions, create_indexes, estimate_vec_sync,
+    spawn_vec_connection, sync_fts_data, sync_vec_data, sync_vec_data_batch,
+};
+use gulfi_openai::{
+    EmbeddingClient, EmbeddingProvider, PromptTemplates, batch::BATCH_PRICE_FACTOR,
+};
 use gulfi_server::configuration::get_configuration;
 use rusqlite::Connection;
-use secrecy::ExposeSecret;
 
 use crate::{CliError, ExitOnError, SyncStrategy};
 
@@ -20,39 +27,223 @@ pub fn handle_fts(conn: &Connection, doc: &Document) -> (usize, u128) {
 pub fn handle_vector(
     conn: &Connection,
     doc: &Document,
-    base_delay: u64,
-    chunk_size: usize,
-    client: &OpenAIClient,
+    options: &SyncOptions,
+    client: &EmbeddingClient,
 ) -> Result<(usize, f32, u128), CliError> {
     let rt = tokio::runtime::Runtime::new()?;
 
     let start = Instant::now();
-    let (inserted, average) =
-        rt.block_on(sync_vec_data(conn, doc, base_delay, chunk_size, client))?;
+    let report = rt.block_on(sync_vec_data(conn, doc, options, client))?;
 
     let elapsed = start.elapsed().as_millis();
 
-    Ok((inserted, average, elapsed))
+    if !report.truncated.is_empty() {
+        eprintln!(
+            "{} entries were truncated to fit the context of the model: {:?}",
+            report.truncated.len().bright_yellow(),
+            report.truncated
+        );
+    }
+
+    if report.reused > 0 {
+        eprintln!(
+            "{} entries reused an embedding already in the store.",
+            report.reused.bright_green()
+        );
+    }
+
+    if !report.split.is_empty() {
+        eprintln!(
+            "{} entries were embedded in several pieces: {:?}",
+            report.split.len().bright_yellow(),
+            report.split
+        );
+    }
+
+    if !report.unresolved.is_empty() {
+        let rows: usize = report.unresolved.iter().map(|job| job.rows.len()).sum();
+        eprintln!(
+            "{} entries couldn't be embedded, run `gulfi sync {} vector --resume` to try again:",
+            rows.bright_red(),
+            doc.name
+        );
+
+        for job in &report.unresolved {
+            eprintln!(
+                "    chunk {} ({} attempts): {:?} -> {}",
+                job.id,
+                job.attempts,
+                job.rows,
+                job.last_error.as_deref().unwrap_or("interrupted")
+            );
+        }
+    }
+
+    Ok((report.inserted, report.average, elapsed))
 }
 
-pub fn handle_update<P: AsRef<Path>>(
+pub fn handle_batch(
+    conn: &Connection,
+    doc: &Document,
+    poll_interval: Duration,
+    client: &EmbeddingClient,
+    prompts: &PromptTemplates,
+) -> Result<(usize, u128), CliError> {
+    let EmbeddingClient::OpenAI(client) = client else {
+        return Err(CliError::Other(eyre::eyre!(
+            "The Batch API is only available with the `openai` embedding provider."
+        )));
+    };
+
+    let rt = tokio::runtime::Runtime::new()?;
+
+    let start = Instant::now();
+    let report = rt.block_on(sync_vec_data_batch(
+        conn,
+        doc,
+        client,
+        prompts,
+        &std::env::temp_dir(),
+        poll_interval,
+    ))?;
+    let elapsed = start.elapsed().as_millis();
+
+    if !report.truncated.is_empty() {
+        eprintln!(
+            "{} entries were truncated to fit the context of the model: {:?}",
+            report.truncated.len().bright_yellow(),
+            report.truncated
+        );
+    }
+
+    for (batch_id, status) in &report.failed_batches {
+        eprintln!("batch {} ended as {status}.", batch_id.bright_red());
+    }
+
+    for (id, reason) in &report.failed {
+        eprintln!("entry {} couldn't be embedded: {reason}", id.bright_red());
+    }
+
+    Ok((report.inserted, elapsed))
+}
+
+pub fn handle_dry_run<P: AsRef<Path>>(
     db_path: P,
     doc: &Document,
     strat: &SyncStrategy,
-    base_delay: u64,
-    chunk_size: usize,
+    options: &SyncOptions,
+    batch: bool,
 ) -> Result<(), CliError> {
+    if let SyncStrategy::Fts = strat {
+        eprintln!(
+            "Syncing {} doesn't send any request.",
+            format!("fts_{}", doc.name).bright_cyan().bold()
+        );
+        return Ok(());
+    }
+
     let conn = spawn_vec_connection(db_path)?;
+    let synced: bool = conn.query_row(
+        "select count(*) > 0 from sqlite_master where type = 'table' and name = ?",
+        [&doc.name],
+        |row| row.get(0),
+    )?;
+    if !synced {
+        eprintln!(
+            "{} hasn't been synced yet, there are no entries to estimate.",
+            doc.name.bright_cyan().bold()
+        );
+        return Ok(());
+    }
+
     let configuration = get_configuration()?;
-    let client = OpenAIClient::new(
-        configuration
-            .embedding_provider
-            .auth_token
-            .expose_secret()
-            .to_string(),
-        configuration.embedding_provider.endpoint_url,
+    let provider = &configuration.embedding_provider;
+    doc.check_dimension(provider.dimensions as usize)?;
+    let client = provider.build_client();
+    let options = &SyncOptions {
+        prompts: provider.prompts_for(client.model()),
+        ..options.clone()
+    };
+
+    let estimate = estimate_vec_sync(&conn, doc, options, &client)?;
+    let model = client.model();
+
+    eprintln!(
+        "{} entries would be embedded with {} in {} requests, about {} tokens.",
+        estimate.entries,
+        model.bright_cyan(),
+        estimate.requests,
+        estimate.tokens.bright_cyan(),
     );
 
+    if estimate.reused > 0 {
+        eprintln!(
+            "{} of them would reuse an embedding already in the store.",
+            estimate.reused.bright_green()
+        );
+    }
+
+    let cost = provider.cost(model, estimate.tokens as u64).map(|cost| {
+        if batch {
+            cost * BATCH_PRICE_FACTOR
+        } else {
+            cost
+        }
+    });
+    match cost {
+        Some(cost) => eprintln!(
+            "Estimated cost{}: {}",
+            if batch { " with the Batch API" } else { "" },
+            format!("${cost:.4}").bright_green().bold()
+        ),
+        None => eprintln!(
+            "{} There's no price for {model} in `embedding_provider.pricing`.",
+            "⚠️".bright_yellow()
+        ),
+    }
+
+    if !estimate.truncated.is_empty() {
+        eprintln!(
+            "{} entries would be truncated to fit the context of the model.",
+            estimate.truncated.len().bright_yellow()
+        );
+    }
+
+    if !estimate.split.is_empty() {
+        eprintln!(
+            "{} entries would be embedded in several pieces.",
+            estimate.split.len().bright_yellow()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn handle_update<P: AsRef<Path>>(
+    db_path: P,
+    doc: &Document,
+    strat: &SyncStrategy,
+    options: &SyncOptions,
+    batch: Option<Duration>,
+) -> Result<(), CliError> {
+    if batch.is_some() && options.overflow == OverflowStrategy::Split {
+        return Err(CliError::Other(eyre::eyre!(
+            "`--overflow split` isn't supported with `--batch`, the pieces of an entry can't be merged back."
+        )));
+    }
+
+    let conn = spawn_vec_connection(db_path)?;
+    let configuration = get_configuration()?;
+    if !matches!(strat, SyncStrategy::Fts) {
+        doc.check_dimension(configuration.embedding_provider.dimensions as usize)?;
+    }
+    let client = configuration.embedding_provider.build_client();
+    let prompts = configuration.embedding_provider.prompts_for(client.model());
+    let options = &SyncOptions {
+        prompts: prompts.clone(),
+        ..options.clone()
+    };
+
     match strat {
         SyncStrategy::Fts => {
             let (inserted, elapsed) = handle_fts(&conn, doc);
@@ -63,29 +254,49 @@ pub fn handle_update<P: AsRef<Path>>(
             );
         }
         SyncStrategy::Vector => {
-            let (inserted, average, vec_elapsed) =
-                handle_vector(&conn, doc, base_delay, chunk_size, &client).or_exit();
+            if let Some(poll_interval) = batch {
+                let (inserted, elapsed) =
+                    handle_batch(&conn, doc, poll_interval, &client, &prompts).or_exit();
 
-            eprintln!(
-                "{inserted} entries were synced in {} ({vec_elapsed} ms, average of {average} ms per chunk).",
-                format!("vec_{}", doc.name).bright_purple().bold(),
-            );
+                eprintln!(
+                    "{inserted} entries were synced in {} ({elapsed} ms).",
+                    format!("vec_{}", doc.name).bright_purple().bold(),
+                );
+            } else {
+                let (inserted, average, vec_elapsed) =
+                    handle_vector(&conn, doc, options, &client).or_exit();
+
+                eprintln!(
+                    "{inserted} entries were synced in {} ({vec_elapsed} ms, average of {average} ms per chunk).",
+                    format!("vec_{}", doc.name).bright_purple().bold(),
+                );
+            }
         }
         SyncStrategy::All => {
             let (inserted_fts, fts_elapsed) = handle_fts(&conn, doc);
 
-            let (inserted, average, vec_elapsed) =
-                handle_vector(&conn, doc, base_delay, chunk_size, &client).or_exit();
-
             eprintln!(
                 "{inserted_fts} entries were synced in {} ({fts_elapsed} ms).",
                 format!("fts_{}", doc.name).bright_cyan().bold(),
             );
 
-            eprintln!(
-                "{inserted} entries were synced in {} ({vec_elapsed} ms, average of {average} ms per chunk).",
-                format!("vec_{}", doc.name).bright_purple().bold(),
-            );
+            if let Some(poll_interval) = batch {
+                let (inserted, elapsed) =
+                    handle_batch(&conn, doc, poll_interval, &client, &prompts).or_exit();
+
+                eprintln!(
+                    "{inserted} entries were synced in {} ({elapsed} ms).",
+                    format!("vec_{}", doc.name).bright_purple().bold(),
+                );
+            } else {
+                let (inserted, average, vec_elapsed) =
+                    handle_vector(&conn, doc, options, &client).or_exit();
+
+                eprintln!(
+                    "{inserted} entries were synced in {} ({vec_elapsed} ms, average of {average} ms per chunk).",
+                    format!("vec_{}", doc.name).bright_purple().bold(),
+                );
+            }
         }
     }
 
@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use color_eyre::owo_colors::OwoColorize;
+use gulfi_ingest::{UsageSource, spawn_vec_connection, usage_since};
+use gulfi_openai::batch::BATCH_PRICE_FACTOR;
+use gulfi_server::configuration::get_configuration;
+
+use crate::CliError;
+
+pub fn handle<P: AsRef<Path>>(db_path: P, days: u32) -> Result<(), CliError> {
+    let conn = spawn_vec_connection(db_path)?;
+    let configuration = get_configuration()?;
+    let provider = &configuration.embedding_provider;
+
+    let records = usage_since(&conn, days)?;
+    if records.is_empty() {
+        eprintln!("No embedding usage was recorded in the last {days} days.");
+        return Ok(());
+    }
+
+    println!(
+        "{:<12} {:<8} {:<16} {:<24} {:>9} {:>12} {:>10}",
+        "day", "source", "document", "model", "requests", "tokens", "cost"
+    );
+
+    let mut total_tokens = 0;
+    let mut total_cost = 0.0;
+    let mut unpriced = false;
+
+    for record in &records {
+        let mut cost = provider.cost(&record.model, record.prompt_tokens);
+        if record.source == UsageSource::Batch.as_str() {
+            cost = cost.map(|cost| cost * BATCH_PRICE_FACTOR);
+        }
+        total_tokens += record.prompt_tokens;
+        match cost {
+            Some(cost) => total_cost += cost,
+            None => unpriced = true,
+        }
+
+        println!(
+            "{:<12} {:<8} {:<16} {:<24} {:>9} {:>12} {:>10}",
+            record.day,
+            record.source,
+            record.doc.as_deref().unwrap_or("-"),
+            record.model,
+            record.requests,
+            record.prompt_tokens,
+            cost.map_or_else(|| "?".to_owned(), |cost| format!("${cost:.4}")),
+        );
+    }
+
+    println!();
+    println!(
+        "{} tokens in the last {days} days, about {}.",
+        total_tokens.bright_cyan(),
+        format!("${total_cost:.4}").bright_green().bold()
+    );
+
+    if unpriced {
+        eprintln!(
+            "{} Some models have no price in `embedding_provider.pricing`, their cost isn't included.",
+            "⚠️".bright_yellow()
+        );
+    }
+
+    Ok(())
+}
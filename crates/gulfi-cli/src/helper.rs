@@ -4,7 +4,7 @@ use std::path::Path;
 
 use color_eyre::owo_colors::OwoColorize;
 use eyre::{Result, eyre};
-use gulfi_ingest::{Document, Field};
+use gulfi_ingest::{Document, Field, SearchDefaults};
 
 pub const WIDTH: usize = 4;
 
@@ -62,6 +62,8 @@ pub fn run_new() -> Result<()> {
     let new_doc = Document {
         name: name.clone(),
         fields,
+        dimension: None,
+        search: SearchDefaults::default(),
     };
 
     let mut all_docs: Vec<Document> = if path.exists() {
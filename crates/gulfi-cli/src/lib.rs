@@ -6,6 +6,7 @@ pub use gulfi_server::configuration::get_configuration;
 
 use clap::{Parser, Subcommand, ValueEnum, command, crate_version};
 use eyre::Result;
+use gulfi_ingest::OverflowStrategy;
 use gulfi_server::configuration::Settings;
 use std::{net::IpAddr, path::PathBuf};
 
@@ -99,6 +100,67 @@ pub enum Command {
         /// Sets the size of the chunks when splitting the entries for processing.
         #[arg(long, default_value_t = 1024)]
         chunk_size: usize,
+
+        /// Sets the maximum amount of tokens sent in a single request.
+        #[arg(long, default_value_t = 300_000)]
+        max_tokens: usize,
+
+        /// Sets what to do with entries longer than the context of the model.
+        #[arg(long, value_enum, default_value_t = OverflowStrategy::Truncate)]
+        overflow: OverflowStrategy,
+
+        /// Only embeds the entries left unresolved by a previous sync. Batch syncs resume on
+        /// their own.
+        #[arg(long, default_value = "false", conflicts_with_all = ["force", "batch"])]
+        resume: bool,
+
+        /// Estimates the requests, tokens and cost of the sync without sending anything or
+        /// changing the database.
+        #[arg(long, default_value = "false", conflicts_with = "force")]
+        dry_run: bool,
+
+        /// Embeds through the OpenAI Batch API instead of the synchronous endpoint.
+        #[arg(long, default_value = "false")]
+        batch: bool,
+
+        /// Sets the time between status checks of a batch in seconds.
+        #[arg(long, default_value_t = 60)]
+        poll_interval: u64,
+    },
+    /// Imports precomputed embeddings into a document.
+    ImportEmbeddings {
+        document: String,
+
+        /// `.jsonl` file with one `{"id", "embedding"}` object per line, or a `.npy` matrix.
+        file: PathBuf,
+
+        /// File with one id (or key) per line, matching the rows of a `.npy` matrix.
+        #[arg(long)]
+        ids: Option<PathBuf>,
+
+        /// Matches rows by this `unique` field instead of by id.
+        #[arg(long)]
+        match_field: Option<String>,
+
+        /// Model the embeddings were computed with, the configured one if it's not set.
+        #[arg(long)]
+        model: Option<String>,
+    },
+    /// Shows the tokens spent on embeddings and their estimated cost.
+    Usage {
+        /// Sets how many days back to look.
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+    },
+    /// Removes the query embeddings stored by the server in `embedding_cache`.
+    PurgeCache {
+        /// Only removes the embeddings of this model.
+        #[arg(long)]
+        model: Option<String>,
+
+        /// Only removes the embeddings older than `embedding_cache.ttl_secs`.
+        #[arg(long, default_value = "false")]
+        expired: bool,
     },
     /// Lists all defined documents.
     List {
@@ -1,4 +1,5 @@
 use std::borrow::Borrow;
+use std::collections::BTreeMap;
 use std::fmt::Write as _;
 use std::{
     fmt::Debug,
@@ -12,14 +13,116 @@ use serde::{Deserialize, Deserializer, Serialize};
 
 use tracing::{error, info, warn};
 
+use crate::DIMENSION;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Document {
     #[serde(deserialize_with = "to_lowercase")]
     pub name: String,
     pub fields: Vec<Field>,
+    /// Dimension of the embeddings stored in `vec_{name}`. Defaults to [`DIMENSION`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimension: Option<usize>,
+    /// Used by the hybrid searches when the request doesn't set them.
+    #[serde(default, skip_serializing_if = "SearchDefaults::is_default")]
+    pub search: SearchDefaults,
+}
+
+/// Parameters of the searches of a document.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SearchDefaults {
+    /// Constant added to the ranks by Reciprocal Rank Fusion, the higher it is the less the
+    /// first positions stand out.
+    pub rrf_k: u32,
+    /// FTS matches considered, all of them if it's not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fts_limit: Option<u64>,
+    /// Nearest neighbors considered, `k` if it's not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vec_limit: Option<u64>,
+    /// Weight of each FTS column in the BM25 ranking, 1 if it's not set. The fields that are
+    /// part of `vec_input` share its column.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fts_weights: BTreeMap<String, f64>,
+}
+
+impl Default for SearchDefaults {
+    fn default() -> Self {
+        Self {
+            rrf_k: 60,
+            fts_limit: None,
+            vec_limit: None,
+            fts_weights: BTreeMap::new(),
+        }
+    }
+}
+
+impl SearchDefaults {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
 }
 
 impl Document {
+    pub fn dimension(&self) -> usize {
+        self.dimension.unwrap_or(DIMENSION)
+    }
+
+    /// Looks up a field by name.
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    /// Columns of the FTS table of the document, in order.
+    pub fn fts_columns(&self) -> Vec<&str> {
+        std::iter::once("vec_input")
+            .chain(
+                self.fields
+                    .iter()
+                    .filter(|f| !f.vec_input)
+                    .map(|f| f.name.as_str()),
+            )
+            .collect()
+    }
+
+    /// Checks the `search` section against the fields of the document: every weight has to be
+    /// of an FTS column, and a non-negative number.
+    pub fn validate(&self) -> eyre::Result<()> {
+        let columns = self.fts_columns();
+
+        for (column, weight) in &self.search.fts_weights {
+            if !columns.contains(&column.as_str()) {
+                return Err(eyre!(
+                    "`{column}` isn't indexed by FTS in {}, it can't have a weight. The columns are {columns:?}.",
+                    self.name
+                ));
+            }
+            if !weight.is_finite() || *weight < 0.0 {
+                return Err(eyre!(
+                    "The weight of `{column}` in {} can't be negative.",
+                    self.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the vectors of the document have as many entries as the embeddings of the
+    /// provider, `vec0` rejects any other length.
+    pub fn check_dimension(&self, dimensions: usize) -> eyre::Result<()> {
+        if self.dimension() != dimensions {
+            return Err(eyre!(
+                "{} stores vectors of {} dimensions, but the embedding provider is configured with {dimensions}. Set `dimension` in the meta file or `embedding_provider.dimensions` so they match.",
+                self.name,
+                self.dimension()
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn generate_vec_input(&self) -> String {
         let mut result = String::from("'  '");
         for i in &self.fields {
@@ -117,3 +220,83 @@ where
     let s = String::deserialize(deserializer)?;
     Ok(s.to_lowercase())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_defaults_fill_the_missing_parameters() {
+        let doc: Document = serde_json::from_str(r#"{"name": "Demo", "fields": []}"#).unwrap();
+        assert_eq!(doc.name, "demo");
+        assert!(doc.search.is_default());
+
+        let doc: Document =
+            serde_json::from_str(r#"{"name": "demo", "fields": [], "search": {"fts_limit": 500}}"#)
+                .unwrap();
+        assert_eq!(
+            doc.search,
+            SearchDefaults {
+                fts_limit: Some(500),
+                ..SearchDefaults::default()
+            }
+        );
+        assert_eq!(doc.search.rrf_k, 60);
+    }
+
+    #[test]
+    fn search_defaults_are_left_out_of_meta_file() {
+        let doc: Document = serde_json::from_str(r#"{"name": "demo", "fields": []}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&doc).unwrap(),
+            r#"{"name":"demo","fields":[]}"#
+        );
+
+        let doc: Document = serde_json::from_str(
+            r#"{"name": "demo", "fields": [], "search": {"rrf_k": 20, "vec_limit": 200}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&doc.search).unwrap(),
+            r#"{"rrf_k":20,"vec_limit":200}"#
+        );
+    }
+
+    #[test]
+    fn fts_weights_are_checked_against_the_columns() {
+        let parse = |weights: &str| -> Document {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "name": "demo",
+                    "fields": [
+                        {{"name": "email", "vec_input": false, "unique": true}},
+                        {{"name": "bio", "vec_input": true, "unique": false}}
+                    ],
+                    "search": {{"fts_weights": {weights}}}
+                }}"#
+            ))
+            .unwrap()
+        };
+
+        let doc = parse(r#"{"vec_input": 2.0, "email": 0}"#);
+        assert_eq!(doc.search.fts_weights["vec_input"], 2.0);
+        assert!(doc.validate().is_ok());
+
+        // `bio` is part of `vec_input`, it doesn't have its own column.
+        assert!(parse(r#"{"bio": 2.0}"#).validate().is_err());
+        assert!(parse(r#"{"edad": 2.0}"#).validate().is_err());
+        assert!(parse(r#"{"email": -1}"#).validate().is_err());
+    }
+
+    #[test]
+    fn dimension_has_to_match_the_provider() {
+        let doc: Document = serde_json::from_str(r#"{"name": "demo", "fields": []}"#).unwrap();
+        assert!(doc.check_dimension(DIMENSION).is_ok());
+        assert!(doc.check_dimension(768).is_err());
+
+        let doc: Document =
+            serde_json::from_str(r#"{"name": "demo", "fields": [], "dimension": 768}"#).unwrap();
+        assert!(doc.check_dimension(768).is_ok());
+        assert!(doc.check_dimension(DIMENSION).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use eyre::{Result, eyre};
+use serde_json::Value;
+
+/// How an imported vector is matched against the rows of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportKey {
+    /// The `id` of the row in the document table.
+    Id(u64),
+    /// The value of a `unique` field of the document.
+    Key(String),
+}
+
+impl std::fmt::Display for ImportKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportKey::Id(id) => write!(f, "{id}"),
+            ImportKey::Key(key) => write!(f, "{key}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportRecord {
+    pub key: ImportKey,
+    pub embedding: Vec<f32>,
+}
+
+/// Reads precomputed embeddings from `path`.
+///
+/// `.jsonl`/`.ndjson` files hold one object per line with an `embedding` (or `vector`) array and
+/// either an `id`, a `key` or a property named after `match_field`. `.npy` files hold a 2D
+/// float32/float64 matrix whose rows are matched, in order, with the lines of `ids_path`.
+pub fn read_embeddings<P: AsRef<Path>>(
+    path: P,
+    ids_path: Option<&Path>,
+    match_field: Option<&str>,
+    dimension: usize,
+) -> Result<Vec<ImportRecord>> {
+    let path = path.as_ref();
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    match ext.as_str() {
+        "jsonl" | "ndjson" => {
+            if ids_path.is_some() {
+                return Err(eyre!("`--ids` is only supported for `.npy` files"));
+            }
+            let reader = BufReader::new(File::open(path)?);
+            parse_jsonl(reader, match_field, dimension)
+        }
+        "npy" => {
+            let Some(ids_path) = ids_path else {
+                return Err(eyre!("`.npy` files need an id list, use `--ids <FILE>`"));
+            };
+
+            let mut bytes = Vec::new();
+            File::open(path)?.read_to_end(&mut bytes)?;
+            let (rows, cols, data) = parse_npy(&bytes)?;
+
+            if cols != dimension {
+                return Err(eyre!(
+                    "The embeddings have dimension {cols} but the document expects {dimension}"
+                ));
+            }
+
+            let keys = read_keys(BufReader::new(File::open(ids_path)?), match_field)?;
+            if keys.len() != rows {
+                return Err(eyre!(
+                    "The id list has {} entries but the matrix has {rows} rows",
+                    keys.len()
+                ));
+            }
+
+            Ok(keys
+                .into_iter()
+                .zip(data.chunks_exact(cols))
+                .map(|(key, embedding)| ImportRecord {
+                    key,
+                    embedding: embedding.to_vec(),
+                })
+                .collect())
+        }
+        other => Err(eyre!(
+            "unknown file extension for embeddings: `{other}`, use `.jsonl` or `.npy`"
+        )),
+    }
+}
+
+fn parse_jsonl<R: BufRead>(
+    reader: R,
+    match_field: Option<&str>,
+    dimension: usize,
+) -> Result<Vec<ImportRecord>> {
+    let mut records = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = i + 1;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let value: Value = serde_json::from_str(&line)
+            .map_err(|err| eyre!("line {line_number}: invalid JSON: {err}"))?;
+
+        let embedding = value
+            .get("embedding")
+            .or_else(|| value.get("vector"))
+            .and_then(Value::as_array)
+            .ok_or_else(|| eyre!("line {line_number}: missing `embedding` array"))?
+            .iter()
+            .map(|v| v.as_f64().map(|v| v as f32))
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(|| eyre!("line {line_number}: `embedding` should only hold numbers"))?;
+
+        if embedding.len() != dimension {
+            return Err(eyre!(
+                "line {line_number}: the embedding has dimension {} but the document expects {dimension}",
+                embedding.len()
+            ));
+        }
+
+        let key = match match_field {
+            Some(field) => value
+                .get(field)
+                .or_else(|| value.get("key"))
+                .and_then(value_as_key)
+                .map(ImportKey::Key)
+                .ok_or_else(|| eyre!("line {line_number}: missing `{field}` or `key`"))?,
+            None => value
+                .get("id")
+                .and_then(|id| {
+                    id.as_u64()
+                        .or_else(|| id.as_str().and_then(|s| s.trim().parse().ok()))
+                })
+                .map(ImportKey::Id)
+                .ok_or_else(|| eyre!("line {line_number}: missing a numeric `id`"))?,
+        };
+
+        records.push(ImportRecord { key, embedding });
+    }
+
+    Ok(records)
+}
+
+fn value_as_key(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn read_keys<R: BufRead>(reader: R, match_field: Option<&str>) -> Result<Vec<ImportKey>> {
+    let mut keys = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let key = if match_field.is_some() {
+            ImportKey::Key(line.to_owned())
+        } else {
+            ImportKey::Id(
+                line.parse()
+                    .map_err(|_| eyre!("id list line {}: `{line}` is not a valid id", i + 1))?,
+            )
+        };
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+/// Parses a little-endian, C-ordered 2D `.npy` matrix of `f4` or `f8` values.
+///
+/// Returns `(rows, cols, data)` with the data converted to `f32`.
+fn parse_npy(bytes: &[u8]) -> Result<(usize, usize, Vec<f32>)> {
+    const MAGIC: &[u8] = b"\x93NUMPY";
+
+    if bytes.len() < 10 || !bytes.starts_with(MAGIC) {
+        return Err(eyre!("not a valid `.npy` file"));
+    }
+
+    let major = bytes[6];
+    let (header_len, header_start) = match major {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 => {
+            let len = bytes
+                .get(8..12)
+                .ok_or_else(|| eyre!("truncated `.npy` header"))?;
+            (
+                u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize,
+                12,
+            )
+        }
+        v => return Err(eyre!("unsupported `.npy` version {v}")),
+    };
+
+    let header = bytes
+        .get(header_start..header_start + header_len)
+        .ok_or_else(|| eyre!("truncated `.npy` header"))?;
+    let header = std::str::from_utf8(header)?;
+
+    let descr = header_value(header, "descr")
+        .and_then(|d| d.split(',').next())
+        .map(|d| d.trim().trim_matches(|c| c == '\'' || c == '"'))
+        .ok_or_else(|| eyre!("`.npy` header has no `descr`"))?;
+
+    if header_value(header, "fortran_order").is_some_and(|v| v.starts_with("True")) {
+        return Err(eyre!("fortran ordered `.npy` files are not supported"));
+    }
+
+    let shape =
+        header_value(header, "shape").ok_or_else(|| eyre!("`.npy` header has no `shape`"))?;
+    let shape = shape
+        .trim_start_matches('(')
+        .split(')')
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::parse::<usize>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (rows, cols) = match shape.as_slice() {
+        [rows, cols] => (*rows, *cols),
+        _ => return Err(eyre!("expected a 2D matrix, got shape {shape:?}")),
+    };
+
+    let data = &bytes[header_start + header_len..];
+    let values = match descr {
+        "<f4" | "=f4" => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>(),
+        "<f8" | "=f8" => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+            .collect::<Vec<_>>(),
+        other => {
+            return Err(eyre!(
+                "unsupported `.npy` dtype `{other}`, use float32 or float64"
+            ));
+        }
+    };
+
+    if values.len() != rows * cols {
+        return Err(eyre!(
+            "`.npy` data holds {} values but the shape is ({rows}, {cols})",
+            values.len()
+        ));
+    }
+
+    Ok((rows, cols, values))
+}
+
+/// Extracts the raw value following `'key':` in a `.npy` header dict.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!("'{key}':");
+    let start = header.find(&pattern)? + pattern.len();
+    Some(header[start..].trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn npy(descr: &str, shape: &str, data: &[u8]) -> Vec<u8> {
+        let mut header =
+            format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn parses_f4_matrix() {
+        let data: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let (rows, cols, values) = parse_npy(&npy("<f4", "(2, 3)", &data)).unwrap();
+
+        assert_eq!((rows, cols), (2, 3));
+        assert_eq!(values, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn rejects_1d_npy() {
+        let data: Vec<u8> = [1.0f32, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert!(parse_npy(&npy("<f4", "(2,)", &data)).is_err());
+    }
+
+    #[test]
+    fn parses_jsonl_by_id_and_by_key() {
+        let input =
+            "{\"id\": 3, \"embedding\": [0.5, 1.0]}\n\n{\"id\": \"7\", \"vector\": [1, 2]}\n";
+        let records = parse_jsonl(input.as_bytes(), None, 2).unwrap();
+        assert_eq!(records[0].key, ImportKey::Id(3));
+        assert_eq!(records[1].key, ImportKey::Id(7));
+        assert_eq!(records[1].embedding, vec![1.0, 2.0]);
+
+        let input = "{\"email\": \"a@b.com\", \"embedding\": [0.5, 1.0]}\n";
+        let records = parse_jsonl(input.as_bytes(), Some("email"), 2).unwrap();
+        assert_eq!(records[0].key, ImportKey::Key("a@b.com".to_owned()));
+    }
+
+    #[test]
+    fn jsonl_dimension_mismatch_is_an_error() {
+        let input = "{\"id\": 1, \"embedding\": [0.5, 1.0, 2.0]}\n";
+        let err = parse_jsonl(input.as_bytes(), None, 2).unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+    }
+}
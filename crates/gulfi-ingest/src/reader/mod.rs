@@ -1,2 +1,4 @@
 mod datasources;
 pub use datasources::*;
+mod embeddings;
+pub use embeddings::*;
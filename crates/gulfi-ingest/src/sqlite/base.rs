@@ -2,6 +2,7 @@ use std::fmt::Write as _;
 use std::io::Write;
 use std::sync::atomic::AtomicBool;
 use std::{
+    collections::{HashMap, HashSet},
     fmt::Debug,
     fs::File,
     io::BufReader,
@@ -17,10 +18,11 @@ use color_eyre::owo_colors::OwoColorize;
 use csv::ReaderBuilder;
 use eyre::{Result, eyre};
 use futures::StreamExt;
-use gulfi_openai::{OpenAIClient, embedding_message::EmbeddingMessage};
+use gulfi_openai::{EmbeddingProvider, Embeddings, embedding_message::EmbeddingMessage};
 use rusqlite::{
     Connection,
     ffi::{sqlite3, sqlite3_api_routines, sqlite3_auto_extension},
+    functions::FunctionFlags,
     params_from_iter,
 };
 use sqlite_vec::sqlite3_vec_init;
@@ -29,6 +31,17 @@ use zerocopy::IntoBytes;
 
 use crate::Filetype;
 use crate::reader::{Document, parse_sources};
+use crate::sqlite::chunks::{
+    Chunk, SyncEstimate, SyncOptions, SyncReport, build_chunks, merge_pieces,
+};
+use crate::sqlite::jobs::{
+    clear_jobs, create_job, fail_job, finish_job, setup_jobs_table, unresolved_jobs,
+};
+use crate::sqlite::metadata::record_embedding_metadata;
+use crate::sqlite::store::{
+    ContentIndex, fill_from_store, setup_store_table, store_embedding, stored_hashes,
+};
+use crate::sqlite::usage::{UsageSource, record_usage, setup_usage_table};
 
 pub const DIMENSION: usize = 1536;
 const KEYWORDS: &[&str] = &["SELECT", "DROP", "DELETE", "UPDATE", "INSERT", "TABLE"];
@@ -36,10 +49,9 @@ const KEYWORDS: &[&str] = &["SELECT", "DROP", "DELETE", "UPDATE", "INSERT", "TAB
 pub async fn sync_vec_data(
     conn: &Connection,
     doc: &Document,
-    base_delay: u64,
-    chunk_size: usize,
-    client: &OpenAIClient,
-) -> Result<(usize, f32)> {
+    options: &SyncOptions,
+    client: &impl EmbeddingProvider,
+) -> Result<SyncReport> {
     let doc_name = doc.name.clone();
     validate_sql_identifier(&doc_name).expect("Should be a safe identifier");
 
@@ -49,33 +61,146 @@ pub async fn sync_vec_data(
         .flush()
         .expect("Should be able to flush the pipe");
 
-    let mut statement = conn.prepare_cached(&format!("select id, vec_input from {doc_name}"))?;
-
-    let v_inputs: Vec<(u64, String)> = match statement.query_map([], |row| {
-        let id: u64 = row.get(0)?;
-        let input: String = row.get::<_, String>(1)?;
-        Ok((id, input))
-    }) {
-        Ok(rows) => rows
-            .map(|v| v.expect("Should have a 'vec_input' field"))
-            .collect(),
-        Err(err) => return Err(eyre!(err)),
-    };
+    setup_jobs_table(conn)?;
+    setup_store_table(conn)?;
+
+    let model = client.model();
+    let dimensions = doc.dimension();
+    let estimator = client.token_estimator();
+    let max_input_tokens = client.max_input_tokens();
+
+    let mut jobs = Vec::new();
+    let mut truncated = Vec::new();
+    let mut split = Vec::new();
+    let mut index = ContentIndex::default();
+    let mut reused = 0;
+    // Rows whose text is also in another row, they're filled once that one is embedded.
+    let mut repeated = Vec::new();
+
+    if options.resume {
+        let pending = unresolved_jobs(conn, &doc_name)?;
+        if pending.is_empty() {
+            println!("There's no pending work in {doc_name}.");
+        }
+
+        // Each job is embedded again as a single chunk, it was already under the limits.
+        let unbounded = SyncOptions {
+            chunk_size: usize::MAX,
+            max_request_tokens: usize::MAX,
+            ..options.clone()
+        };
+
+        for job in pending {
+            let mut rows = rows_by_id(conn, &doc_name, &job.rows)?;
+            index_rows(&mut index, &rows, options);
+
+            let filled = fill_from_store(conn, &doc_name, model, dimensions, &job.rows, &index)?;
+            reused += filled.len();
+            rows.retain(|(id, _)| !filled.contains(id));
+
+            // The rows repeating the text of another one are filled once it's embedded.
+            let mut seen = HashSet::new();
+            rows.retain(|(id, _)| {
+                index
+                    .hash(*id)
+                    .is_none_or(|hash| seen.insert(hash.to_owned()))
+            });
+
+            let (chunks, job_truncated, job_split) =
+                build_chunks(rows, &estimator, max_input_tokens, &unbounded);
+
+            truncated.extend(job_truncated);
+            split.extend(job_split);
+
+            match chunks.into_iter().next() {
+                Some(chunk) => jobs.push((job.id, chunk)),
+                // The rows don't exist anymore.
+                None => finish_job(conn, job.id)?,
+            }
+        }
+    } else {
+        clear_jobs(conn, &doc_name)?;
+
+        let mut statement =
+            conn.prepare_cached(&format!("select id, vec_input from {doc_name}"))?;
+
+        let v_inputs: Vec<(u64, String)> = match statement.query_map([], |row| {
+            let id: u64 = row.get(0)?;
+            let input: String = row.get::<_, String>(1)?;
+            Ok((id, input))
+        }) {
+            Ok(rows) => rows
+                .map(|v| v.expect("Should have a 'vec_input' field"))
+                .collect(),
+            Err(err) => return Err(eyre!(err)),
+        };
+
+        index_rows(&mut index, &v_inputs, options);
+
+        let ids: Vec<u64> = v_inputs.iter().map(|(id, _)| *id).collect();
+        let filled: HashSet<u64> =
+            fill_from_store(conn, &doc_name, model, dimensions, &ids, &index)?
+                .into_iter()
+                .collect();
+        reused += filled.len();
+
+        let mut seen = HashSet::new();
+        let mut copies: HashMap<String, Vec<u64>> = HashMap::new();
+        let mut unique = Vec::with_capacity(v_inputs.len() - filled.len());
+        for (id, input) in v_inputs {
+            if filled.contains(&id) {
+                continue;
+            }
+            match index.hash(id) {
+                Some(hash) if !seen.insert(hash.to_owned()) => {
+                    repeated.push(id);
+                    copies.entry(hash.to_owned()).or_default().push(id);
+                }
+                _ => unique.push((id, input)),
+            }
+        }
+
+        let (chunks, all_truncated, all_split) =
+            build_chunks(unique, &estimator, max_input_tokens, options);
+
+        truncated = all_truncated;
+        split = all_split;
+
+        // The rows repeating a text are tracked in the job of the chunk that embeds it, so they're
+        // reported and resumed along with it if it fails.
+        let tx = conn.unchecked_transaction()?;
+        for chunk in chunks {
+            let mut rows = chunk.indices.clone();
+            for id in &chunk.indices {
+                if let Some(ids) = index.hash(*id).and_then(|hash| copies.remove(hash)) {
+                    rows.extend(ids);
+                }
+            }
+            jobs.push((create_job(&tx, &doc_name, &rows)?, chunk));
+        }
+        tx.commit()?;
+    }
 
     let http_client = reqwest::ClientBuilder::new()
         .deflate(true)
         .gzip(true)
         .build()?;
+    let http_client = &http_client;
 
+    let base_delay = options.base_delay;
     let bar_max = 30;
-    let chunks = v_inputs.chunks(chunk_size).count();
+    let chunks = jobs.len();
     let jobs_done = Arc::new(AtomicUsize::new(0));
 
-    let futures_iterator = v_inputs
-        .chunks(chunk_size)
+    let futures_iterator = jobs
+        .into_iter()
         .enumerate()
-        .map(|(chunk_id, chunk)| {
-            let (indices, v_inputs) = chunk.iter().cloned().unzip();
+        .map(|(chunk_id, (job_id, chunk))| {
+            let Chunk {
+                indices,
+                inputs: v_inputs,
+                ..
+            } = chunk;
             let (tx, mut rx) = tokio::sync::mpsc::channel::<EmbeddingMessage>(10);
 
             let jobs_done = jobs_done.clone();
@@ -105,64 +230,87 @@ pub async fn sync_vec_data(
                 }
             });
 
-            client.embed_vec_with_progress(
-                indices,
-                v_inputs,
-                &http_client,
-                chunk_id,
-                base_delay,
-                tx,
-            )
+            async move {
+                let result = client
+                    .embed_vec_with_progress(
+                        indices,
+                        v_inputs,
+                        http_client,
+                        chunk_id,
+                        base_delay,
+                        tx,
+                    )
+                    .await;
+                (job_id, result)
+            }
         });
 
     let futures_stream = futures::stream::iter(futures_iterator);
     let total_inserted = Arc::new(AtomicUsize::new(0));
     let acc_time_per_chunk = Arc::new(AtomicUsize::new(0));
 
-    futures_stream.for_each_concurrent(Some(6), |future| {
-        let total_inserted = total_inserted.clone();
-        let acc_time_per_chunk = acc_time_per_chunk.clone();
-        let sent_doc_name = doc_name.clone();
-
-        async move {
-            match future.await {
-                Ok((data, millis)) => {
+    // The limiter of the provider may allow fewer requests in flight, never more.
+    futures_stream
+        .for_each_concurrent(client.max_concurrency().max(1), |future| {
+            let total_inserted = total_inserted.clone();
+            let acc_time_per_chunk = acc_time_per_chunk.clone();
+            let sent_doc_name = doc_name.clone();
+            let index = &index;
 
-                    let mut statement =
-                        conn.prepare(&format!("insert into vec_{sent_doc_name}(row_id, vec_input_embedding) values (?,?)")).expect("Should be able to prepare query");
+            async move {
+                let (job_id, result) = future.await;
 
-                    conn.execute("BEGIN TRANSACTION", []).expect(
-                        "Should be a valid SQL sentence",
-                    );
+                let stored = result.and_then(|(data, millis)| {
+                    let insertions = store_chunk(conn, &sent_doc_name, model, index, job_id, data)?;
+                    Ok((insertions, millis))
+                });
 
-                    let mut insertions = 0;
-                    for (id, embedding) in data {
-                        insertions += statement.execute(
-                            rusqlite::params![id, embedding.as_bytes()],
-                        ).unwrap_or_else(|_| panic!("Error inserting in vec_{sent_doc_name}"));
+                match stored {
+                    Ok((insertions, millis)) => {
+                        total_inserted.fetch_add(insertions, Ordering::Relaxed);
 
+                        let millis = millis.try_into().unwrap_or_default();
+                        acc_time_per_chunk.fetch_add(millis, Ordering::Relaxed);
+                    }
+                    Err(err) => {
+                        error!("Error processing chunk: {err}");
+                        if let Err(err) = fail_job(conn, job_id, &err.to_string()) {
+                            error!("Couldn't record the failed chunk {job_id}: {err}");
+                        }
                     }
-                    conn.execute("COMMIT", []).expect(
-                    "Should be a valid SQL sentence",
-                    );
-
-                     total_inserted.fetch_add(insertions, Ordering::Relaxed);
-
-                     let millis = millis.try_into().unwrap_or_default();
-                     acc_time_per_chunk.fetch_add(millis, Ordering::Relaxed);
-
                 }
-                Err(err) => {
-                    error!("Error processing chunk: {err}");
-                },
             }
-        }
-    }).await;
+        })
+        .await;
+
+    // The rows of a previous sync repeating the text of an unresolved chunk weren't tracked, so
+    // every row still without an embedding is looked up.
+    if options.resume {
+        let missing = missing_rows(conn, &doc_name)?;
+        index_rows(&mut index, &missing, options);
+        repeated = missing.into_iter().map(|(id, _)| id).collect();
+    }
+    reused += fill_from_store(conn, &doc_name, model, dimensions, &repeated, &index)?.len();
+
+    let usage = client.usage().take();
+    setup_usage_table(conn)?;
+    record_usage(
+        conn,
+        UsageSource::Sync,
+        Some(&doc_name),
+        client.model(),
+        usage,
+    )?;
+    record_embedding_metadata(conn, &doc_name, client.model(), &options.prompts)?;
 
     let total = total_inserted.load(Ordering::Relaxed);
     let total_acc_chunks = acc_time_per_chunk.load(Ordering::Relaxed);
 
-    let media = total_acc_chunks as f32 / chunks as f32;
+    let media = if chunks == 0 {
+        0.0
+    } else {
+        total_acc_chunks as f32 / chunks as f32
+    };
 
     print!("\r    Progress: ");
     for _ in 0..bar_max {
@@ -181,7 +329,148 @@ pub async fn sync_vec_data(
         .flush()
         .expect("Should be able to flush the pipe");
 
-    Ok((total, media))
+    Ok(SyncReport {
+        inserted: total + reused,
+        reused,
+        average: media,
+        truncated,
+        split,
+        unresolved: unresolved_jobs(conn, &doc_name)?,
+        usage,
+    })
+}
+
+/// Computes the requests and tokens `sync_vec_data` would send with the same `options`.
+pub fn estimate_vec_sync(
+    conn: &Connection,
+    doc: &Document,
+    options: &SyncOptions,
+    client: &impl EmbeddingProvider,
+) -> Result<SyncEstimate> {
+    let doc_name = doc.name.clone();
+    validate_sql_identifier(&doc_name)?;
+    setup_jobs_table(conn)?;
+    setup_store_table(conn)?;
+
+    let rows = if options.resume {
+        let ids: Vec<u64> = unresolved_jobs(conn, &doc_name)?
+            .into_iter()
+            .flat_map(|job| job.rows)
+            .collect();
+        rows_by_id(conn, &doc_name, &ids)?
+    } else {
+        let mut statement = conn.prepare(&format!("select id, vec_input from {doc_name}"))?;
+        statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(u64, String)>, _>>()?
+    };
+
+    let entries = rows.len();
+
+    let mut index = ContentIndex::default();
+    index_rows(&mut index, &rows, options);
+
+    let hashes: Vec<&str> = rows.iter().filter_map(|(id, _)| index.hash(*id)).collect();
+    let mut seen = stored_hashes(conn, client.model(), doc.dimension(), &hashes)?;
+    let rows: Vec<(u64, String)> = rows
+        .into_iter()
+        .filter(|(id, _)| {
+            index
+                .hash(*id)
+                .is_some_and(|hash| seen.insert(hash.to_owned()))
+        })
+        .collect();
+
+    let (chunks, truncated, split) = build_chunks(
+        rows,
+        &client.token_estimator(),
+        client.max_input_tokens(),
+        options,
+    );
+
+    Ok(SyncEstimate {
+        reused: entries - chunks.iter().map(unique_rows).sum::<usize>(),
+        entries,
+        requests: chunks.len(),
+        tokens: chunks.iter().map(|chunk| chunk.tokens).sum(),
+        truncated,
+        split,
+    })
+}
+
+fn rows_by_id(conn: &Connection, doc_name: &str, ids: &[u64]) -> Result<Vec<(u64, String)>> {
+    let mut statement = conn.prepare(&format!(
+        "select id, vec_input from {doc_name} where id in (select value from json_each(?))"
+    ))?;
+
+    let rows = statement
+        .query_map([serde_json::to_string(ids)?], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<Result<Vec<(u64, String)>, _>>()?;
+
+    Ok(rows)
+}
+
+/// Hashes the text each row would send, with the document template applied.
+fn index_rows(index: &mut ContentIndex, rows: &[(u64, String)], options: &SyncOptions) {
+    for (id, input) in rows {
+        index.insert(*id, &options.prompts.apply_document(input));
+    }
+}
+
+/// Rows of a chunk, counting a split row once.
+fn unique_rows(chunk: &Chunk) -> usize {
+    let mut indices = chunk.indices.clone();
+    indices.dedup();
+    indices.len()
+}
+
+fn missing_rows(conn: &Connection, doc_name: &str) -> Result<Vec<(u64, String)>> {
+    let mut statement = conn.prepare(&format!(
+        "select id, vec_input from {doc_name}
+        where id not in (select row_id from vec_{doc_name})"
+    ))?;
+
+    let rows = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(u64, String)>, _>>()?;
+
+    Ok(rows)
+}
+
+/// Stores the embeddings of a chunk, both in `vec_{doc_name}` and in `embedding_store`, and
+/// removes its job, in a single transaction.
+fn store_chunk(
+    conn: &Connection,
+    doc_name: &str,
+    model: &str,
+    index: &ContentIndex,
+    job_id: u64,
+    data: Embeddings,
+) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut insertions = 0;
+    {
+        let mut delete =
+            tx.prepare_cached(&format!("delete from vec_{doc_name} where row_id = ?"))?;
+        let mut insert = tx.prepare_cached(&format!(
+            "insert into vec_{doc_name}(row_id, vec_input_embedding) values (?,?)"
+        ))?;
+
+        for (id, embedding) in merge_pieces(data) {
+            delete.execute([id])?;
+            insertions += insert.execute(rusqlite::params![id, embedding.as_bytes()])?;
+
+            if let Some(hash) = index.hash(id) {
+                store_embedding(&tx, model, hash, &embedding)?;
+            }
+        }
+    }
+    finish_job(&tx, job_id)?;
+    tx.commit()?;
+
+    Ok(insertions)
 }
 
 pub fn create_indexes(conn: &Connection, doc: &Document) -> Result<()> {
@@ -291,9 +580,49 @@ pub fn spawn_vec_connection<P: AsRef<Path>>(db_path: P) -> Result<Connection, ru
     let mode: String = db.pragma_query_value(None, "journal_mode", |row| row.get(0))?;
     debug!("Current journal mode: {}", mode);
 
+    // The bundled SQLite is built without the math functions, the z-score normalization of the
+    // searches needs this one. Like the builtin one, it's NULL for NULL.
+    db.create_scalar_function(
+        "sqrt",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get::<Option<f64>>(0)?.map(f64::sqrt)),
+    )?;
+
     Ok(db)
 }
 
+/// Adds the columns introduced after `historial` was first created. Does nothing if the table
+/// doesn't exist yet.
+pub fn migrate_history_table(conn: &Connection) -> Result<()> {
+    let mut statement = conn.prepare("select name from pragma_table_info('historial')")?;
+    let columns = statement
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<HashSet<String>, _>>()?;
+
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    for (column, kind) in [
+        ("rrf_k", "integer"),
+        ("fts_limit", "integer"),
+        ("vec_limit", "integer"),
+        ("normalization", "text"),
+        ("fts_mode", "text"),
+        ("fts_weights", "text"),
+    ] {
+        if !columns.contains(column) {
+            conn.execute(
+                &format!("alter table historial add column {column} {kind}"),
+                [],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn setup_sqlite(conn: &rusqlite::Connection, doc: &Document) -> Result<()> {
     let (sqlite_version, vec_version): (String, String) =
         conn.query_row("select sqlite_version(), vec_version()", [], |row| {
@@ -312,6 +641,12 @@ pub fn setup_sqlite(conn: &rusqlite::Connection, doc: &Document) -> Result<()> {
                 peso_fts real,
                 peso_semantic real,
                 neighbors number,
+                rrf_k integer,
+                fts_limit integer,
+                vec_limit integer,
+                normalization text,
+                fts_mode text,
+                fts_weights text,
                 timestamp datetime default current_timestamp
             );
 
@@ -354,8 +689,10 @@ pub fn setup_sqlite(conn: &rusqlite::Connection, doc: &Document) -> Result<()> {
     conn.execute_batch(&statement)
         .map_err(|err| eyre!(err))
         .expect("Should be a valid SQL sentence");
+    migrate_history_table(conn)?;
 
     let doc_name = doc.name.clone();
+    let dimension = doc.dimension();
 
     let (raw_fields_str, fields_str, field_names) = {
         let fields: Vec<String> = doc
@@ -423,7 +760,7 @@ pub fn setup_sqlite(conn: &rusqlite::Connection, doc: &Document) -> Result<()> {
 
             create virtual table if not exists vec_{doc_name} using vec0(
                 row_id integer primary key,
-                vec_input_embedding float[{DIMENSION}]
+                vec_input_embedding float[{dimension}]
             );
             ",
     );
@@ -670,7 +1007,7 @@ fn parse_and_insert<T: AsRef<Path> + Debug>(
     Ok(total_count)
 }
 
-fn validate_sql_identifier(name: &str) -> Result<()> {
+pub(crate) fn validate_sql_identifier(name: &str) -> Result<()> {
     if name.is_empty() || name.len() > 64 {
         return Err(eyre!("Invalid identifier length"));
     }
@@ -746,3 +1083,279 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gulfi_openai::{LocalHashClient, PromptTemplates};
+
+    use super::*;
+    use crate::{MEMORY_DB_PATH, reader::Field, sqlite::jobs::create_job};
+
+    fn document(name: &str, dimension: usize) -> Document {
+        Document {
+            name: name.to_owned(),
+            fields: vec![
+                Field {
+                    name: "email".to_owned(),
+                    vec_input: false,
+                    unique: true,
+                },
+                Field {
+                    name: "bio".to_owned(),
+                    vec_input: true,
+                    unique: false,
+                },
+            ],
+            dimension: Some(dimension),
+            search: Default::default(),
+        }
+    }
+
+    /// Creates the tables of `doc` with a row for each of `bios`, numbered from 1.
+    fn insert_rows(conn: &Connection, doc: &Document, bios: &[&str]) {
+        setup_sqlite(conn, doc).unwrap();
+        for (n, bio) in bios.iter().enumerate() {
+            conn.execute(
+                &format!(
+                    "insert into {}(id, email, vec_input) values (?1, ?2, ?3)",
+                    doc.name
+                ),
+                rusqlite::params![n + 1, format!("{n}@x.com"), bio],
+            )
+            .unwrap();
+        }
+    }
+
+    fn embedded(conn: &Connection, doc_name: &str) -> Vec<u64> {
+        let mut statement = conn
+            .prepare(&format!(
+                "select row_id from vec_{doc_name} order by row_id"
+            ))
+            .unwrap();
+        statement
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reuses_stored_embeddings_across_rows_and_documents() {
+        let conn = spawn_vec_connection(MEMORY_DB_PATH).unwrap();
+        let client = LocalHashClient::new(4);
+        let options = SyncOptions::default();
+
+        let first = document("primero", 4);
+        insert_rows(
+            &conn,
+            &first,
+            &["rust developer", "python", "rust developer"],
+        );
+
+        let report = sync_vec_data(&conn, &first, &options, &client)
+            .await
+            .unwrap();
+        assert_eq!(report.inserted, 3);
+        assert_eq!(report.reused, 1);
+        assert_eq!(report.usage.requests, 1);
+        assert_eq!(embedded(&conn, "primero"), [1, 2, 3]);
+
+        let second = document("segundo", 4);
+        insert_rows(&conn, &second, &["python", "rust developer"]);
+
+        let estimate = estimate_vec_sync(&conn, &second, &options, &client).unwrap();
+        assert_eq!(estimate.entries, 2);
+        assert_eq!(estimate.reused, 2);
+        assert_eq!(estimate.requests, 0);
+
+        let report = sync_vec_data(&conn, &second, &options, &client)
+            .await
+            .unwrap();
+        assert_eq!(report.inserted, 2);
+        assert_eq!(report.reused, 2);
+        assert_eq!(report.usage.requests, 0);
+        assert_eq!(embedded(&conn, "segundo"), [1, 2]);
+
+        // The same model with another dimension can't use them.
+        let wide = document("ancho", 8);
+        insert_rows(&conn, &wide, &["python"]);
+
+        let estimate = estimate_vec_sync(&conn, &wide, &options, &LocalHashClient::new(8)).unwrap();
+        assert_eq!(estimate.reused, 0);
+        assert_eq!(estimate.requests, 1);
+    }
+
+    #[tokio::test]
+    async fn fills_the_repeated_rows_on_resume() {
+        let conn = spawn_vec_connection(MEMORY_DB_PATH).unwrap();
+        let client = LocalHashClient::new(4);
+        let doc = document("demo", 4);
+        insert_rows(&conn, &doc, &["rust", "rust", "go"]);
+
+        // A sync stopped before embedding the chunk of the first row, the second one repeats it.
+        setup_jobs_table(&conn).unwrap();
+        create_job(&conn, "demo", &[1]).unwrap();
+
+        let options = SyncOptions {
+            resume: true,
+            ..SyncOptions::default()
+        };
+        let report = sync_vec_data(&conn, &doc, &options, &client).await.unwrap();
+
+        assert_eq!(report.usage.requests, 1);
+        assert_eq!(report.reused, 1);
+        assert!(report.unresolved.is_empty());
+        assert_eq!(embedded(&conn, "demo"), [1, 2]);
+    }
+
+    /// Provider whose requests always fail.
+    struct Unavailable(gulfi_openai::UsageCounter);
+
+    impl EmbeddingProvider for Unavailable {
+        async fn embed_vec_with_progress(
+            &self,
+            _: Vec<u64>,
+            _: Vec<String>,
+            _: &reqwest::Client,
+            _: usize,
+            _: u64,
+            _: tokio::sync::mpsc::Sender<EmbeddingMessage>,
+        ) -> Result<(gulfi_openai::Embeddings, u128)> {
+            Err(eyre!("unavailable"))
+        }
+
+        async fn embed_single(
+            &self,
+            _: &str,
+            _: &reqwest::Client,
+        ) -> Result<Vec<f32>, gulfi_openai::EmbeddingError> {
+            Err(gulfi_openai::EmbeddingError::EmptyResponse)
+        }
+
+        fn model(&self) -> &str {
+            "unavailable"
+        }
+
+        fn usage(&self) -> &gulfi_openai::UsageCounter {
+            &self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_the_repeated_rows_of_a_failed_chunk() {
+        let conn = spawn_vec_connection(MEMORY_DB_PATH).unwrap();
+        let doc = document("demo", 4);
+        insert_rows(&conn, &doc, &["rust", "go", "rust"]);
+
+        let failing = Unavailable(Default::default());
+        let report = sync_vec_data(&conn, &doc, &SyncOptions::default(), &failing)
+            .await
+            .unwrap();
+
+        let unresolved: Vec<u64> = report
+            .unresolved
+            .iter()
+            .flat_map(|job| job.rows.clone())
+            .collect();
+        assert_eq!(unresolved, [1, 2, 3]);
+
+        // Resuming embeds the text once and fills both rows with it.
+        let options = SyncOptions {
+            resume: true,
+            ..SyncOptions::default()
+        };
+        let client = LocalHashClient::new(4);
+        let report = sync_vec_data(&conn, &doc, &options, &client).await.unwrap();
+
+        assert!(report.unresolved.is_empty());
+        assert_eq!(report.reused, 1);
+        assert_eq!(embedded(&conn, "demo"), [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn stores_the_embedding_of_each_row() {
+        let conn = spawn_vec_connection(MEMORY_DB_PATH).unwrap();
+        let client = LocalHashClient::new(8);
+        let doc = document("demo", 8);
+        let bios = ["rust developer", "python", "go and rust", "java"];
+        insert_rows(&conn, &doc, &bios);
+
+        // A chunk per row, so they're embedded by several requests at once.
+        let options = SyncOptions {
+            chunk_size: 1,
+            prompts: PromptTemplates {
+                query: None,
+                document: Some("passage: ".to_owned()),
+            },
+            ..SyncOptions::default()
+        };
+        let report = sync_vec_data(&conn, &doc, &options, &client).await.unwrap();
+        assert_eq!(report.inserted, bios.len());
+        assert_eq!(report.usage.requests, 4);
+
+        let mut statement = conn
+            .prepare("select row_id, vec_input_embedding from vec_demo order by row_id")
+            .unwrap();
+        let stored: Vec<(u64, Vec<u8>)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(stored.len(), bios.len());
+        for ((id, embedding), bio) in stored.into_iter().zip(bios) {
+            let expected = client.embed(&format!("passage: {bio}"));
+            assert_eq!(embedding, expected.as_bytes(), "row {id}");
+        }
+    }
+
+    #[test]
+    fn adds_the_missing_columns_to_the_history() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate_history_table(&conn).unwrap();
+
+        conn.execute_batch(
+            "create table historial(
+                id integer primary key,
+                query text not null unique,
+                strategy text,
+                doc text,
+                peso_fts real,
+                peso_semantic real,
+                neighbors number,
+                timestamp datetime default current_timestamp
+            );
+            insert into historial(query, strategy, doc) values ('rust', 'Fts', 'demo');",
+        )
+        .unwrap();
+
+        migrate_history_table(&conn).unwrap();
+        migrate_history_table(&conn).unwrap();
+
+        let columns: Vec<String> = conn
+            .prepare("select name from pragma_table_info('historial') where cid > 7")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            columns,
+            [
+                "rrf_k",
+                "fts_limit",
+                "vec_limit",
+                "normalization",
+                "fts_mode",
+                "fts_weights"
+            ]
+        );
+
+        let (query, rrf_k): (String, Option<u32>) = conn
+            .query_row("select query, rrf_k from historial", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((query.as_str(), rrf_k), ("rust", None));
+    }
+}
@@ -0,0 +1,363 @@
+use std::{collections::HashSet, io::Write, path::Path, time::Duration};
+
+use color_eyre::owo_colors::OwoColorize;
+use eyre::{Result, eyre};
+use gulfi_openai::{
+    EmbeddingProvider, OpenAIClient, PromptTemplates,
+    batch::{BATCH_MAX_REQUESTS, Batch, BatchStatus},
+};
+use rusqlite::{Connection, params};
+use tracing::{debug, warn};
+use zerocopy::IntoBytes;
+
+use crate::{
+    Document,
+    sqlite::metadata::record_embedding_metadata,
+    sqlite::store::{ContentIndex, fill_from_store, setup_store_table, store_embedding},
+    sqlite::usage::{UsageSource, record_usage, setup_usage_table},
+    validate_sql_identifier,
+};
+
+/// Status stored once the results of a completed batch are in `vec_{doc}`.
+const INGESTED: &str = "ingested";
+
+/// A batch still waiting to be ingested.
+struct PendingBatch {
+    batch_id: String,
+    /// Rows submitted in the batch, unknown for batches created before they were recorded.
+    rows: Option<Vec<u64>>,
+}
+
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub inserted: usize,
+    /// Rows filled from `embedding_store` without being submitted, included in `inserted`.
+    pub reused: usize,
+    /// Rows whose input was cut to fit the context of the model.
+    pub truncated: Vec<u64>,
+    /// Rows whose request failed inside a completed batch.
+    pub failed: Vec<(u64, String)>,
+    /// Batches that ended without completing.
+    pub failed_batches: Vec<(String, BatchStatus)>,
+}
+
+pub fn setup_batch_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "create table if not exists vec_batches (
+            batch_id text primary key,
+            doc text not null,
+            input_file_id text not null,
+            status text not null,
+            row_ids text,
+            created_at datetime default current_timestamp,
+            completed_at datetime
+        );",
+    )?;
+
+    let has_rows: bool = conn.query_row(
+        "select count(*) > 0 from pragma_table_info('vec_batches') where name = 'row_ids'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_rows {
+        conn.execute("alter table vec_batches add column row_ids text", [])?;
+    }
+
+    Ok(())
+}
+
+/// Embeds every row of `doc` without an embedding through the OpenAI Batch API, applying the
+/// document template of `prompts` to each input.
+///
+/// Batch ids are persisted in `vec_batches` with the rows of each one, so calling it again after
+/// an interruption keeps polling the batches already submitted and only submits the rows none of
+/// them covers. Rows whose text is already in `embedding_store` are filled from it instead, and
+/// the results of every batch are stored there too.
+///
+/// Inputs longer than the context of the model are truncated, a batch can't merge the pieces of
+/// a split row back.
+pub async fn sync_vec_data_batch(
+    conn: &Connection,
+    doc: &Document,
+    client: &OpenAIClient,
+    prompts: &PromptTemplates,
+    work_dir: &Path,
+    poll_interval: Duration,
+) -> Result<BatchReport> {
+    let doc_name = doc.name.clone();
+    validate_sql_identifier(&doc_name)?;
+    setup_batch_table(conn)?;
+    setup_usage_table(conn)?;
+    setup_store_table(conn)?;
+
+    let http_client = reqwest::ClientBuilder::new()
+        .deflate(true)
+        .gzip(true)
+        .build()?;
+
+    let pending = pending_batches(conn, &doc_name)?;
+    if !pending.is_empty() {
+        println!(
+            "Resuming {} pending batch(es) for {doc_name}...",
+            pending.len()
+        );
+    }
+
+    let covered: Option<HashSet<u64>> =
+        pending
+            .iter()
+            .try_fold(HashSet::new(), |mut covered, batch| {
+                covered.extend(batch.rows.as_ref()?);
+                Some(covered)
+            });
+    let mut pending: Vec<String> = pending.into_iter().map(|batch| batch.batch_id).collect();
+
+    let missing: Vec<(u64, String)> = missing_rows(conn, &doc_name)?
+        .into_iter()
+        .map(|(id, input)| (id, prompts.apply_document(&input).into_owned()))
+        .collect();
+
+    let mut index = ContentIndex::default();
+    for (id, input) in &missing {
+        index.insert(*id, input);
+    }
+    let ids: Vec<u64> = missing.iter().map(|(id, _)| *id).collect();
+    let reused: HashSet<u64> = fill_from_store(
+        conn,
+        &doc_name,
+        &client.model,
+        doc.dimension(),
+        &ids,
+        &index,
+    )?
+    .into_iter()
+    .collect();
+
+    let mut report = BatchReport {
+        inserted: reused.len(),
+        reused: reused.len(),
+        ..BatchReport::default()
+    };
+
+    match covered {
+        Some(covered) => {
+            let estimator = client.token_estimator();
+            let max_input_tokens = client.max_input_tokens();
+
+            let rows: Vec<(u64, String)> = missing
+                .into_iter()
+                .filter(|(id, _)| !covered.contains(id) && !reused.contains(id))
+                .map(|(id, input)| {
+                    if estimator.count(&input) <= max_input_tokens {
+                        return (id, input);
+                    }
+                    report.truncated.push(id);
+                    (id, estimator.truncate(&input, max_input_tokens))
+                })
+                .collect();
+
+            if rows.is_empty() && pending.is_empty() && reused.is_empty() {
+                println!("Every entry in {doc_name} already has an embedding.");
+                return Ok(BatchReport::default());
+            }
+
+            if !rows.is_empty() {
+                println!(
+                    "Submitting {} entries of {doc_name} to the Batch API...",
+                    rows.len()
+                );
+            }
+
+            for (n, chunk) in rows.chunks(BATCH_MAX_REQUESTS).enumerate() {
+                let batch_id =
+                    submit_batch(conn, client, &http_client, &doc_name, work_dir, n, chunk).await?;
+                pending.push(batch_id);
+            }
+        }
+        None => warn!(
+            "Some pending batches of {doc_name} don't record their rows, the entries missing from them will be submitted once they finish."
+        ),
+    }
+
+    for batch_id in pending {
+        let batch = wait_for_batch(conn, client, &http_client, &batch_id, poll_interval).await?;
+
+        if batch.status != BatchStatus::Completed {
+            println!();
+            report.failed_batches.push((batch_id, batch.status));
+            continue;
+        }
+
+        let output = client.download_batch_output(&http_client, &batch).await?;
+        report.inserted +=
+            insert_embeddings(conn, &doc_name, &client.model, &index, &output.embeddings)?;
+        report.failed.extend(output.failed);
+        record_usage(
+            conn,
+            UsageSource::Batch,
+            Some(&doc_name),
+            &client.model,
+            output.usage,
+        )?;
+
+        conn.execute(
+            "update vec_batches set status = ?, completed_at = current_timestamp where batch_id = ?",
+            params![INGESTED, batch_id],
+        )?;
+
+        println!(
+            "\r    batch {} {}                    ",
+            batch_id.bright_cyan(),
+            "ingested".bright_green()
+        );
+    }
+
+    record_embedding_metadata(conn, &doc_name, &client.model, prompts)?;
+    println!("{} updated!", "VEC tables".bright_purple());
+
+    Ok(report)
+}
+
+/// Uploads `chunk` and creates its batch, recording it in `vec_batches` with its rows.
+async fn submit_batch(
+    conn: &Connection,
+    client: &OpenAIClient,
+    http_client: &reqwest::Client,
+    doc_name: &str,
+    work_dir: &Path,
+    n: usize,
+    chunk: &[(u64, String)],
+) -> Result<String> {
+    let path = work_dir.join(format!("{doc_name}_batch_{n}.jsonl"));
+    client.write_batch_input(&path, chunk)?;
+
+    let file_id = client.upload_batch_file(http_client, &path).await?;
+    let batch = client.create_batch(http_client, &file_id).await?;
+
+    let rows: Vec<u64> = chunk.iter().map(|(id, _)| *id).collect();
+    conn.execute(
+        "insert into vec_batches(batch_id, doc, input_file_id, status, row_ids) values (?,?,?,?,?)",
+        params![
+            batch.id,
+            doc_name,
+            batch.input_file_id,
+            batch.status.as_str(),
+            serde_json::to_string(&rows)?
+        ],
+    )?;
+
+    if let Err(err) = std::fs::remove_file(&path) {
+        warn!("Couldn't remove {}: {err}", path.display());
+    }
+
+    println!(
+        "    batch {} created ({} entries)",
+        batch.id.bright_cyan(),
+        chunk.len()
+    );
+
+    Ok(batch.id)
+}
+
+async fn wait_for_batch(
+    conn: &Connection,
+    client: &OpenAIClient,
+    http_client: &reqwest::Client,
+    batch_id: &str,
+    poll_interval: Duration,
+) -> Result<Batch> {
+    loop {
+        let batch = client.retrieve_batch(http_client, batch_id).await?;
+
+        conn.execute(
+            "update vec_batches set status = ? where batch_id = ?",
+            params![batch.status.as_str(), batch_id],
+        )?;
+
+        let counts = batch.request_counts.clone().unwrap_or_default();
+        print!(
+            "\r    batch {} {} ({}/{} done, {} failed)",
+            batch_id.bright_cyan(),
+            batch.status,
+            counts.completed,
+            counts.total,
+            counts.failed
+        );
+        std::io::stdout().flush()?;
+
+        if batch.status.is_terminal() {
+            debug!(%batch_id, status = %batch.status, "batch finished");
+            return Ok(batch);
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+fn pending_batches(conn: &Connection, doc_name: &str) -> Result<Vec<PendingBatch>> {
+    let mut statement = conn.prepare(
+        "select batch_id, row_ids from vec_batches
+        where doc = ? and status not in (?, 'failed', 'expired', 'cancelled')
+        order by created_at",
+    )?;
+
+    let batches = statement
+        .query_map(params![doc_name, INGESTED], |row| {
+            Ok((row.get(0)?, row.get::<_, Option<String>>(1)?))
+        })?
+        .collect::<Result<Vec<(String, _)>, _>>()?;
+
+    batches
+        .into_iter()
+        .map(|(batch_id, rows)| {
+            let rows = rows
+                .map(|rows| serde_json::from_str(&rows))
+                .transpose()
+                .map_err(|err| eyre!("Invalid rows recorded for batch {batch_id}: {err}"))?;
+            Ok(PendingBatch { batch_id, rows })
+        })
+        .collect()
+}
+
+fn missing_rows(conn: &Connection, doc_name: &str) -> Result<Vec<(u64, String)>> {
+    let mut statement = conn.prepare(&format!(
+        "select id, vec_input from {doc_name}
+        where id not in (select row_id from vec_{doc_name})"
+    ))?;
+
+    let rows = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(u64, String)>, _>>()
+        .map_err(|err| eyre!(err))?;
+
+    Ok(rows)
+}
+
+fn insert_embeddings(
+    conn: &Connection,
+    doc_name: &str,
+    model: &str,
+    index: &ContentIndex,
+    data: &[(u64, Vec<f32>)],
+) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut insertions = 0;
+    {
+        let mut delete = tx.prepare(&format!("delete from vec_{doc_name} where row_id = ?"))?;
+        let mut insert = tx.prepare(&format!(
+            "insert into vec_{doc_name}(row_id, vec_input_embedding) values (?,?)"
+        ))?;
+
+        for (id, embedding) in data {
+            delete.execute([id])?;
+            insertions += insert.execute(params![id, embedding.as_bytes()])?;
+
+            if let Some(hash) = index.hash(*id) {
+                store_embedding(&tx, model, hash, embedding)?;
+            }
+        }
+    }
+    tx.commit()?;
+
+    Ok(insertions)
+}
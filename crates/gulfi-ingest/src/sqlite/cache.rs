@@ -0,0 +1,264 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::Result;
+use rusqlite::{Connection, OptionalExtension, params};
+use zerocopy::IntoBytes;
+
+/// Bounds of the `embedding_cache` table.
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddingCacheLimits {
+    /// Seconds an embedding is served after being stored.
+    pub ttl_secs: u64,
+    pub max_entries: u64,
+    /// Maximum size of the stored embeddings, in bytes.
+    pub max_bytes: u64,
+}
+
+/// Creates `embedding_cache`, with the totals its eviction checks kept up to date by triggers.
+///
+/// A table from before the dimensions were part of the key is dropped, it's only a cache.
+pub fn setup_embedding_cache_table(conn: &Connection) -> Result<()> {
+    let outdated: bool = conn.query_row(
+        "select count(*) > 0 and sum(name = 'dimensions') = 0
+        from pragma_table_info('embedding_cache')",
+        [],
+        |row| row.get(0),
+    )?;
+    if outdated {
+        conn.execute_batch(
+            "drop table embedding_cache;
+            drop table if exists embedding_cache_totals;",
+        )?;
+    }
+
+    conn.execute_batch(
+        "create table if not exists embedding_cache (
+            model text not null,
+            dimensions integer not null,
+            query text not null,
+            embedding blob not null,
+            created_at integer not null,
+            primary key (model, dimensions, query)
+        );
+
+        create index if not exists embedding_cache_created_at on embedding_cache(created_at);
+
+        create table if not exists embedding_cache_totals (
+            id integer primary key check (id = 1),
+            entries integer not null,
+            bytes integer not null
+        );
+
+        insert or ignore into embedding_cache_totals(id, entries, bytes)
+        select 1, count(*), coalesce(sum(length(embedding)), 0) from embedding_cache;
+
+        create trigger if not exists embedding_cache_insert after insert on embedding_cache begin
+            update embedding_cache_totals
+            set entries = entries + 1, bytes = bytes + length(new.embedding);
+        end;
+
+        create trigger if not exists embedding_cache_update after update of embedding on embedding_cache begin
+            update embedding_cache_totals
+            set bytes = bytes - length(old.embedding) + length(new.embedding);
+        end;
+
+        create trigger if not exists embedding_cache_delete after delete on embedding_cache begin
+            update embedding_cache_totals
+            set entries = entries - 1, bytes = bytes - length(old.embedding);
+        end;",
+    )?;
+    Ok(())
+}
+
+/// The embedding of `query` with `dimensions` entries stored less than `ttl_secs` ago, if any.
+pub fn cached_embedding(
+    conn: &Connection,
+    model: &str,
+    dimensions: usize,
+    query: &str,
+    ttl_secs: u64,
+) -> Result<Option<Vec<f32>>> {
+    let blob: Option<Vec<u8>> = conn
+        .prepare_cached(
+            "select embedding from embedding_cache
+            where model = ? and dimensions = ? and query = ? and created_at >= ?",
+        )?
+        .query_row(
+            params![model, dimensions, query, expired_before(ttl_secs)],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    // The blob isn't guaranteed to be aligned for `f32`, so the values are copied one by one.
+    Ok(blob.map(|blob| {
+        blob.chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect()
+    }))
+}
+
+/// Stores the embedding of `query`, then evicts the expired entries and the oldest ones until
+/// the table fits in `limits`.
+pub fn store_cached_embedding(
+    conn: &Connection,
+    model: &str,
+    dimensions: usize,
+    query: &str,
+    embedding: &[f32],
+    limits: EmbeddingCacheLimits,
+) -> Result<()> {
+    let now = now();
+
+    conn.prepare_cached(
+        "insert into embedding_cache(model, dimensions, query, embedding, created_at)
+        values (?,?,?,?,?)
+        on conflict(model, dimensions, query) do update set
+            embedding = excluded.embedding,
+            created_at = excluded.created_at",
+    )?
+    .execute(params![model, dimensions, query, embedding.as_bytes(), now])?;
+
+    conn.prepare_cached("delete from embedding_cache where created_at < ?")?
+        .execute([expired_before(limits.ttl_secs)])?;
+
+    let (entries, bytes): (u64, u64) = conn
+        .prepare_cached("select entries, bytes from embedding_cache_totals")?
+        .query_row([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    let over_entries = entries.saturating_sub(limits.max_entries);
+    let over_bytes = match bytes.checked_sub(limits.max_bytes) {
+        Some(excess) if excess > 0 => excess.div_ceil((embedding.len() * 4).max(1) as u64),
+        _ => 0,
+    };
+
+    let evict = over_entries.max(over_bytes);
+    if evict > 0 {
+        conn.execute(
+            "delete from embedding_cache where rowid in (
+                select rowid from embedding_cache order by created_at limit ?
+            )",
+            [evict],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Removes the cached embeddings of `model`, or every one of them. With `expired_only` only the
+/// ones older than `ttl_secs` are removed.
+pub fn purge_embedding_cache(
+    conn: &Connection,
+    model: Option<&str>,
+    expired_only: bool,
+    ttl_secs: u64,
+) -> Result<usize> {
+    setup_embedding_cache_table(conn)?;
+
+    let created_before = if expired_only {
+        expired_before(ttl_secs)
+    } else {
+        i64::MAX
+    };
+
+    let removed = match model {
+        Some(model) => conn.execute(
+            "delete from embedding_cache where model = ? and created_at < ?",
+            params![model, created_before],
+        )?,
+        None => conn.execute(
+            "delete from embedding_cache where created_at < ?",
+            [created_before],
+        )?,
+    };
+
+    Ok(removed)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Entries created before this timestamp are older than `ttl_secs`.
+fn expired_before(ttl_secs: u64) -> i64 {
+    now().saturating_sub(i64::try_from(ttl_secs).unwrap_or(i64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_oldest_entries() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_embedding_cache_table(&conn).unwrap();
+
+        let limits = EmbeddingCacheLimits {
+            ttl_secs: 60,
+            max_entries: 2,
+            max_bytes: u64::MAX,
+        };
+
+        for (n, query) in ["a", "b", "c"].into_iter().enumerate() {
+            store_cached_embedding(&conn, "model", 2, query, &[n as f32, 1.0], limits).unwrap();
+            // Makes every entry older than the next one.
+            conn.execute(
+                "update embedding_cache set created_at = created_at - ? where query = ?",
+                params![10 - n as i64, query],
+            )
+            .unwrap();
+        }
+
+        assert_eq!(cached_embedding(&conn, "model", 2, "a", 60).unwrap(), None);
+        assert_eq!(
+            cached_embedding(&conn, "model", 2, "c", 60).unwrap(),
+            Some(vec![2.0, 1.0])
+        );
+        assert_eq!(cached_embedding(&conn, "other", 2, "c", 60).unwrap(), None);
+        assert_eq!(cached_embedding(&conn, "model", 3, "c", 60).unwrap(), None);
+
+        assert_eq!(purge_embedding_cache(&conn, None, false, 60).unwrap(), 2);
+    }
+
+    #[test]
+    fn keeps_the_totals_of_the_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_embedding_cache_table(&conn).unwrap();
+
+        let limits = EmbeddingCacheLimits {
+            ttl_secs: 60,
+            max_entries: 10,
+            max_bytes: 24,
+        };
+        let totals = |conn: &Connection| -> (u64, u64) {
+            conn.query_row(
+                "select entries, bytes from embedding_cache_totals",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+
+        store_cached_embedding(&conn, "model", 2, "a", &[1.0, 1.0], limits).unwrap();
+        store_cached_embedding(&conn, "model", 3, "a", &[1.0, 1.0, 1.0], limits).unwrap();
+        assert_eq!(totals(&conn), (2, 20));
+
+        // Replacing an entry only changes its size.
+        store_cached_embedding(&conn, "model", 2, "a", &[2.0, 2.0], limits).unwrap();
+        assert_eq!(totals(&conn), (2, 20));
+
+        // Over `max_bytes`, the oldest entry is evicted.
+        conn.execute(
+            "update embedding_cache set created_at = created_at - 1 where dimensions = 3",
+            [],
+        )
+        .unwrap();
+        store_cached_embedding(&conn, "model", 2, "b", &[3.0, 3.0], limits).unwrap();
+        assert_eq!(totals(&conn), (2, 16));
+
+        purge_embedding_cache(&conn, Some("model"), false, 60).unwrap();
+        assert_eq!(totals(&conn), (0, 0));
+    }
+}
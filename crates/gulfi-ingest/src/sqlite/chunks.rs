@@ -0,0 +1,227 @@
+use gulfi_openai::{Embeddings, PromptTemplates, TokenEstimator, TokenUsage};
+
+use crate::sqlite::jobs::FailedJob;
+
+/// What to do with an input longer than the context of the model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum OverflowStrategy {
+    /// Keeps only the tokens that fit.
+    #[default]
+    Truncate,
+    /// Embeds every piece and averages the vectors.
+    Split,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Base time for the backoff between retries, in ms.
+    pub base_delay: u64,
+    /// Maximum amount of inputs in a single request.
+    pub chunk_size: usize,
+    /// Maximum amount of tokens in a single request.
+    pub max_request_tokens: usize,
+    pub overflow: OverflowStrategy,
+    /// Only embeds the rows of the chunks left unresolved by a previous sync.
+    pub resume: bool,
+    /// Templates of the model, the document one is applied to every input.
+    pub prompts: PromptTemplates,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            base_delay: 2000,
+            chunk_size: 1024,
+            max_request_tokens: 300_000,
+            overflow: OverflowStrategy::default(),
+            resume: false,
+            prompts: PromptTemplates::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub inserted: usize,
+    /// Average time per chunk, in ms.
+    pub average: f32,
+    /// Rows whose input was cut to fit the context of the model.
+    pub truncated: Vec<u64>,
+    /// Rows whose input was embedded in several pieces.
+    pub split: Vec<u64>,
+    /// Chunks still without embeddings, stored in `vec_sync_jobs`.
+    pub unresolved: Vec<FailedJob>,
+    /// Rows filled from `embedding_store` without sending their input, included in `inserted`.
+    pub reused: usize,
+    pub usage: TokenUsage,
+}
+
+/// What a sync would send to the provider, computed without sending anything.
+#[derive(Debug, Default)]
+pub struct SyncEstimate {
+    pub entries: usize,
+    /// Entries whose text is already in `embedding_store` or repeated in another entry.
+    pub reused: usize,
+    pub requests: usize,
+    pub tokens: usize,
+    pub truncated: Vec<u64>,
+    pub split: Vec<u64>,
+}
+
+/// A single request worth of inputs. A split row appears once per piece.
+#[derive(Debug, Default)]
+pub(crate) struct Chunk {
+    pub indices: Vec<u64>,
+    pub inputs: Vec<String>,
+    pub tokens: usize,
+}
+
+/// Groups `rows` into chunks under both the input and token budgets of `options`, cutting the
+/// inputs longer than `max_input_tokens` as `options.overflow` says.
+///
+/// The document template of `options.prompts` is applied before counting the tokens.
+///
+/// The pieces of a split row always end up in the same chunk, so they can be merged back as
+/// soon as the chunk is embedded.
+pub(crate) fn build_chunks(
+    rows: Vec<(u64, String)>,
+    estimator: &TokenEstimator,
+    max_input_tokens: usize,
+    options: &SyncOptions,
+) -> (Vec<Chunk>, Vec<u64>, Vec<u64>) {
+    let mut chunks = Vec::new();
+    let mut current = Chunk::default();
+    let mut truncated = Vec::new();
+    let mut split = Vec::new();
+
+    for (id, mut input) in rows {
+        if options.prompts.document.is_some() {
+            input = options.prompts.apply_document(&input).into_owned();
+        }
+        let tokens = estimator.count(&input);
+
+        let pieces = if tokens <= max_input_tokens {
+            vec![(input, tokens)]
+        } else {
+            match options.overflow {
+                OverflowStrategy::Truncate => {
+                    truncated.push(id);
+                    vec![(
+                        estimator.truncate(&input, max_input_tokens),
+                        max_input_tokens,
+                    )]
+                }
+                OverflowStrategy::Split => {
+                    split.push(id);
+                    estimator
+                        .split(&input, max_input_tokens)
+                        .into_iter()
+                        .map(|piece| {
+                            let tokens = estimator.count(&piece);
+                            (piece, tokens)
+                        })
+                        .collect()
+                }
+            }
+        };
+
+        let row_tokens: usize = pieces.iter().map(|(_, tokens)| tokens).sum();
+        let fits = current.inputs.len() + pieces.len() <= options.chunk_size
+            && current.tokens + row_tokens <= options.max_request_tokens;
+
+        if !fits && !current.inputs.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+
+        for (piece, _) in pieces {
+            current.indices.push(id);
+            current.inputs.push(piece);
+        }
+        current.tokens += row_tokens;
+    }
+
+    if !current.inputs.is_empty() {
+        chunks.push(current);
+    }
+
+    (chunks, truncated, split)
+}
+
+/// Averages the embeddings of the pieces of a split row into a single normalized vector.
+pub(crate) fn merge_pieces(data: Embeddings) -> Embeddings {
+    let mut merged: Embeddings = Vec::with_capacity(data.len());
+    let mut pieces = 1;
+
+    for (id, embedding) in data {
+        match merged.last_mut() {
+            Some((last_id, acc)) if *last_id == id => {
+                acc.iter_mut()
+                    .zip(&embedding)
+                    .for_each(|(acc, value)| *acc += value);
+                pieces += 1;
+            }
+            _ => {
+                normalize_last(&mut merged, pieces);
+                pieces = 1;
+                merged.push((id, embedding));
+            }
+        }
+    }
+    normalize_last(&mut merged, pieces);
+
+    merged
+}
+
+fn normalize_last(merged: &mut Embeddings, pieces: usize) {
+    if pieces < 2 {
+        return;
+    }
+
+    if let Some((_, embedding)) = merged.last_mut() {
+        let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|v| *v /= norm);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_respect_the_token_budget() {
+        let options = SyncOptions {
+            chunk_size: 10,
+            max_request_tokens: 6,
+            overflow: OverflowStrategy::Split,
+            ..Default::default()
+        };
+
+        // With the heuristic estimator every 4 characters count as a token.
+        let rows = vec![
+            (1, "aaaa".repeat(2)),
+            (2, "aaaa".repeat(3)),
+            (3, "aaaa".repeat(5)),
+            (4, "aaaa".to_owned()),
+        ];
+
+        let (chunks, truncated, split) =
+            build_chunks(rows, &TokenEstimator::Heuristic, 3, &options);
+
+        assert!(truncated.is_empty());
+        assert_eq!(split, vec![3]);
+
+        let indices: Vec<_> = chunks.iter().map(|c| c.indices.clone()).collect();
+        assert_eq!(indices, vec![vec![1, 2], vec![3, 3, 4]]);
+
+        let merged = merge_pieces(vec![
+            (1, vec![1.0, 0.0]),
+            (3, vec![1.0, 0.0]),
+            (3, vec![0.0, 1.0]),
+        ]);
+        assert_eq!(merged.len(), 2);
+        assert!((merged[1].1[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+    }
+}
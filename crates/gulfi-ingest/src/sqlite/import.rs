@@ -0,0 +1,251 @@
+use color_eyre::owo_colors::OwoColorize;
+use eyre::{Result, eyre};
+use gulfi_openai::PromptTemplates;
+use rusqlite::{Connection, OptionalExtension, params};
+use tracing::debug;
+use zerocopy::IntoBytes;
+
+use crate::{
+    Document, ImportKey, ImportRecord, sqlite::metadata::record_embedding_metadata,
+    validate_sql_identifier,
+};
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub inserted: usize,
+    pub unmatched: Vec<ImportKey>,
+}
+
+/// Fills `vec_{doc}` with precomputed embeddings, replacing the existing vector of every matched row.
+///
+/// Rows are matched by `id`, or by the value of `match_field` when given, which has to be a
+/// `unique` field stored in the document table.
+///
+/// The embeddings are recorded as computed with `model` and `prompts`, like the ones of a sync.
+pub fn import_embeddings(
+    conn: &Connection,
+    doc: &Document,
+    records: Vec<ImportRecord>,
+    match_field: Option<&str>,
+    model: &str,
+    prompts: &PromptTemplates,
+) -> Result<ImportReport> {
+    let doc_name = doc.name.clone();
+    validate_sql_identifier(&doc_name)?;
+
+    let lookup_sql = match match_field {
+        Some(field) => {
+            let Some(f) = doc.field(field) else {
+                return Err(eyre!("'{field}' is not a field of '{doc_name}'"));
+            };
+
+            if !f.unique {
+                return Err(eyre!(
+                    "'{field}' has to be a unique field to be used for matching"
+                ));
+            }
+
+            if f.vec_input {
+                return Err(eyre!(
+                    "'{field}' is part of 'vec_input' and can't be used for matching"
+                ));
+            }
+
+            validate_sql_identifier(field)?;
+            format!("select id from {doc_name} where {field} = ?")
+        }
+        None => format!("select id from {doc_name} where id = ?"),
+    };
+
+    let dimension = doc.dimension();
+    if let Some(record) = records.iter().find(|r| r.embedding.len() != dimension) {
+        return Err(eyre!(
+            "The embedding for '{}' has dimension {} but '{doc_name}' expects {dimension}",
+            record.key,
+            record.embedding.len()
+        ));
+    }
+
+    let start = std::time::Instant::now();
+    let mut report = ImportReport::default();
+
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut lookup = tx.prepare(&lookup_sql)?;
+        let mut delete = tx.prepare(&format!("delete from vec_{doc_name} where row_id = ?"))?;
+        let mut insert = tx.prepare(&format!(
+            "insert into vec_{doc_name}(row_id, vec_input_embedding) values (?,?)"
+        ))?;
+
+        for ImportRecord { key, embedding } in records {
+            let row_id: Option<u64> = match &key {
+                ImportKey::Id(id) => lookup.query_row([id], |row| row.get(0)).optional()?,
+                ImportKey::Key(value) => lookup.query_row([value], |row| row.get(0)).optional()?,
+            };
+
+            let Some(row_id) = row_id else {
+                debug!(%key, "no row matches the imported embedding");
+                report.unmatched.push(key);
+                continue;
+            };
+
+            delete.execute([row_id])?;
+            report.inserted += insert.execute(params![row_id, embedding.as_bytes()])?;
+        }
+    }
+    tx.commit()?;
+
+    if report.inserted > 0 {
+        record_embedding_metadata(conn, &doc_name, model, prompts)?;
+    }
+
+    eprintln!(
+        "{} updated! ({} ms)",
+        format!("vec_{doc_name}").bright_purple(),
+        start.elapsed().as_millis()
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MEMORY_DB_PATH, reader::Field, setup_sqlite, spawn_vec_connection,
+        sqlite::metadata::embedding_metadata,
+    };
+
+    fn document() -> Document {
+        Document {
+            name: "demo".to_owned(),
+            fields: vec![
+                Field {
+                    name: "email".to_owned(),
+                    vec_input: false,
+                    unique: true,
+                },
+                Field {
+                    name: "bio".to_owned(),
+                    vec_input: true,
+                    unique: false,
+                },
+            ],
+            dimension: Some(2),
+            search: Default::default(),
+        }
+    }
+
+    fn record(key: ImportKey, embedding: [f32; 2]) -> ImportRecord {
+        ImportRecord {
+            key,
+            embedding: embedding.to_vec(),
+        }
+    }
+
+    fn stored(conn: &Connection) -> Vec<(u64, Vec<u8>)> {
+        let mut statement = conn
+            .prepare("select row_id, vec_input_embedding from vec_demo order by row_id")
+            .unwrap();
+        statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_the_records_with_the_rows() {
+        let conn = spawn_vec_connection(MEMORY_DB_PATH).unwrap();
+        let doc = document();
+        setup_sqlite(&conn, &doc).unwrap();
+        for (id, email) in [(1, "a@x.com"), (2, "b@x.com")] {
+            conn.execute(
+                "insert into demo(id, email, vec_input) values (?1, ?2, 'bio')",
+                params![id, email],
+            )
+            .unwrap();
+        }
+        let prompts = PromptTemplates::default();
+
+        let report = import_embeddings(
+            &conn,
+            &doc,
+            vec![
+                record(ImportKey::Id(2), [0.5, 0.5]),
+                record(ImportKey::Id(9), [1.0, 1.0]),
+            ],
+            None,
+            "precomputed",
+            &prompts,
+        )
+        .unwrap();
+        assert_eq!(report.inserted, 1);
+        assert_eq!(report.unmatched, vec![ImportKey::Id(9)]);
+        assert_eq!(stored(&conn), vec![(2, [0.5f32, 0.5].as_bytes().to_vec())]);
+
+        // A second import replaces the vector of the row it matches.
+        let report = import_embeddings(
+            &conn,
+            &doc,
+            vec![
+                record(ImportKey::Key("a@x.com".to_owned()), [1.0, 0.0]),
+                record(ImportKey::Key("b@x.com".to_owned()), [0.0, 1.0]),
+            ],
+            Some("email"),
+            "precomputed",
+            &prompts,
+        )
+        .unwrap();
+        assert_eq!(report.inserted, 2);
+        assert!(report.unmatched.is_empty());
+        assert_eq!(
+            stored(&conn),
+            vec![
+                (1, [1.0f32, 0.0].as_bytes().to_vec()),
+                (2, [0.0f32, 1.0].as_bytes().to_vec()),
+            ]
+        );
+
+        let metadata = embedding_metadata(&conn, "demo").unwrap().unwrap();
+        assert_eq!(metadata.model, "precomputed");
+        assert_eq!(metadata.prompts, prompts);
+
+        // `bio` is neither unique nor stored outside of `vec_input`.
+        assert!(
+            import_embeddings(&conn, &doc, vec![], Some("bio"), "precomputed", &prompts).is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_embeddings_of_another_dimension() {
+        let conn = spawn_vec_connection(MEMORY_DB_PATH).unwrap();
+        let doc = document();
+        setup_sqlite(&conn, &doc).unwrap();
+        conn.execute(
+            "insert into demo(id, email, vec_input) values (1, 'a@x.com', 'bio')",
+            [],
+        )
+        .unwrap();
+
+        let records = vec![
+            record(ImportKey::Id(1), [0.5, 0.5]),
+            ImportRecord {
+                key: ImportKey::Id(1),
+                embedding: vec![0.5, 0.5, 0.5],
+            },
+        ];
+        let err = import_embeddings(
+            &conn,
+            &doc,
+            records,
+            None,
+            "precomputed",
+            &PromptTemplates::default(),
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("dimension 3"), "{err}");
+        assert!(stored(&conn).is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+use eyre::Result;
+use rusqlite::{Connection, params, types::Type};
+
+const PENDING: &str = "pending";
+const FAILED: &str = "failed";
+
+/// A chunk of rows that couldn't be embedded.
+#[derive(Debug, Clone)]
+pub struct FailedJob {
+    pub id: u64,
+    pub rows: Vec<u64>,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+pub fn setup_jobs_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "create table if not exists vec_sync_jobs (
+            id integer primary key,
+            doc text not null,
+            row_ids text not null,
+            status text not null,
+            attempts integer not null default 0,
+            last_error text,
+            updated_at datetime default current_timestamp
+        );",
+    )?;
+    Ok(())
+}
+
+/// Drops every job left by a previous sync of `doc_name`.
+pub(crate) fn clear_jobs(conn: &Connection, doc_name: &str) -> Result<()> {
+    conn.execute("delete from vec_sync_jobs where doc = ?", [doc_name])?;
+    Ok(())
+}
+
+/// Records a chunk about to be embedded, returning the id of its job.
+pub(crate) fn create_job(conn: &Connection, doc_name: &str, rows: &[u64]) -> Result<u64> {
+    let mut rows = rows.to_vec();
+    rows.dedup();
+
+    conn.execute(
+        "insert into vec_sync_jobs(doc, row_ids, status) values (?,?,?)",
+        params![doc_name, serde_json::to_string(&rows)?, PENDING],
+    )?;
+
+    Ok(conn.last_insert_rowid() as u64)
+}
+
+/// Removes a job once its embeddings are stored.
+pub(crate) fn finish_job(conn: &Connection, job_id: u64) -> Result<()> {
+    conn.execute("delete from vec_sync_jobs where id = ?", [job_id])?;
+    Ok(())
+}
+
+pub(crate) fn fail_job(conn: &Connection, job_id: u64, error: &str) -> Result<()> {
+    conn.execute(
+        "update vec_sync_jobs
+        set status = ?, attempts = attempts + 1, last_error = ?, updated_at = current_timestamp
+        where id = ?",
+        params![FAILED, error, job_id],
+    )?;
+    Ok(())
+}
+
+/// Every job of `doc_name` that is still waiting for its embeddings, either because it failed or
+/// because the sync was interrupted.
+pub fn unresolved_jobs(conn: &Connection, doc_name: &str) -> Result<Vec<FailedJob>> {
+    let mut statement = conn.prepare(
+        "select id, row_ids, attempts, last_error from vec_sync_jobs
+        where doc = ? and status in (?, ?)
+        order by id",
+    )?;
+
+    let jobs = statement
+        .query_map(params![doc_name, PENDING, FAILED], |row| {
+            let rows: String = row.get(1)?;
+            Ok(FailedJob {
+                id: row.get(0)?,
+                rows: serde_json::from_str(&rows).map_err(|err| {
+                    rusqlite::Error::FromSqlConversionFailure(1, Type::Text, Box::new(err))
+                })?,
+                attempts: row.get(2)?,
+                last_error: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_stay_unresolved_until_finished() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_jobs_table(&conn).unwrap();
+
+        let first = create_job(&conn, "demo", &[1, 2, 2, 3]).unwrap();
+        let second = create_job(&conn, "demo", &[4]).unwrap();
+        create_job(&conn, "other", &[1]).unwrap();
+
+        fail_job(&conn, second, "timeout").unwrap();
+        fail_job(&conn, second, "rate limit").unwrap();
+
+        let jobs = unresolved_jobs(&conn, "demo").unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].id, first);
+        assert_eq!(jobs[0].rows, [1, 2, 3]);
+        assert_eq!(jobs[0].attempts, 0);
+        assert_eq!(jobs[0].last_error, None);
+        assert_eq!(jobs[1].attempts, 2);
+        assert_eq!(jobs[1].last_error.as_deref(), Some("rate limit"));
+
+        finish_job(&conn, first).unwrap();
+        let jobs = unresolved_jobs(&conn, "demo").unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, second);
+
+        clear_jobs(&conn, "demo").unwrap();
+        assert!(unresolved_jobs(&conn, "demo").unwrap().is_empty());
+        assert_eq!(unresolved_jobs(&conn, "other").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn corrupt_rows_are_an_error() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_jobs_table(&conn).unwrap();
+
+        conn.execute(
+            "insert into vec_sync_jobs(doc, row_ids, status) values ('demo', '[1, 2', ?)",
+            [PENDING],
+        )
+        .unwrap();
+
+        assert!(unresolved_jobs(&conn, "demo").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use eyre::Result;
+use gulfi_openai::PromptTemplates;
+use rusqlite::{Connection, OptionalExtension, params};
+
+/// How the embeddings of a document were computed, so the queries can be embedded the same way.
+#[derive(Debug, Clone)]
+pub struct EmbeddingMetadata {
+    pub model: String,
+    pub prompts: PromptTemplates,
+    pub updated_at: String,
+}
+
+pub fn setup_metadata_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "create table if not exists vec_metadata (
+            doc text primary key,
+            model text not null,
+            query_prompt text,
+            document_prompt text,
+            updated_at datetime default current_timestamp
+        );",
+    )?;
+    Ok(())
+}
+
+pub fn record_embedding_metadata(
+    conn: &Connection,
+    doc_name: &str,
+    model: &str,
+    prompts: &PromptTemplates,
+) -> Result<()> {
+    setup_metadata_table(conn)?;
+    conn.execute(
+        "insert into vec_metadata(doc, model, query_prompt, document_prompt) values (?,?,?,?)
+        on conflict(doc) do update set
+            model = excluded.model,
+            query_prompt = excluded.query_prompt,
+            document_prompt = excluded.document_prompt,
+            updated_at = current_timestamp",
+        params![doc_name, model, prompts.query, prompts.document],
+    )?;
+    Ok(())
+}
+
+/// The metadata of the last sync of `doc_name`, if it was ever synced.
+pub fn embedding_metadata(conn: &Connection, doc_name: &str) -> Result<Option<EmbeddingMetadata>> {
+    setup_metadata_table(conn)?;
+    let metadata = conn
+        .query_row(
+            "select model, query_prompt, document_prompt, updated_at from vec_metadata where doc = ?",
+            [doc_name],
+            |row| {
+                Ok(EmbeddingMetadata {
+                    model: row.get(0)?,
+                    prompts: PromptTemplates {
+                        query: row.get(1)?,
+                        document: row.get(2)?,
+                    },
+                    updated_at: row.get(3)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(metadata)
+}
@@ -1,3 +1,19 @@
 mod base;
+mod batch;
+mod cache;
+mod chunks;
+mod import;
+mod jobs;
+mod metadata;
 pub mod pool;
+mod store;
+mod usage;
 pub use base::*;
+pub use batch::*;
+pub use cache::*;
+pub use chunks::{OverflowStrategy, SyncEstimate, SyncOptions, SyncReport};
+pub use import::*;
+pub use jobs::{FailedJob, setup_jobs_table, unresolved_jobs};
+pub use metadata::*;
+pub use store::{setup_store_table, text_hash};
+pub use usage::*;
@@ -39,7 +39,7 @@ impl ConnectionPool {
     ///
     /// # Example
     /// ``` rust
-    /// use gulfi_sqlite::pooling::ConnectionPool;
+    /// use gulfi_ingest::pool::ConnectionPool;
     /// use rusqlite::Connection;
     ///
     /// let pool = ConnectionPool::new(6, || {
@@ -0,0 +1,144 @@
+use std::collections::{HashMap, HashSet};
+
+use eyre::Result;
+use rusqlite::{Connection, params};
+use sha2::{Digest, Sha256};
+use zerocopy::IntoBytes;
+
+/// Rows of a sync keyed by the hash of the text they embed.
+///
+/// Rows with the same text share a single embedding: only the first one is sent to the
+/// provider, the rest are filled from `embedding_store` once it's there.
+#[derive(Debug, Default)]
+pub(crate) struct ContentIndex {
+    hashes: HashMap<u64, String>,
+}
+
+impl ContentIndex {
+    pub fn insert(&mut self, id: u64, text: &str) {
+        self.hashes.insert(id, text_hash(text));
+    }
+
+    pub fn hash(&self, id: u64) -> Option<&str> {
+        self.hashes.get(&id).map(String::as_str)
+    }
+}
+
+/// Creates `embedding_store`, which keeps every embedding computed by a sync once per model and
+/// text. It spares the requests for a text that was already embedded, the vector tables still
+/// keep their own copy, as `vec0` can only search those.
+pub fn setup_store_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "create table if not exists embedding_store (
+            model text not null,
+            hash text not null,
+            embedding blob not null,
+            created_at datetime default current_timestamp,
+            primary key (model, hash)
+        ) without rowid;",
+    )?;
+    Ok(())
+}
+
+/// Hex SHA-256 of `text`, the address of its embedding in `embedding_store`.
+pub fn text_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+pub(crate) fn store_embedding(
+    conn: &Connection,
+    model: &str,
+    hash: &str,
+    embedding: &[f32],
+) -> Result<()> {
+    conn.prepare_cached(
+        "insert or replace into embedding_store(model, hash, embedding) values (?,?,?)",
+    )?
+    .execute(params![model, hash, embedding.as_bytes()])?;
+    Ok(())
+}
+
+/// Copies the stored embedding of each of `rows` into `vec_{doc_name}`, returning the ids of the
+/// rows that had one. The blobs go from one table to the other without being decoded, the
+/// vector table can't point to the store.
+///
+/// Only embeddings of `dimensions` entries are used, the same model can be configured to return
+/// vectors of another size.
+pub(crate) fn fill_from_store(
+    conn: &Connection,
+    doc_name: &str,
+    model: &str,
+    dimensions: usize,
+    rows: &[u64],
+    index: &ContentIndex,
+) -> Result<Vec<u64>> {
+    let tx = conn.unchecked_transaction()?;
+    let mut filled = Vec::new();
+    {
+        let mut lookup = tx.prepare_cached(
+            "select embedding from embedding_store
+            where model = ? and hash = ? and length(embedding) = ?",
+        )?;
+        let mut delete =
+            tx.prepare_cached(&format!("delete from vec_{doc_name} where row_id = ?"))?;
+        let mut insert = tx.prepare_cached(&format!(
+            "insert into vec_{doc_name}(row_id, vec_input_embedding) values (?,?)"
+        ))?;
+
+        for &id in rows {
+            let Some(hash) = index.hash(id) else {
+                continue;
+            };
+
+            let mut stored = lookup.query(params![model, hash, blob_len(dimensions)])?;
+            if let Some(row) = stored.next()? {
+                let embedding: Vec<u8> = row.get(0)?;
+                delete.execute([id])?;
+                insert.execute(params![id, embedding])?;
+                filled.push(id);
+            }
+        }
+    }
+    tx.commit()?;
+
+    Ok(filled)
+}
+
+/// Which of `hashes` already have an embedding of `model` with `dimensions` entries.
+pub(crate) fn stored_hashes(
+    conn: &Connection,
+    model: &str,
+    dimensions: usize,
+    hashes: &[&str],
+) -> Result<HashSet<String>> {
+    let mut statement = conn.prepare(
+        "select hash from embedding_store
+        where model = ? and length(embedding) = ? and hash in (select value from json_each(?))",
+    )?;
+    let stored = statement
+        .query_map(
+            params![model, blob_len(dimensions), serde_json::to_string(hashes)?],
+            |row| row.get(0),
+        )?
+        .collect::<Result<HashSet<String>, _>>()?;
+    Ok(stored)
+}
+
+/// Bytes of a stored embedding of `dimensions` entries.
+fn blob_len(dimensions: usize) -> usize {
+    dimensions * size_of::<f32>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_are_stable() {
+        assert_eq!(
+            text_hash("hola"),
+            "b221d9dbb083a7f33428d7c2a3c3198ae925614d70210e28716ccaa7cd4ddb79"
+        );
+        assert_ne!(text_hash("hola"), text_hash("hola "));
+    }
+}
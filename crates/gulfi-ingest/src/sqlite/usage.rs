@@ -0,0 +1,204 @@
+use eyre::Result;
+use gulfi_openai::TokenUsage;
+use rusqlite::{Connection, params};
+
+/// What the tokens were spent on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageSource {
+    /// A single `gulfi sync` run, stored in its own row.
+    Sync,
+    /// The results of a batch of `gulfi sync --batch`, billed at the Batch API price.
+    Batch,
+    /// Search queries, added up per day.
+    Search,
+}
+
+impl UsageSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sync => "sync",
+            Self::Batch => "batch",
+            Self::Search => "search",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UsageRecord {
+    pub day: String,
+    pub source: String,
+    pub doc: Option<String>,
+    pub model: String,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+}
+
+pub fn setup_usage_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "create table if not exists embedding_usage (
+            id integer primary key,
+            day date not null default (date('now')),
+            source text not null,
+            doc text,
+            model text not null,
+            requests integer not null default 0,
+            prompt_tokens integer not null default 0,
+            created_at datetime default current_timestamp
+        );
+
+        create unique index if not exists embedding_usage_search_day
+        on embedding_usage(day, model) where source = 'search';",
+    )?;
+    Ok(())
+}
+
+/// Stores the usage of a sync run, or adds it to today's search total.
+pub fn record_usage(
+    conn: &Connection,
+    source: UsageSource,
+    doc: Option<&str>,
+    model: &str,
+    usage: TokenUsage,
+) -> Result<()> {
+    if usage.requests == 0 {
+        return Ok(());
+    }
+
+    match source {
+        UsageSource::Sync | UsageSource::Batch => conn.execute(
+            "insert into embedding_usage(source, doc, model, requests, prompt_tokens)
+            values (?,?,?,?,?)",
+            params![
+                source.as_str(),
+                doc,
+                model,
+                usage.requests,
+                usage.prompt_tokens
+            ],
+        )?,
+        UsageSource::Search => conn.execute(
+            "insert into embedding_usage(source, model, requests, prompt_tokens)
+            values (?,?,?,?)
+            on conflict(day, model) where source = 'search' do update set
+                requests = requests + excluded.requests,
+                prompt_tokens = prompt_tokens + excluded.prompt_tokens",
+            params![source.as_str(), model, usage.requests, usage.prompt_tokens],
+        )?,
+    };
+
+    Ok(())
+}
+
+/// Every usage record of the last `days` days, newest first.
+pub fn usage_since(conn: &Connection, days: u32) -> Result<Vec<UsageRecord>> {
+    setup_usage_table(conn)?;
+
+    let mut statement = conn.prepare(
+        "select day, source, doc, model, requests, prompt_tokens from embedding_usage
+        where day >= date('now', ?)
+        order by day desc, created_at desc",
+    )?;
+
+    let records = statement
+        .query_map([format!("-{days} days")], |row| {
+            Ok(UsageRecord {
+                day: row.get(0)?,
+                source: row.get(1)?,
+                doc: row.get(2)?,
+                model: row.get(3)?,
+                requests: row.get(4)?,
+                prompt_tokens: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(requests: u64, prompt_tokens: u64) -> TokenUsage {
+        TokenUsage {
+            requests,
+            prompt_tokens,
+        }
+    }
+
+    fn totals(records: &[UsageRecord]) -> Vec<(&str, Option<&str>, &str, u64, u64)> {
+        records
+            .iter()
+            .map(|r| {
+                (
+                    r.source.as_str(),
+                    r.doc.as_deref(),
+                    r.model.as_str(),
+                    r.requests,
+                    r.prompt_tokens,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn searches_are_added_up_per_day_and_model() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_usage_table(&conn).unwrap();
+
+        record_usage(&conn, UsageSource::Search, None, "small", usage(1, 5)).unwrap();
+        record_usage(&conn, UsageSource::Search, None, "small", usage(2, 7)).unwrap();
+        record_usage(&conn, UsageSource::Search, None, "large", usage(1, 3)).unwrap();
+        record_usage(&conn, UsageSource::Search, None, "small", usage(0, 0)).unwrap();
+
+        let mut records = usage_since(&conn, 1).unwrap();
+        records.sort_by(|a, b| a.model.cmp(&b.model));
+        assert_eq!(
+            totals(&records),
+            [
+                ("search", None, "large", 1, 3),
+                ("search", None, "small", 3, 12)
+            ]
+        );
+    }
+
+    #[test]
+    fn syncs_keep_a_row_each_and_old_days_are_left_out() {
+        let conn = Connection::open_in_memory().unwrap();
+        setup_usage_table(&conn).unwrap();
+
+        record_usage(
+            &conn,
+            UsageSource::Sync,
+            Some("demo"),
+            "small",
+            usage(2, 10),
+        )
+        .unwrap();
+        record_usage(&conn, UsageSource::Sync, Some("demo"), "small", usage(1, 4)).unwrap();
+        record_usage(
+            &conn,
+            UsageSource::Batch,
+            Some("demo"),
+            "small",
+            usage(1, 8),
+        )
+        .unwrap();
+        conn.execute(
+            "insert into embedding_usage(day, source, model, requests, prompt_tokens)
+            values (date('now', '-40 days'), 'search', 'small', 9, 90)",
+            [],
+        )
+        .unwrap();
+
+        let records = usage_since(&conn, 30).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records.iter().map(|r| r.prompt_tokens).sum::<u64>(),
+            22,
+            "{records:?}"
+        );
+        assert!(records.iter().all(|r| r.doc.as_deref() == Some("demo")));
+        assert_eq!(usage_since(&conn, 60).unwrap().len(), 4);
+    }
+}
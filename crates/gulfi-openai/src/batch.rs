@@ -0,0 +1,395 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use eyre::{Result, eyre};
+use reqwest::{
+    Client, Url,
+    multipart::{Form, Part},
+};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, instrument};
+
+use crate::{EmbeddingObject, Embeddings, OpenAIClient, RequestBody, ResponseBody, TokenUsage};
+
+/// Maximum amount of requests accepted in a single batch input file.
+pub const BATCH_MAX_REQUESTS: usize = 50_000;
+
+/// Fraction of the regular price charged for the requests of a batch.
+pub const BATCH_PRICE_FACTOR: f64 = 0.5;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Validating,
+    Failed,
+    InProgress,
+    Finalizing,
+    Completed,
+    Expired,
+    Cancelling,
+    Cancelled,
+}
+
+impl BatchStatus {
+    /// Whether the batch won't change its status anymore.
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            Self::Failed | Self::Completed | Self::Expired | Self::Cancelled
+        )
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Validating => "validating",
+            Self::Failed => "failed",
+            Self::InProgress => "in_progress",
+            Self::Finalizing => "finalizing",
+            Self::Completed => "completed",
+            Self::Expired => "expired",
+            Self::Cancelling => "cancelling",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl std::fmt::Display for BatchStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RequestCounts {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Batch {
+    pub id: String,
+    pub status: BatchStatus,
+    pub input_file_id: String,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    #[serde(default)]
+    pub request_counts: Option<RequestCounts>,
+}
+
+/// The embeddings of a finished batch, along with the rows whose request failed.
+#[derive(Debug, Default)]
+pub struct BatchOutput {
+    pub embeddings: Embeddings,
+    pub failed: Vec<(u64, String)>,
+    pub usage: TokenUsage,
+}
+
+#[derive(Deserialize)]
+struct FileObject {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct BatchLine {
+    custom_id: String,
+    response: Option<BatchLineResponse>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct BatchLineResponse {
+    status_code: u16,
+    body: serde_json::Value,
+}
+
+impl OpenAIClient {
+    /// Base URL of the API, derived from the embeddings `endpoint_url`.
+    fn api_base(&self) -> Result<Url> {
+        let mut url = Url::parse(&self.endpoint_url)?;
+        url.path_segments_mut()
+            .map_err(|()| eyre!("'{}' is not a valid base URL", self.endpoint_url))?
+            .pop();
+        Ok(url)
+    }
+
+    fn api_url(&self, path: &str) -> Result<Url> {
+        let base = self.api_base()?;
+        Ok(Url::parse(&format!(
+            "{}/{path}",
+            base.as_str().trim_end_matches('/')
+        ))?)
+    }
+
+    /// Writes the JSONL input of a batch, with one embedding request per row. The body of each
+    /// one is the same as the one of a synchronous request.
+    pub fn write_batch_input<P: AsRef<Path>>(&self, path: P, rows: &[(u64, String)]) -> Result<()> {
+        let url = Url::parse(&self.endpoint_url)?;
+        let endpoint = url.path();
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        for (id, input) in rows {
+            let line = json!({
+                "custom_id": id.to_string(),
+                "method": "POST",
+                "url": endpoint,
+                "body": RequestBody {
+                    input: std::slice::from_ref(input),
+                    model: &self.model,
+                    encoding_format: Some(self.encoding_format),
+                    dimensions: self.dimensions,
+                },
+            });
+            serde_json::to_writer(&mut writer, &line)?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "batch.upload", skip(self, client, path))]
+    pub async fn upload_batch_file<P: AsRef<Path>>(
+        &self,
+        client: &Client,
+        path: P,
+    ) -> Result<String> {
+        let path = path.as_ref();
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "batch.jsonl".to_owned());
+
+        let bytes = tokio::fs::read(path).await?;
+        let form = Form::new()
+            .text("purpose", "batch")
+            .part("file", Part::bytes(bytes).file_name(file_name));
+
+        let file: FileObject = client
+            .post(self.api_url("files")?)
+            .bearer_auth(self.auth_token.expose_secret())
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        debug!(file_id = %file.id, "batch input uploaded");
+        Ok(file.id)
+    }
+
+    #[instrument(name = "batch.create", skip(self, client))]
+    pub async fn create_batch(&self, client: &Client, input_file_id: &str) -> Result<Batch> {
+        let endpoint = Url::parse(&self.endpoint_url)?.path().to_owned();
+
+        let batch = client
+            .post(self.api_url("batches")?)
+            .bearer_auth(self.auth_token.expose_secret())
+            .json(&json!({
+                "input_file_id": input_file_id,
+                "endpoint": endpoint,
+                "completion_window": "24h",
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(batch)
+    }
+
+    #[instrument(name = "batch.retrieve", skip(self, client))]
+    pub async fn retrieve_batch(&self, client: &Client, batch_id: &str) -> Result<Batch> {
+        let batch = client
+            .get(self.api_url(&format!("batches/{batch_id}"))?)
+            .bearer_auth(self.auth_token.expose_secret())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(batch)
+    }
+
+    /// Downloads and parses the output (and error) files of a completed batch.
+    #[instrument(name = "batch.download", skip(self, client, batch), fields(batch_id = %batch.id))]
+    pub async fn download_batch_output(
+        &self,
+        client: &Client,
+        batch: &Batch,
+    ) -> Result<BatchOutput> {
+        let mut output = BatchOutput::default();
+
+        for file_id in [&batch.output_file_id, &batch.error_file_id]
+            .into_iter()
+            .flatten()
+        {
+            let content = client
+                .get(self.api_url(&format!("files/{file_id}/content"))?)
+                .bearer_auth(self.auth_token.expose_secret())
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+
+            parse_batch_output(&content, &mut output)?;
+        }
+
+        Ok(output)
+    }
+}
+
+fn parse_batch_output(content: &[u8], output: &mut BatchOutput) -> Result<()> {
+    for line in content.split(|b| *b == b'\n') {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        let line: BatchLine = serde_json::from_slice(line)?;
+        let id: u64 = line
+            .custom_id
+            .parse()
+            .map_err(|_| eyre!("'{}' is not a valid row id", line.custom_id))?;
+
+        match line.response {
+            Some(response) if response.status_code == 200 => {
+                let body: ResponseBody = serde_json::from_value(response.body)?;
+                output.usage.requests += 1;
+                output.usage.prompt_tokens += body
+                    .usage
+                    .as_ref()
+                    .and_then(|u| u.prompt_tokens)
+                    .unwrap_or(0);
+                let embedding = EmbeddingObject::embeddings_iter(body.embeddings)
+                    .next()
+                    .ok_or_else(|| eyre!("the response for row {id} has no embedding"))?;
+                output.embeddings.push((id, embedding));
+            }
+            Some(response) => output
+                .failed
+                .push((id, format!("{} -> {}", response.status_code, response.body))),
+            None => output.failed.push((
+                id,
+                line.error
+                    .map(|err| err.to_string())
+                    .unwrap_or_else(|| "unknown error".to_owned()),
+            )),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        extract::Path as UrlPath,
+        routing::{get, post},
+    };
+    use serde_json::Value;
+
+    async fn spawn_mock() -> String {
+        let app = Router::new()
+            .route("/v1/files", post(|| async { Json(json!({ "id": "file-in" })) }))
+            .route(
+                "/v1/batches",
+                post(|Json(body): Json<Value>| async move {
+                    assert_eq!(body["input_file_id"], "file-in");
+                    assert_eq!(body["endpoint"], "/v1/embeddings");
+                    Json(json!({
+                        "id": "batch_1",
+                        "status": "validating",
+                        "input_file_id": "file-in",
+                    }))
+                }),
+            )
+            .route(
+                "/v1/batches/:id",
+                get(|UrlPath(id): UrlPath<String>| async move {
+                    Json(json!({
+                        "id": id,
+                        "status": "completed",
+                        "input_file_id": "file-in",
+                        "output_file_id": "file-out",
+                        "request_counts": { "total": 2, "completed": 1, "failed": 1 },
+                    }))
+                }),
+            )
+            .route(
+                "/v1/files/:id/content",
+                get(|| async {
+                    concat!(
+                        r#"{"custom_id": "7", "response": {"status_code": 200, "body": {"data": [{"index": 0, "embedding": [0.5, 0.25]}]}}, "error": null}"#,
+                        "\n",
+                        r#"{"custom_id": "9", "response": {"status_code": 400, "body": {"error": "too long"}}, "error": null}"#,
+                        "\n",
+                    )
+                }),
+            );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Should bind to a local port");
+        let addr = listener.local_addr().expect("Should have a local address");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        format!("http://{addr}/v1/embeddings")
+    }
+
+    #[tokio::test]
+    async fn runs_a_batch_against_a_mock_server() {
+        let endpoint = spawn_mock().await;
+        let openai = OpenAIClient::new("token".to_owned(), endpoint);
+        let client = Client::new();
+
+        let path = std::env::temp_dir().join(format!("gulfi_batch_{}.jsonl", std::process::id()));
+        openai
+            .write_batch_input(&path, &[(7, "hola".to_owned()), (9, "chau".to_owned())])
+            .unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let first: Value = serde_json::from_str(written.lines().next().unwrap()).unwrap();
+        assert_eq!(first["custom_id"], "7");
+        assert_eq!(first["url"], "/v1/embeddings");
+        assert_eq!(first["body"]["input"], serde_json::json!(["hola"]));
+        assert_eq!(first["body"]["dimensions"], crate::DEFAULT_DIMENSIONS);
+
+        // Models like `text-embedding-ada-002` reject the field, it's only sent when configured.
+        let ada = OpenAIClient::new("token".to_owned(), openai.endpoint_url.clone())
+            .with_model("text-embedding-ada-002", None);
+        ada.write_batch_input(&path, &[(7, "hola".to_owned())])
+            .unwrap();
+        let line: Value =
+            serde_json::from_str(std::fs::read_to_string(&path).unwrap().trim()).unwrap();
+        assert!(line["body"].get("dimensions").is_none());
+
+        openai
+            .write_batch_input(&path, &[(7, "hola".to_owned()), (9, "chau".to_owned())])
+            .unwrap();
+
+        let file_id = openai.upload_batch_file(&client, &path).await.unwrap();
+        let batch = openai.create_batch(&client, &file_id).await.unwrap();
+        assert_eq!(batch.status, BatchStatus::Validating);
+
+        let batch = openai.retrieve_batch(&client, &batch.id).await.unwrap();
+        assert!(batch.status.is_terminal());
+
+        let output = openai.download_batch_output(&client, &batch).await.unwrap();
+        assert_eq!(output.embeddings, vec![(7, vec![0.5, 0.25])]);
+        assert_eq!(output.failed.len(), 1);
+        assert_eq!(output.failed[0].0, 9);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
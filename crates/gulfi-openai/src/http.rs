@@ -0,0 +1,323 @@
+use std::sync::Arc;
+
+use eyre::{Result, eyre};
+use reqwest::{Client, RequestBuilder};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::sync::mpsc::Sender;
+use tracing::instrument;
+
+use crate::{
+    EmbeddingError, EmbeddingProvider, Embeddings, HttpBackend, ParsedResponse, RateLimiter,
+    RateLimits, UsageCounter, embed_batch, embed_one, embedding_message::EmbeddingMessage,
+};
+
+/// Describes the JSON body sent to, and received from, a generic embedding endpoint.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HttpEndpointConfig {
+    /// Body field holding the list of inputs.
+    pub input_field: String,
+    /// Body field holding the model name, if the endpoint takes one.
+    pub model_field: Option<String>,
+    /// JSON pointer to the list of embeddings in the response, e.g. `/data`.
+    pub response_pointer: String,
+    /// Field holding the vector when the list contains objects instead of arrays.
+    pub embedding_field: Option<String>,
+    /// Field holding the position of the matching input. Without it, the list is assumed to
+    /// follow the order of the input.
+    pub index_field: Option<String>,
+    /// JSON pointer to the amount of tokens billed, e.g. `/usage/prompt_tokens`.
+    pub usage_pointer: Option<String>,
+    /// Header used to send the `auth_token`.
+    pub auth_header: String,
+    /// Scheme prepended to the `auth_token`, e.g. `Bearer`.
+    pub auth_scheme: Option<String>,
+    /// Extra fields merged into every request body.
+    pub extra_body: Map<String, Value>,
+}
+
+impl Default for HttpEndpointConfig {
+    fn default() -> Self {
+        Self {
+            input_field: "input".to_owned(),
+            model_field: Some("model".to_owned()),
+            response_pointer: "/data".to_owned(),
+            embedding_field: Some("embedding".to_owned()),
+            index_field: Some("index".to_owned()),
+            usage_pointer: Some("/usage/prompt_tokens".to_owned()),
+            auth_header: "Authorization".to_owned(),
+            auth_scheme: Some("Bearer".to_owned()),
+            extra_body: Map::new(),
+        }
+    }
+}
+
+/// Client for any JSON-over-HTTP embedding endpoint described by an [`HttpEndpointConfig`].
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    pub endpoint_url: String,
+    pub auth_token: SecretString,
+    pub model: String,
+    pub config: HttpEndpointConfig,
+    pub limiter: Arc<RateLimiter>,
+    pub usage: Arc<UsageCounter>,
+}
+
+impl HttpClient {
+    pub fn new(
+        endpoint_url: String,
+        auth_token: SecretString,
+        model: String,
+        config: HttpEndpointConfig,
+    ) -> Self {
+        Self {
+            endpoint_url,
+            auth_token,
+            model,
+            config,
+            limiter: Arc::default(),
+            usage: Arc::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.limiter = Arc::new(RateLimiter::new(limits));
+        self
+    }
+}
+
+impl HttpBackend for HttpClient {
+    fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    fn build_request(&self, client: &Client, input: &[String]) -> RequestBuilder {
+        let mut body = self.config.extra_body.clone();
+        body.insert(self.config.input_field.clone(), Value::from(input.to_vec()));
+
+        if let Some(model_field) = &self.config.model_field {
+            body.insert(model_field.clone(), Value::from(self.model.clone()));
+        }
+
+        let mut request = client.post(&self.endpoint_url).json(&body);
+
+        let token = self.auth_token.expose_secret();
+        if !token.is_empty() {
+            let value = match &self.config.auth_scheme {
+                Some(scheme) => format!("{scheme} {token}"),
+                None => token.to_owned(),
+            };
+            request = request.header(self.config.auth_header.as_str(), value);
+        }
+
+        request
+    }
+
+    fn parse_response(&self, payload: &mut [u8]) -> Result<ParsedResponse> {
+        let response: Value = simd_json::serde::from_slice(payload)?;
+        let pointer = &self.config.response_pointer;
+
+        let items = response
+            .pointer(pointer)
+            .and_then(Value::as_array)
+            .ok_or_else(|| eyre!("The response has no embedding list at `{pointer}`"))?;
+
+        let prompt_tokens = self
+            .config
+            .usage_pointer
+            .as_deref()
+            .and_then(|pointer| response.pointer(pointer))
+            .and_then(Value::as_u64);
+
+        let embeddings = items
+            .iter()
+            .enumerate()
+            .map(|(position, item)| {
+                let index = match (&self.config.index_field, item) {
+                    (Some(field), Value::Object(obj)) if obj.contains_key(field) => obj[field]
+                        .as_u64()
+                        .map(|i| i as usize)
+                        .ok_or_else(|| eyre!("The response has a malformed index: {item}"))?,
+                    _ => position,
+                };
+
+                let vector = match (&self.config.embedding_field, item) {
+                    (Some(field), Value::Object(obj)) => obj.get(field),
+                    _ => Some(item),
+                };
+
+                vector
+                    .and_then(Value::as_array)
+                    .and_then(|values| {
+                        values
+                            .iter()
+                            .map(|v| v.as_f64().map(|v| v as f32))
+                            .collect::<Option<Vec<f32>>>()
+                    })
+                    .map(|vector| (index, vector))
+                    .ok_or_else(|| eyre!("The response has a malformed embedding: {item}"))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ParsedResponse {
+            embeddings,
+            prompt_tokens,
+        })
+    }
+}
+
+impl EmbeddingProvider for HttpClient {
+    async fn embed_vec_with_progress(
+        &self,
+        indices: Vec<u64>,
+        input: Vec<String>,
+        client: &Client,
+        proc_id: usize,
+        base_delay: u64,
+        tx: Sender<EmbeddingMessage>,
+    ) -> Result<(Embeddings, u128)> {
+        embed_batch(self, indices, input, client, proc_id, base_delay, tx).await
+    }
+
+    #[instrument(name = "embed.request", skip(self, input, client), fields(url = %self.endpoint_url, input_len = input.len()))]
+    async fn embed_single(&self, input: &str, client: &Client) -> Result<Vec<f32>, EmbeddingError> {
+        embed_one(self, input, client).await
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn usage(&self) -> &UsageCounter {
+        &self.usage
+    }
+
+    fn max_concurrency(&self) -> usize {
+        self.limiter.max_concurrency()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn body_of(request: RequestBuilder) -> (reqwest::Request, Value) {
+        let request = request.build().expect("Should build the request");
+        let body = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .expect("Should have a JSON body");
+        let body = serde_json::from_slice(body).expect("Should be valid JSON");
+        (request, body)
+    }
+
+    #[test]
+    fn builds_the_configured_body_and_auth() {
+        let config = HttpEndpointConfig {
+            input_field: "texts".to_owned(),
+            model_field: None,
+            auth_header: "X-Api-Key".to_owned(),
+            auth_scheme: None,
+            extra_body: Map::from_iter([("truncate".to_owned(), json!("END"))]),
+            ..HttpEndpointConfig::default()
+        };
+        let client = HttpClient::new(
+            "http://localhost/embed".to_owned(),
+            SecretString::from("secret"),
+            "model-a".to_owned(),
+            config,
+        );
+
+        let input = vec!["hola".to_owned(), "chau".to_owned()];
+        let (request, body) = body_of(client.build_request(&Client::new(), &input));
+
+        assert_eq!(request.url().as_str(), "http://localhost/embed");
+        assert_eq!(request.headers()["X-Api-Key"], "secret");
+        assert_eq!(
+            body,
+            json!({ "texts": ["hola", "chau"], "truncate": "END" })
+        );
+
+        let default = HttpClient::new(
+            "http://localhost/embed".to_owned(),
+            SecretString::from("secret"),
+            "model-a".to_owned(),
+            HttpEndpointConfig::default(),
+        );
+        let (request, body) = body_of(default.build_request(&Client::new(), &input));
+
+        assert_eq!(request.headers()["Authorization"], "Bearer secret");
+        assert_eq!(
+            body,
+            json!({ "input": ["hola", "chau"], "model": "model-a" })
+        );
+
+        let anonymous = HttpClient::new(
+            "http://localhost/embed".to_owned(),
+            SecretString::from(""),
+            "model-a".to_owned(),
+            HttpEndpointConfig::default(),
+        );
+        let (request, _) = body_of(anonymous.build_request(&Client::new(), &input));
+        assert!(!request.headers().contains_key("Authorization"));
+    }
+
+    #[test]
+    fn parses_objects_and_plain_arrays() {
+        let client = HttpClient::new(
+            String::new(),
+            SecretString::from(""),
+            String::new(),
+            HttpEndpointConfig::default(),
+        );
+
+        let mut payload = br#"{"data": [{"index": 1, "embedding": [1.0]}, {"index": 0, "embedding": [0.5]}], "usage": {"prompt_tokens": 7}}"#.to_vec();
+        let parsed = client.parse_response(&mut payload).unwrap();
+        assert_eq!(parsed.embeddings, vec![(1, vec![1.0]), (0, vec![0.5])]);
+        assert_eq!(parsed.prompt_tokens, Some(7));
+
+        let arrays = HttpClient::new(
+            String::new(),
+            SecretString::from(""),
+            String::new(),
+            HttpEndpointConfig {
+                response_pointer: "/result/vectors".to_owned(),
+                embedding_field: None,
+                usage_pointer: None,
+                ..HttpEndpointConfig::default()
+            },
+        );
+
+        let mut payload = br#"{"result": {"vectors": [[0.0, 1.0], [2.0, 3.0]]}}"#.to_vec();
+        let parsed = arrays.parse_response(&mut payload).unwrap();
+        assert_eq!(
+            parsed.embeddings,
+            vec![(0, vec![0.0, 1.0]), (1, vec![2.0, 3.0])]
+        );
+        assert_eq!(parsed.prompt_tokens, None);
+    }
+
+    #[test]
+    fn rejects_malformed_responses() {
+        let client = HttpClient::new(
+            String::new(),
+            SecretString::from(""),
+            String::new(),
+            HttpEndpointConfig::default(),
+        );
+
+        for payload in [
+            r#"{"embeddings": []}"#,
+            r#"{"data": [{"index": 0, "embedding": ["a"]}]}"#,
+            r#"{"data": [{"index": -1, "embedding": [0.0]}]}"#,
+            r#"{"data": [{"index": 0}]}"#,
+        ] {
+            let mut payload = payload.as_bytes().to_vec();
+            assert!(client.parse_response(&mut payload).is_err(), "{payload:?}");
+        }
+    }
+}
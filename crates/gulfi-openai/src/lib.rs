@@ -1,36 +1,114 @@
+pub mod batch;
 pub mod embedding_message;
-
-use secrecy::{ExposeSecret, SecretBox, SecretString};
+pub mod http;
+pub mod limiter;
+pub mod local;
+pub mod ollama;
+pub mod openai;
+pub mod prompts;
+pub mod rerank;
+pub mod tokens;
+pub mod usage;
+
+pub use http::HttpClient;
+pub use limiter::{RateLimiter, RateLimits};
+pub use local::LocalHashClient;
+pub use ollama::OllamaClient;
+pub use openai::*;
+pub use prompts::PromptTemplates;
+pub use rerank::RerankClient;
+pub use tokens::TokenEstimator;
+pub use usage::{TokenUsage, UsageCounter};
+
+use std::future::Future;
 use std::time::{Duration, Instant};
 
 use bytes::BufMut;
 use eyre::Result;
 use rand::Rng;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use reqwest::{Client, RequestBuilder};
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, error, info, instrument, warn};
+use tracing::{debug, info, warn};
 
 use crate::embedding_message::EmbeddingMessage;
 
 const MAX_RETRIES: u32 = 5;
 
+/// Retries of a single input. A search query is waiting on it, so it gives up sooner, and
+/// never takes longer than its deadline across every attempt.
+const QUERY_RETRY: RetryPolicy = RetryPolicy {
+    max_retries: 3,
+    base_delay: 200,
+    deadline: Some(Duration::from_secs(20)),
+};
+/// Time a single input request may take before giving up on it.
+pub(crate) const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Context length assumed for providers that don't report one.
+pub const DEFAULT_MAX_INPUT_TOKENS: usize = 8192;
+
+/// Embeddings paired with the id of the row they belong to.
+pub type Embeddings = Vec<(u64, Vec<f32>)>;
+
+/// A service able to turn text into embeddings.
+pub trait EmbeddingProvider: Send + Sync {
+    /// Embeds a batch of `input`, reporting its progress through `tx`.
+    ///
+    /// Returns every embedding paired with its entry in `indices` and the elapsed time in ms.
+    fn embed_vec_with_progress(
+        &self,
+        indices: Vec<u64>,
+        input: Vec<String>,
+        client: &Client,
+        proc_id: usize,
+        base_delay: u64,
+        tx: Sender<EmbeddingMessage>,
+    ) -> impl Future<Output = Result<(Embeddings, u128)>> + Send;
+
+    /// Embeds a single input, usually a search query.
+    ///
+    /// Transient failures are retried with the same backoff as [`Self::embed_vec_with_progress`],
+    /// the rest are returned as the [`EmbeddingError`] that describes them.
+    fn embed_single(
+        &self,
+        input: &str,
+        client: &Client,
+    ) -> impl Future<Output = Result<Vec<f32>, EmbeddingError>> + Send;
+
+    /// Name of the model, used to price its usage.
+    fn model(&self) -> &str;
+
+    /// Tokens billed by the provider since the client was built, or since the last
+    /// [`UsageCounter::take`].
+    fn usage(&self) -> &UsageCounter;
+
+    /// Estimator used to keep the requests under the token limits of the model.
+    fn token_estimator(&self) -> TokenEstimator {
+        TokenEstimator::Heuristic
+    }
+
+    /// Maximum amount of tokens the model accepts for a single input.
+    fn max_input_tokens(&self) -> usize {
+        DEFAULT_MAX_INPUT_TOKENS
+    }
+
+    /// Maximum amount of requests in flight at once.
+    fn max_concurrency(&self) -> usize {
+        RateLimits::default().max_concurrency
+    }
+}
+
+/// Every available [`EmbeddingProvider`], selected from the `embedding_provider` settings.
 #[derive(Debug, Clone)]
-pub struct OpenAIClient {
-    pub auth_token: SecretString,
-    pub endpoint_url: String,
+pub enum EmbeddingClient {
+    OpenAI(OpenAIClient),
+    Ollama(OllamaClient),
+    Http(HttpClient),
+    LocalHash(LocalHashClient),
 }
 
-impl OpenAIClient {
-    pub fn new(auth_token: String, endpoint_url: String) -> Self {
-        Self {
-            auth_token: SecretString::new(auth_token.into()),
-            endpoint_url,
-        }
-    }
-    // https://community.openai.com/t/does-the-index-field-on-an-embedding-response-correlate-to-the-index-of-the-input-text-it-was-generated-from/526099
-    // FIX: Siempre hay una request que devuelve 400 no 429.
-    pub async fn embed_vec_with_progress(
+impl EmbeddingProvider for EmbeddingClient {
+    async fn embed_vec_with_progress(
         &self,
         indices: Vec<u64>,
         input: Vec<String>,
@@ -38,220 +116,380 @@ impl OpenAIClient {
         proc_id: usize,
         base_delay: u64,
         tx: Sender<EmbeddingMessage>,
-    ) -> Result<(Vec<(u64, Vec<f32>)>, u128)> {
-        let global_start = Instant::now();
-
-        let _ = tx
-            .send(EmbeddingMessage::Preparing { count: input.len() })
-            .await;
+    ) -> Result<(Embeddings, u128)> {
+        match self {
+            EmbeddingClient::OpenAI(c) => {
+                c.embed_vec_with_progress(indices, input, client, proc_id, base_delay, tx)
+                    .await
+            }
+            EmbeddingClient::Ollama(c) => {
+                c.embed_vec_with_progress(indices, input, client, proc_id, base_delay, tx)
+                    .await
+            }
+            EmbeddingClient::Http(c) => {
+                c.embed_vec_with_progress(indices, input, client, proc_id, base_delay, tx)
+                    .await
+            }
+            EmbeddingClient::LocalHash(c) => {
+                c.embed_vec_with_progress(indices, input, client, proc_id, base_delay, tx)
+                    .await
+            }
+        }
+    }
 
-        let request = RequestBody {
-            input,
-            model: "text-embedding-3-small".to_owned(),
-            encoding_format: Some(EncodingFormat::Float),
-            dimensions: Some(1536),
-        };
+    async fn embed_single(&self, input: &str, client: &Client) -> Result<Vec<f32>, EmbeddingError> {
+        match self {
+            EmbeddingClient::OpenAI(c) => c.embed_single(input, client).await,
+            EmbeddingClient::Ollama(c) => c.embed_single(input, client).await,
+            EmbeddingClient::Http(c) => c.embed_single(input, client).await,
+            EmbeddingClient::LocalHash(c) => c.embed_single(input, client).await,
+        }
+    }
 
-        let open_ai_key = self.auth_token.clone();
-        let endpoint_url = self.endpoint_url.clone();
+    fn token_estimator(&self) -> TokenEstimator {
+        match self {
+            EmbeddingClient::OpenAI(c) => c.token_estimator(),
+            EmbeddingClient::Ollama(c) => c.token_estimator(),
+            EmbeddingClient::Http(c) => c.token_estimator(),
+            EmbeddingClient::LocalHash(c) => c.token_estimator(),
+        }
+    }
 
-        let mut current_try = 0;
-        let mut response = None;
+    fn max_input_tokens(&self) -> usize {
+        match self {
+            EmbeddingClient::OpenAI(c) => c.max_input_tokens(),
+            EmbeddingClient::Ollama(c) => c.max_input_tokens(),
+            EmbeddingClient::Http(c) => c.max_input_tokens(),
+            EmbeddingClient::LocalHash(c) => c.max_input_tokens(),
+        }
+    }
 
-        while current_try <= MAX_RETRIES {
-            let req_start = Instant::now();
-            let _ = tx
-                .send(EmbeddingMessage::SendingRequest {
-                    attempt: (current_try + 1) as usize,
-                    max_attempts: (MAX_RETRIES + 1) as usize,
-                })
-                .await;
+    fn max_concurrency(&self) -> usize {
+        match self {
+            EmbeddingClient::OpenAI(c) => c.max_concurrency(),
+            EmbeddingClient::Ollama(c) => c.max_concurrency(),
+            EmbeddingClient::Http(c) => c.max_concurrency(),
+            EmbeddingClient::LocalHash(c) => c.max_concurrency(),
+        }
+    }
 
-            let call = EmbeddingCall {
-                request: EmbeddingRequest {
-                    client,
-                    token: &open_ai_key,
-                    request: &request,
-                    endpoint_url: &endpoint_url,
-                },
-                retry: RetryContext {
-                    attempt: current_try,
-                    max_retries: MAX_RETRIES,
-                    time_backoff: base_delay,
-                },
-                proc_id,
-            };
+    fn model(&self) -> &str {
+        match self {
+            EmbeddingClient::OpenAI(c) => c.model(),
+            EmbeddingClient::Ollama(c) => c.model(),
+            EmbeddingClient::Http(c) => c.model(),
+            EmbeddingClient::LocalHash(c) => c.model(),
+        }
+    }
 
-            match request_embeddings(call).await {
-                Ok(resp) => {
-                    let elapsed = req_start.elapsed().as_millis();
-                    let _ = tx
-                        .send(EmbeddingMessage::RequestSuccessful {
-                            elapsed_ms: (elapsed),
-                        })
-                        .await;
-                    response = Some(resp);
-                    break;
-                }
-                Err(EmbeddingError::RateLimit) => {
-                    let _ = tx
-                        .send(EmbeddingMessage::RateLimit {
-                            attempt: (current_try + 1) as usize,
-                            max_attempts: (MAX_RETRIES + 1) as usize,
-                        })
-                        .await;
-                    current_try += 1;
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(EmbeddingMessage::Error {
-                            message: format!("{e}"),
-                        })
-                        .await;
-                    return Err(e.into());
-                }
-            }
+    fn usage(&self) -> &UsageCounter {
+        match self {
+            EmbeddingClient::OpenAI(c) => c.usage(),
+            EmbeddingClient::Ollama(c) => c.usage(),
+            EmbeddingClient::Http(c) => c.usage(),
+            EmbeddingClient::LocalHash(c) => c.usage(),
         }
+    }
+}
 
-        let Some(mut response) = response else {
-            let _ = tx.send(EmbeddingMessage::MaxRetriesExceeded).await;
-            return Err(EmbeddingError::MaxRetriesExceeded.into());
-        };
+/// The wire format of an embedding API reachable over HTTP.
+pub(crate) trait HttpBackend: Sync {
+    /// Limiter shared by every request to the backend.
+    fn limiter(&self) -> &RateLimiter;
 
-        let _ = tx.send(EmbeddingMessage::ParsingResponse).await;
-        let start = Instant::now();
+    /// Builds the request that embeds `input`.
+    fn build_request(&self, client: &Client, input: &[String]) -> RequestBuilder;
 
-        let capacity = response.content_length().unwrap_or(0) as usize;
-        let mut payload = Vec::with_capacity(capacity);
-        while let Some(chunk) = response.chunk().await? {
-            payload.put(chunk);
+    /// Extracts the embeddings from a response body, each one paired with the position of its
+    /// input in the request.
+    fn parse_response(&self, payload: &mut [u8]) -> Result<ParsedResponse>;
+}
+
+pub(crate) struct ParsedResponse {
+    pub embeddings: Vec<(usize, Vec<f32>)>,
+    /// Tokens billed for the request, if the response reports them.
+    pub prompt_tokens: Option<u64>,
+}
+
+/// Places every embedding in the position of its input, checking that each of the `expected`
+/// inputs got exactly one embedding.
+pub(crate) fn order_by_index(
+    expected: usize,
+    items: Vec<(usize, Vec<f32>)>,
+) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    if items.len() != expected {
+        return Err(EmbeddingError::CountMismatch {
+            expected,
+            received: items.len(),
+        });
+    }
+
+    let mut slots: Vec<Option<Vec<f32>>> = vec![None; expected];
+    for (index, embedding) in items {
+        let slot = slots
+            .get_mut(index)
+            .ok_or(EmbeddingError::IndexOutOfRange { index, expected })?;
+
+        if slot.replace(embedding).is_some() {
+            return Err(EmbeddingError::DuplicateIndex(index));
         }
+    }
 
-        let response: ResponseBody = simd_json::serde::from_slice(&mut payload)?;
+    slots
+        .into_iter()
+        .enumerate()
+        .map(|(index, slot)| slot.ok_or(EmbeddingError::MissingIndex(index)))
+        .collect()
+}
 
-        let elapsed = start.elapsed().as_millis();
+pub(crate) async fn embed_batch<B: HttpBackend + EmbeddingProvider>(
+    backend: &B,
+    indices: Vec<u64>,
+    input: Vec<String>,
+    client: &Client,
+    proc_id: usize,
+    base_delay: u64,
+    tx: Sender<EmbeddingMessage>,
+) -> Result<(Embeddings, u128)> {
+    let global_start = Instant::now();
+
+    let _ = tx
+        .send(EmbeddingMessage::Preparing { count: input.len() })
+        .await;
+
+    let estimator = backend.token_estimator();
+    let tokens = input.iter().map(|input| estimator.count(input)).sum();
+
+    let policy = RetryPolicy {
+        max_retries: MAX_RETRIES,
+        base_delay,
+        deadline: None,
+    };
+    let mut current_try = 0;
+    let mut response = None;
+
+    while current_try <= policy.max_retries {
+        let req_start = Instant::now();
         let _ = tx
-            .send(EmbeddingMessage::ParsingComplete {
-                elapsed_ms: elapsed,
+            .send(EmbeddingMessage::SendingRequest {
+                attempt: (current_try + 1) as usize,
+                max_attempts: (policy.max_retries + 1) as usize,
             })
             .await;
 
-        let _ = tx.send(EmbeddingMessage::ProcessingEmbeddings).await;
-        let embedding: Vec<(u64, Vec<f32>)> = std::iter::zip(
-            indices,
-            EmbeddingObject::embeddings_iter(response.embeddings),
-        )
-        .collect();
+        let call = EmbeddingCall {
+            request: backend.build_request(client, &input),
+            attempt: current_try,
+            policy,
+            limiter: backend.limiter(),
+            tokens,
+            proc_id,
+        };
 
-        let total_elapsed = global_start.elapsed().as_millis();
-        let _ = tx
-            .send(EmbeddingMessage::Complete {
-                total_elapsed_ms: total_elapsed,
-            })
-            .await;
+        match request_embeddings(call).await {
+            Ok(resp) => {
+                let elapsed = req_start.elapsed().as_millis();
+                let _ = tx
+                    .send(EmbeddingMessage::RequestSuccessful {
+                        elapsed_ms: (elapsed),
+                    })
+                    .await;
+                response = Some(resp);
+                break;
+            }
+            Err(EmbeddingError::RateLimit) => {
+                let _ = tx
+                    .send(EmbeddingMessage::RateLimit {
+                        attempt: (current_try + 1) as usize,
+                        max_attempts: (policy.max_retries + 1) as usize,
+                    })
+                    .await;
+                current_try += 1;
+            }
+            Err(e) => {
+                let _ = tx
+                    .send(EmbeddingMessage::Error {
+                        message: format!("{e}"),
+                    })
+                    .await;
+                return Err(e.into());
+            }
+        }
+    }
+
+    let Some(mut response) = response else {
+        let _ = tx.send(EmbeddingMessage::MaxRetriesExceeded).await;
+        return Err(EmbeddingError::MaxRetriesExceeded.into());
+    };
 
-        Ok((embedding, total_elapsed))
+    let _ = tx.send(EmbeddingMessage::ParsingResponse).await;
+    let start = Instant::now();
+
+    let capacity = response.content_length().unwrap_or(0) as usize;
+    let mut payload = Vec::with_capacity(capacity);
+    while let Some(chunk) = response.chunk().await? {
+        payload.put(chunk);
     }
 
-    #[instrument(name = "embed.request", skip(self, input, client) ,  fields(url = %self.endpoint_url, input_len = input.len()))]
-    pub async fn embed_single(&self, input: &str, client: &Client) -> Result<Vec<f32>> {
-        let input = input.to_string();
-        let global_start = Instant::now();
-
-        #[derive(Serialize, Deserialize)]
-        pub struct RequestBody {
-            pub input: String,
-            pub model: String,
-            pub encoding_format: Option<EncodingFormat>,
-            pub dimensions: Option<u64>,
+    let parsed = backend.parse_response(&mut payload)?;
+    backend
+        .usage()
+        .record(parsed.prompt_tokens.unwrap_or(tokens as u64));
+    let embeddings = match order_by_index(input.len(), parsed.embeddings) {
+        Ok(embeddings) => embeddings,
+        Err(err) => {
+            let _ = tx
+                .send(EmbeddingMessage::Error {
+                    message: format!("{err}"),
+                })
+                .await;
+            return Err(err.into());
         }
+    };
+
+    let elapsed = start.elapsed().as_millis();
+    let _ = tx
+        .send(EmbeddingMessage::ParsingComplete {
+            elapsed_ms: elapsed,
+        })
+        .await;
+
+    let _ = tx.send(EmbeddingMessage::ProcessingEmbeddings).await;
+    let embedding: Embeddings = std::iter::zip(indices, embeddings).collect();
+
+    let total_elapsed = global_start.elapsed().as_millis();
+    let _ = tx
+        .send(EmbeddingMessage::Complete {
+            total_elapsed_ms: total_elapsed,
+        })
+        .await;
+
+    Ok((embedding, total_elapsed))
+}
 
-        let request = RequestBody {
-            input,
-            model: "text-embedding-3-small".to_owned(),
-            encoding_format: Some(EncodingFormat::Float),
-            dimensions: Some(1536),
-        };
+pub(crate) async fn embed_one<B: HttpBackend + EmbeddingProvider>(
+    backend: &B,
+    input: &str,
+    client: &Client,
+) -> Result<Vec<f32>, EmbeddingError> {
+    let global_start = Instant::now();
 
-        let open_ai_key = self.auth_token.clone();
-        let endpoint_url = self.endpoint_url.clone();
+    let input = [input.to_string()];
+    let tokens = backend.token_estimator().count(&input[0]);
 
-        let req_start = Instant::now();
-        info!("Sending request to Open AI...");
-        let response = client
-            .post(endpoint_url)
-            // .post("https://api.openai.com/v1/embeddings")
-            .bearer_auth(open_ai_key.expose_secret())
-            .json(&request)
-            .send()
-            .await?;
-
-        assert_eq!(response.status().as_u16(), 200);
-        info!("request took {} ms", req_start.elapsed().as_millis());
-
-        let start = Instant::now();
-        let response: ResponseBody = response.json().await?;
-        info!(
-            "Parsing the response took {} ms",
-            start.elapsed().as_millis()
-        );
-
-        let embedding = response
-            .embeddings
-            .into_iter()
-            .next()
-            .expect("Parsed response should not be empty. ")
-            .embedding;
-
-        info!(
-            "Embedding successfully generated! took {} ms",
-            global_start.elapsed().as_millis()
-        );
-
-        Ok(embedding)
+    let policy = QUERY_RETRY;
+    let attempts = async {
+        let mut current_try = 0;
+        loop {
+            let req_start = Instant::now();
+            info!("Sending embedding request...");
+
+            let call = EmbeddingCall {
+                request: backend.build_request(client, &input).timeout(QUERY_TIMEOUT),
+                attempt: current_try,
+                policy,
+                limiter: backend.limiter(),
+                tokens,
+                proc_id: 0,
+            };
+
+            match request_embeddings(call).await {
+                Ok(response) => {
+                    info!("request took {} ms", req_start.elapsed().as_millis());
+                    return Ok(response);
+                }
+                Err(err @ (EmbeddingError::RateLimit | EmbeddingError::Timeout))
+                    if current_try < policy.max_retries =>
+                {
+                    warn!("{err}, retrying");
+                    current_try += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    };
+    let response = policy.bound(attempts).await?;
+
+    let start = Instant::now();
+    let mut payload = response.bytes().await?.to_vec();
+    let parsed = backend
+        .parse_response(&mut payload)
+        .map_err(|err| EmbeddingError::InvalidResponse(err.to_string()))?;
+    backend
+        .usage()
+        .record(parsed.prompt_tokens.unwrap_or(tokens as u64));
+
+    if parsed.embeddings.is_empty() {
+        return Err(EmbeddingError::EmptyResponse);
     }
-}
 
-struct EmbeddingRequest<'a> {
-    client: &'a Client,
-    token: &'a SecretBox<str>,
-    request: &'a RequestBody,
-    endpoint_url: &'a str,
+    let embedding = order_by_index(1, parsed.embeddings)?
+        .into_iter()
+        .next()
+        .filter(|embedding| !embedding.is_empty())
+        .ok_or(EmbeddingError::EmptyResponse)?;
+    info!(
+        "Parsing the response took {} ms",
+        start.elapsed().as_millis()
+    );
+
+    info!(
+        "Embedding successfully generated! took {} ms",
+        global_start.elapsed().as_millis()
+    );
+
+    Ok(embedding)
 }
 
-struct RetryContext {
-    attempt: u32,
+/// How the requests to a provider are retried.
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
     max_retries: u32,
-    time_backoff: u64,
+    /// Base time for the exponential backoff between retries, in ms.
+    base_delay: u64,
+    /// Time every attempt may take together, unbounded if it's not set.
+    deadline: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Awaits `attempts` until the deadline of the policy, if it has one.
+    async fn bound<T>(
+        &self,
+        attempts: impl Future<Output = Result<T, EmbeddingError>>,
+    ) -> Result<T, EmbeddingError> {
+        match self.deadline {
+            Some(deadline) => tokio::time::timeout(deadline, attempts)
+                .await
+                .map_err(|_| EmbeddingError::Timeout)?,
+            None => attempts.await,
+        }
+    }
 }
 
 struct EmbeddingCall<'a> {
-    request: EmbeddingRequest<'a>,
-    retry: RetryContext,
+    request: RequestBuilder,
+    attempt: u32,
+    policy: RetryPolicy,
+    limiter: &'a RateLimiter,
+    tokens: usize,
     proc_id: usize,
 }
 
 async fn request_embeddings(call: EmbeddingCall<'_>) -> Result<reqwest::Response, EmbeddingError> {
     let EmbeddingCall {
         request,
-        retry,
+        attempt,
+        policy,
+        limiter,
+        tokens,
         proc_id,
     } = call;
-    let EmbeddingRequest {
-        client,
-        token,
-        request: body,
-        endpoint_url,
-    } = request;
-    let RetryContext {
-        attempt,
-        max_retries,
-        time_backoff,
-    } = retry;
+    let max_retries = policy.max_retries;
 
     if attempt > 0 {
         warn!("Try {attempt}/{max_retries} [{proc_id}]");
 
+        let time_backoff = policy.base_delay;
         let base_delay = time_backoff * 2u64.pow(attempt);
         let jittered_delay = rand::rng().random_range(0..=base_delay / 2);
 
@@ -259,17 +497,17 @@ async fn request_embeddings(call: EmbeddingCall<'_>) -> Result<reqwest::Response
         tokio::time::sleep(Duration::from_millis(base_delay + jittered_delay)).await;
     }
 
-    let response = client
-        .post(endpoint_url)
-        .bearer_auth(token.expose_secret())
-        .json(body)
-        .send()
-        .await?;
+    let permit = limiter.acquire(tokens).await;
+    let response = request.send().await?;
+    limiter.observe(response.status(), response.headers());
+    drop(permit);
 
     let status = response.status();
 
     match status.as_u16() {
         200..299 => Ok(response),
+        401 | 403 => Err(EmbeddingError::Unauthorized(error_body(response).await)),
+        400 | 404 | 413 | 422 => Err(EmbeddingError::BadRequest(error_body(response).await)),
         429 | 502 | 520 => {
             let retry_after = response
                 .headers()
@@ -291,10 +529,7 @@ async fn request_embeddings(call: EmbeddingCall<'_>) -> Result<reqwest::Response
                 .error_for_status_ref()
                 .expect_err("Deberia poder obtener el error");
 
-            let err_body = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "No response body".to_string());
+            let err_body = error_body(response).await;
 
             let error_msg = format!("{status} -> {err_body}",);
 
@@ -303,68 +538,163 @@ async fn request_embeddings(call: EmbeddingCall<'_>) -> Result<reqwest::Response
     }
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum EncodingFormat {
-    Float,
-    Base64,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ResponseBody {
-    #[serde(rename = "data")]
-    pub embeddings: Vec<EmbeddingObject>,
-    // pub usage: TokenUsage,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct EmbeddingObject {
-    embedding: Vec<f32>,
-}
-
-impl EmbeddingObject {
-    pub fn embeddings_iter(
-        objects: impl IntoIterator<Item = Self>,
-    ) -> impl Iterator<Item = Vec<f32>> {
-        objects.into_iter().map(|obj| obj.embedding)
-    }
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct RequestBody {
-    pub input: Vec<String>,
-    pub model: String,
-    pub encoding_format: Option<EncodingFormat>,
-    pub dimensions: Option<u64>,
+pub(crate) async fn error_body(response: reqwest::Response) -> String {
+    response
+        .text()
+        .await
+        .unwrap_or_else(|_| "No response body".to_string())
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum EmbeddingError {
     #[error("Request failed: {0} {1}")]
     RequestError(reqwest::Error, String),
+    #[error("The provider rejected the credentials: {0}")]
+    Unauthorized(String),
+    #[error("The provider rejected the request: {0}")]
+    BadRequest(String),
     #[error("Rate limit exceeded")]
     RateLimit,
+    #[error("The provider took too long to answer")]
+    Timeout,
+    #[error("The response has no embeddings")]
+    EmptyResponse,
+    #[error("The response couldn't be parsed: {0}")]
+    InvalidResponse(String),
     #[error("Max retries exceeded")]
     MaxRetriesExceeded,
+    #[error("Expected {expected} embeddings, but the response has {received}")]
+    CountMismatch { expected: usize, received: usize },
+    #[error("The response has no embedding for input {0}")]
+    MissingIndex(usize),
+    #[error("The response has more than one embedding for input {0}")]
+    DuplicateIndex(usize),
+    #[error("The response has an embedding for input {index}, but only {expected} were sent")]
+    IndexOutOfRange { index: usize, expected: usize },
 }
 
 impl From<reqwest::Error> for EmbeddingError {
     fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            return EmbeddingError::Timeout;
+        }
         EmbeddingError::RequestError(err, String::default())
     }
 }
 
-// TODO: Implementar las interfaces para poder realizar batch requests y ahorrar gastos.
-// pub async fn batch_embed(input: [&str]) -> eyre::Result<Vec<Vec<f32>>> {
-// }
-
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use axum::{Json, Router, http::StatusCode, routing::post};
+    use serde_json::json;
+
+    async fn spawn_mock() -> String {
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let app = Router::new()
+            .route(
+                "/flaky",
+                post(move || async move {
+                    // Rejects the first request, as a provider over its rate limit would.
+                    if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return Err(StatusCode::TOO_MANY_REQUESTS);
+                    }
+                    Ok(Json(json!({
+                        "data": [{ "index": 0, "embedding": [0.5, 0.25] }],
+                        "usage": { "prompt_tokens": 3, "total_tokens": 3 },
+                    })))
+                }),
+            )
+            .route(
+                "/denied",
+                post(|| async { (StatusCode::UNAUTHORIZED, "invalid api key") }),
+            )
+            .route("/empty", post(|| async { Json(json!({ "data": [] })) }));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Should bind to a local port");
+        let addr = listener.local_addr().expect("Should have a local address");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn embed_single_retries_and_types_its_errors() {
+        let base = spawn_mock().await;
+        let client = Client::new();
+
+        let flaky = OpenAIClient::new("token".to_owned(), format!("{base}/flaky"));
+        let embedding = flaky
+            .embed_single("hola", &client)
+            .await
+            .expect("Should succeed after being rate limited once");
+        assert_eq!(embedding, vec![0.5, 0.25]);
+        assert_eq!(flaky.usage().snapshot().prompt_tokens, 3);
+
+        let denied = OpenAIClient::new("token".to_owned(), format!("{base}/denied"));
+        assert!(matches!(
+            denied.embed_single("hola", &client).await,
+            Err(EmbeddingError::Unauthorized(body)) if body == "invalid api key"
+        ));
+
+        let empty = OpenAIClient::new("token".to_owned(), format!("{base}/empty"));
+        assert!(matches!(
+            empty.embed_single("hola", &client).await,
+            Err(EmbeddingError::EmptyResponse)
+        ));
+    }
+
+    #[tokio::test]
+    async fn retries_give_up_at_the_deadline() {
+        let policy = RetryPolicy {
+            deadline: Some(Duration::from_millis(20)),
+            ..QUERY_RETRY
+        };
+
+        let slow = policy.bound(async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        });
+        assert!(matches!(slow.await, Err(EmbeddingError::Timeout)));
+
+        let fast = policy.bound(async { Ok(1) });
+        assert_eq!(fast.await.unwrap(), 1);
+
+        let unbounded = RetryPolicy {
+            deadline: None,
+            ..QUERY_RETRY
+        };
+        let failed = unbounded.bound(async { Err::<(), _>(EmbeddingError::RateLimit) });
+        assert!(matches!(failed.await, Err(EmbeddingError::RateLimit)));
+    }
 
-    // #[test]
-    // fn it_works() {
-    //     let result = add(2, 2);
-    //     assert_eq!(result, 4);
-    // }
+    #[test]
+    fn embeddings_are_placed_by_their_index() {
+        let ordered = order_by_index(3, vec![(2, vec![2.0]), (0, vec![0.0]), (1, vec![1.0])])
+            .expect("Should order a complete response");
+        assert_eq!(ordered, vec![vec![0.0], vec![1.0], vec![2.0]]);
+
+        assert!(matches!(
+            order_by_index(2, vec![(0, vec![0.0])]),
+            Err(EmbeddingError::CountMismatch {
+                expected: 2,
+                received: 1
+            })
+        ));
+        assert!(matches!(
+            order_by_index(2, vec![(0, vec![0.0]), (0, vec![1.0])]),
+            Err(EmbeddingError::DuplicateIndex(0))
+        ));
+        assert!(matches!(
+            order_by_index(2, vec![(0, vec![0.0]), (5, vec![1.0])]),
+            Err(EmbeddingError::IndexOutOfRange { index: 5, .. })
+        ));
+    }
 }
@@ -0,0 +1,345 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::{StatusCode, header::HeaderMap};
+use serde::Deserialize;
+use tokio::sync::Notify;
+use tracing::debug;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Client side limits, used when the provider doesn't send `x-ratelimit-*` headers.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimits {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+    /// Upper bound for the amount of requests in flight.
+    pub max_concurrency: usize,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: None,
+            tokens_per_minute: None,
+            max_concurrency: 6,
+        }
+    }
+}
+
+/// Paces the requests to a provider, reading its `x-ratelimit-*` headers and resizing the
+/// amount of requests in flight as it goes.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    state: Mutex<State>,
+    released: Notify,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Requests sent in the last minute, with the tokens of each one.
+    window: VecDeque<(Instant, usize)>,
+    remaining_requests: Option<u64>,
+    remaining_tokens: Option<u64>,
+    requests_reset_at: Option<Instant>,
+    tokens_reset_at: Option<Instant>,
+    concurrency: usize,
+    in_flight: usize,
+}
+
+/// What a request has to wait for before being sent.
+enum Wait {
+    /// One of the requests in flight to finish.
+    Slot,
+    For(Duration),
+}
+
+/// A slot to send a request. It's given back once dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    limiter: &'a RateLimiter,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().expect("Should not be poisoned");
+        state.in_flight = state.in_flight.saturating_sub(1);
+        drop(state);
+        self.limiter.released.notify_waiters();
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimits::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        let concurrency = limits.max_concurrency.max(1);
+        Self {
+            limits,
+            state: Mutex::new(State {
+                window: VecDeque::new(),
+                remaining_requests: None,
+                remaining_tokens: None,
+                requests_reset_at: None,
+                tokens_reset_at: None,
+                concurrency,
+                in_flight: 0,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Requests allowed in flight at most, whatever the headers say.
+    pub fn max_concurrency(&self) -> usize {
+        self.limits.max_concurrency.max(1)
+    }
+
+    /// Current amount of requests allowed in flight.
+    pub fn concurrency(&self) -> usize {
+        self.state
+            .lock()
+            .expect("Should not be poisoned")
+            .concurrency
+    }
+
+    /// Waits until a request of `tokens` can be sent.
+    pub async fn acquire(&self, tokens: usize) -> Permit<'_> {
+        loop {
+            let released = self.released.notified();
+
+            let wait = {
+                let mut state = self.state.lock().expect("Should not be poisoned");
+                let now = Instant::now();
+
+                match self.wait_time(&mut state, now, tokens) {
+                    None => {
+                        state.in_flight += 1;
+                        state.window.push_back((now, tokens));
+                        if let Some(remaining) = state.remaining_requests.as_mut() {
+                            *remaining = remaining.saturating_sub(1);
+                        }
+                        if let Some(remaining) = state.remaining_tokens.as_mut() {
+                            *remaining = remaining.saturating_sub(tokens as u64);
+                        }
+                        return Permit { limiter: self };
+                    }
+                    Some(wait) => wait,
+                }
+            };
+
+            match wait {
+                Wait::For(wait) => {
+                    debug!(wait_ms = wait.as_millis(), "waiting for the rate limit");
+                    tokio::time::sleep(wait).await;
+                }
+                Wait::Slot => released.await,
+            }
+        }
+    }
+
+    /// Returns `None` if the request can go now, or what to wait for otherwise.
+    fn wait_time(&self, state: &mut State, now: Instant, tokens: usize) -> Option<Wait> {
+        while let Some((sent, _)) = state.window.front() {
+            if now.duration_since(*sent) < WINDOW {
+                break;
+            }
+            state.window.pop_front();
+        }
+
+        if state.in_flight >= state.concurrency {
+            return Some(Wait::Slot);
+        }
+
+        let until = |instant: Option<Instant>| {
+            instant
+                .filter(|at| *at > now)
+                .map(|at| at.duration_since(now))
+        };
+
+        match state.remaining_requests {
+            Some(0) => {
+                if let Some(wait) = until(state.requests_reset_at) {
+                    return Some(Wait::For(wait));
+                }
+            }
+            Some(_) => {}
+            None => {
+                if let Some(rpm) = self.limits.requests_per_minute
+                    && state.window.len() >= rpm as usize
+                {
+                    return Some(Wait::For(self.window_wait(state, now)));
+                }
+            }
+        }
+
+        match state.remaining_tokens {
+            Some(remaining) => {
+                if remaining < tokens as u64
+                    && let Some(wait) = until(state.tokens_reset_at)
+                {
+                    return Some(Wait::For(wait));
+                }
+            }
+            None => {
+                if let Some(tpm) = self.limits.tokens_per_minute {
+                    let used: usize = state.window.iter().map(|(_, tokens)| tokens).sum();
+                    if !state.window.is_empty() && used + tokens > tpm as usize {
+                        return Some(Wait::For(self.window_wait(state, now)));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn window_wait(&self, state: &State, now: Instant) -> Duration {
+        state
+            .window
+            .front()
+            .map(|(sent, _)| (*sent + WINDOW).saturating_duration_since(now))
+            .unwrap_or_default()
+    }
+
+    /// Updates the limits with the headers of a response, shrinking the concurrency after a
+    /// `429` and growing it back while there's room left.
+    pub fn observe(&self, status: StatusCode, headers: &HeaderMap) {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("Should not be poisoned");
+
+        if let Some(remaining) = header_u64(headers, "x-ratelimit-remaining-requests") {
+            state.remaining_requests = Some(remaining);
+        }
+        if let Some(remaining) = header_u64(headers, "x-ratelimit-remaining-tokens") {
+            state.remaining_tokens = Some(remaining);
+        }
+        if let Some(reset) = header_duration(headers, "x-ratelimit-reset-requests") {
+            state.requests_reset_at = Some(now + reset);
+        }
+        if let Some(reset) = header_duration(headers, "x-ratelimit-reset-tokens") {
+            state.tokens_reset_at = Some(now + reset);
+        }
+
+        let max = self.limits.max_concurrency.max(1);
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            state.concurrency = (state.concurrency / 2).max(1);
+        } else if status.is_success() {
+            let room = state
+                .remaining_requests
+                .is_none_or(|remaining| remaining > 2 * state.concurrency as u64);
+            if room && state.concurrency < max {
+                state.concurrency += 1;
+            }
+        }
+
+        debug!(
+            concurrency = state.concurrency,
+            remaining_requests = ?state.remaining_requests,
+            remaining_tokens = ?state.remaining_tokens,
+            "rate limit updated"
+        );
+        drop(state);
+        self.released.notify_waiters();
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+fn header_duration(headers: &HeaderMap, name: &str) -> Option<Duration> {
+    parse_reset(headers.get(name)?.to_str().ok()?)
+}
+
+/// Parses the reset times sent by OpenAI, e.g. `1s`, `6m0s` or `20ms`.
+fn parse_reset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Some(Duration::from_secs_f64(seconds.max(0.0)));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let number: f64 = number.parse().ok()?;
+
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let seconds = match unit {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => return None,
+        };
+
+        total += Duration::from_secs_f64(seconds);
+        rest = tail;
+    }
+
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_reset_durations() {
+        assert_eq!(parse_reset("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_reset("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset("2"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_reset("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn adapts_to_the_headers() {
+        let limiter = RateLimiter::new(RateLimits {
+            max_concurrency: 4,
+            ..Default::default()
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining-requests", "100".parse().unwrap());
+        headers.insert("x-ratelimit-reset-requests", "1s".parse().unwrap());
+
+        limiter.observe(StatusCode::TOO_MANY_REQUESTS, &headers);
+        assert_eq!(limiter.concurrency(), 2);
+
+        limiter.observe(StatusCode::OK, &headers);
+        assert_eq!(limiter.concurrency(), 3);
+
+        let first = limiter.acquire(10).await;
+        let second = limiter.acquire(10).await;
+        let third = limiter.acquire(10).await;
+
+        // Every slot is taken, so the next request has to wait for one to be released.
+        let waiting = tokio::time::timeout(Duration::from_millis(50), limiter.acquire(10)).await;
+        assert!(waiting.is_err());
+
+        drop(first);
+        let fourth = tokio::time::timeout(Duration::from_millis(50), limiter.acquire(10)).await;
+        assert!(fourth.is_ok());
+
+        drop((second, third, fourth));
+    }
+}
@@ -0,0 +1,170 @@
+use std::{sync::Arc, time::Instant};
+
+use eyre::Result;
+use reqwest::Client;
+use tokio::sync::mpsc::Sender;
+use tracing::instrument;
+
+use crate::{
+    EmbeddingError, EmbeddingProvider, Embeddings, UsageCounter,
+    embedding_message::EmbeddingMessage,
+};
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Offline provider that hashes token n-grams into a fixed size vector.
+///
+/// The vectors carry no meaning beyond lexical overlap, but they are deterministic, so they're
+/// good enough for demos and tests that shouldn't depend on the network.
+#[derive(Debug, Clone)]
+pub struct LocalHashClient {
+    pub dimensions: usize,
+    pub usage: Arc<UsageCounter>,
+}
+
+impl LocalHashClient {
+    pub fn new(dimensions: usize) -> Self {
+        Self {
+            dimensions,
+            usage: Arc::default(),
+        }
+    }
+
+    /// Embeds `input` into an L2-normalized vector of `dimensions` entries.
+    pub fn embed(&self, input: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dimensions];
+        if self.dimensions == 0 {
+            return vector;
+        }
+
+        let lowercase = input.to_lowercase();
+        let tokens: Vec<&str> = lowercase
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        let mut features: Vec<String> = Vec::new();
+        for token in &tokens {
+            features.push(format!("w:{token}"));
+
+            let chars: Vec<char> = format!("<{token}>").chars().collect();
+            for trigram in chars.windows(3) {
+                features.push(format!("c:{}", trigram.iter().collect::<String>()));
+            }
+        }
+        for pair in tokens.windows(2) {
+            features.push(format!("b:{} {}", pair[0], pair[1]));
+        }
+
+        if features.is_empty() {
+            features.push(String::new());
+        }
+
+        for feature in features {
+            let hash = fnv1a(feature.as_bytes());
+            let slot = (hash % self.dimensions as u64) as usize;
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[slot] += sign;
+        }
+
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+
+        vector
+    }
+}
+
+impl LocalHashClient {
+    fn count_tokens(&self, input: &[String]) -> u64 {
+        let estimator = self.token_estimator();
+        input.iter().map(|text| estimator.count(text) as u64).sum()
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+impl EmbeddingProvider for LocalHashClient {
+    async fn embed_vec_with_progress(
+        &self,
+        indices: Vec<u64>,
+        input: Vec<String>,
+        _client: &Client,
+        _proc_id: usize,
+        _base_delay: u64,
+        tx: Sender<EmbeddingMessage>,
+    ) -> Result<(Embeddings, u128)> {
+        let start = Instant::now();
+
+        let _ = tx
+            .send(EmbeddingMessage::Preparing { count: input.len() })
+            .await;
+        let _ = tx.send(EmbeddingMessage::ProcessingEmbeddings).await;
+
+        let embeddings =
+            std::iter::zip(indices, input.iter().map(|text| self.embed(text))).collect();
+        self.usage.record(self.count_tokens(&input));
+
+        let total_elapsed = start.elapsed().as_millis();
+        let _ = tx
+            .send(EmbeddingMessage::Complete {
+                total_elapsed_ms: total_elapsed,
+            })
+            .await;
+
+        Ok((embeddings, total_elapsed))
+    }
+
+    #[instrument(name = "embed.local", skip(self, input, _client), fields(input_len = input.len()))]
+    async fn embed_single(
+        &self,
+        input: &str,
+        _client: &Client,
+    ) -> Result<Vec<f32>, EmbeddingError> {
+        self.usage
+            .record(self.count_tokens(std::slice::from_ref(&input.to_owned())));
+        Ok(self.embed(input))
+    }
+
+    fn max_input_tokens(&self) -> usize {
+        usize::MAX
+    }
+
+    fn model(&self) -> &str {
+        "local-hash"
+    }
+
+    fn usage(&self) -> &UsageCounter {
+        &self.usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_hash_is_deterministic_and_normalized() {
+        let client = LocalHashClient::new(64);
+
+        let a = client.embed("Hola mundo");
+        let b = client.embed("hola, MUNDO");
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 64);
+
+        let norm = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+
+        let c = client.embed("otra cosa distinta");
+        assert_ne!(a, c);
+
+        let empty = client.embed("");
+        assert!((empty.iter().map(|v| v * v).sum::<f32>().sqrt() - 1.0).abs() < 1e-5);
+    }
+}
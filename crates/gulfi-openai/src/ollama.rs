@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use eyre::Result;
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+use tracing::instrument;
+
+use crate::{
+    EmbeddingError, EmbeddingProvider, Embeddings, HttpBackend, ParsedResponse, RateLimiter,
+    RateLimits, UsageCounter, embed_batch, embed_one, embedding_message::EmbeddingMessage,
+};
+
+/// Client for Ollama's `/api/embed` endpoint.
+#[derive(Debug, Clone)]
+pub struct OllamaClient {
+    pub endpoint_url: String,
+    pub model: String,
+    pub limiter: Arc<RateLimiter>,
+    pub usage: Arc<UsageCounter>,
+}
+
+impl OllamaClient {
+    pub fn new(endpoint_url: String, model: String) -> Self {
+        Self {
+            endpoint_url,
+            model,
+            limiter: Arc::default(),
+            usage: Arc::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.limiter = Arc::new(RateLimiter::new(limits));
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct RequestBody<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct ResponseBody {
+    embeddings: Vec<Vec<f32>>,
+    prompt_eval_count: Option<u64>,
+}
+
+impl HttpBackend for OllamaClient {
+    fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    fn build_request(&self, client: &Client, input: &[String]) -> RequestBuilder {
+        client.post(&self.endpoint_url).json(&RequestBody {
+            model: &self.model,
+            input,
+        })
+    }
+
+    // Ollama doesn't report an index, the embeddings come in the same order as the input.
+    fn parse_response(&self, payload: &mut [u8]) -> Result<ParsedResponse> {
+        let response: ResponseBody = simd_json::serde::from_slice(payload)?;
+        Ok(ParsedResponse {
+            embeddings: response.embeddings.into_iter().enumerate().collect(),
+            prompt_tokens: response.prompt_eval_count,
+        })
+    }
+}
+
+impl EmbeddingProvider for OllamaClient {
+    async fn embed_vec_with_progress(
+        &self,
+        indices: Vec<u64>,
+        input: Vec<String>,
+        client: &Client,
+        proc_id: usize,
+        base_delay: u64,
+        tx: Sender<EmbeddingMessage>,
+    ) -> Result<(Embeddings, u128)> {
+        embed_batch(self, indices, input, client, proc_id, base_delay, tx).await
+    }
+
+    #[instrument(name = "embed.request", skip(self, input, client), fields(url = %self.endpoint_url, input_len = input.len()))]
+    async fn embed_single(&self, input: &str, client: &Client) -> Result<Vec<f32>, EmbeddingError> {
+        embed_one(self, input, client).await
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn usage(&self) -> &UsageCounter {
+        &self.usage
+    }
+
+    fn max_concurrency(&self) -> usize {
+        self.limiter.max_concurrency()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+    use serde_json::{Value, json};
+
+    #[test]
+    fn builds_the_body_with_the_model() {
+        let client = OllamaClient::new("http://localhost/api/embed".to_owned(), "nomic".to_owned());
+
+        let input = vec!["hola".to_owned()];
+        let request = client
+            .build_request(&Client::new(), &input)
+            .build()
+            .expect("Should build the request");
+        let body: Value = serde_json::from_slice(
+            request
+                .body()
+                .and_then(reqwest::Body::as_bytes)
+                .expect("Should have a JSON body"),
+        )
+        .unwrap();
+
+        assert_eq!(request.url().as_str(), "http://localhost/api/embed");
+        assert_eq!(body, json!({ "model": "nomic", "input": ["hola"] }));
+    }
+
+    #[test]
+    fn parses_the_embeddings_in_order() {
+        let client = OllamaClient::new(String::new(), String::new());
+
+        let mut payload =
+            br#"{"model": "nomic", "embeddings": [[0.5], [1.0]], "prompt_eval_count": 4}"#.to_vec();
+        let parsed = client.parse_response(&mut payload).unwrap();
+        assert_eq!(parsed.embeddings, vec![(0, vec![0.5]), (1, vec![1.0])]);
+        assert_eq!(parsed.prompt_tokens, Some(4));
+
+        let mut payload = br#"{"error": "model not found"}"#.to_vec();
+        assert!(client.parse_response(&mut payload).is_err());
+    }
+
+    #[tokio::test]
+    async fn embed_single_reads_the_response() {
+        let app = Router::new().route(
+            "/api/embed",
+            post(|Json(body): Json<Value>| async move {
+                assert_eq!(body["input"], json!(["hola"]));
+                Json(json!({ "embeddings": [[0.5, 0.25]], "prompt_eval_count": 2 }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Should bind to a local port");
+        let addr = listener.local_addr().expect("Should have a local address");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = OllamaClient::new(format!("http://{addr}/api/embed"), "nomic".to_owned());
+        let embedding = client
+            .embed_single("hola", &Client::new())
+            .await
+            .expect("Should embed the input");
+
+        assert_eq!(embedding, vec![0.5, 0.25]);
+        assert_eq!(client.usage().snapshot().prompt_tokens, 2);
+    }
+}
@@ -0,0 +1,63 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// Placeholder replaced by the text to embed.
+const TEXT_PLACEHOLDER: &str = "{text}";
+
+/// Instructions some models expect around their input, e.g. `query: ` and `passage: ` for e5 or
+/// `search_query: ` and `search_document: ` for nomic.
+///
+/// A template containing `{text}` is filled in, any other template is used as a prefix.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PromptTemplates {
+    /// Applied to the search queries.
+    pub query: Option<String>,
+    /// Applied to the `vec_input` of every row when syncing.
+    pub document: Option<String>,
+}
+
+impl PromptTemplates {
+    pub fn apply_query<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        apply(self.query.as_deref(), text)
+    }
+
+    pub fn apply_document<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        apply(self.document.as_deref(), text)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.query.is_none() && self.document.is_none()
+    }
+}
+
+fn apply<'a>(template: Option<&str>, text: &'a str) -> Cow<'a, str> {
+    match template {
+        None => Cow::Borrowed(text),
+        Some(template) if template.contains(TEXT_PLACEHOLDER) => {
+            Cow::Owned(template.replace(TEXT_PLACEHOLDER, text))
+        }
+        Some(prefix) => Cow::Owned(format!("{prefix}{text}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_are_filled_or_prepended() {
+        let prompts = PromptTemplates {
+            query: Some("Instruct: find the author\nQuery: {text}".to_owned()),
+            document: Some("passage: ".to_owned()),
+        };
+
+        assert_eq!(
+            prompts.apply_query("hola"),
+            "Instruct: find the author\nQuery: hola"
+        );
+        assert_eq!(prompts.apply_document("hola"), "passage: hola");
+        assert_eq!(PromptTemplates::default().apply_query("hola"), "hola");
+    }
+}
@@ -0,0 +1,162 @@
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{EmbeddingError, QUERY_TIMEOUT, error_body};
+
+/// Client for a Cohere or Jina compatible `/rerank` endpoint, which scores each document
+/// against the query with a cross-encoder.
+#[derive(Debug, Clone)]
+pub struct RerankClient {
+    pub endpoint_url: String,
+    pub auth_token: SecretString,
+    pub model: String,
+}
+
+#[derive(Serialize)]
+struct RequestBody<'a> {
+    model: &'a str,
+    query: &'a str,
+    documents: &'a [String],
+    top_n: usize,
+}
+
+#[derive(Deserialize)]
+struct ResponseBody {
+    results: Vec<RerankResult>,
+}
+
+#[derive(Deserialize)]
+struct RerankResult {
+    index: usize,
+    relevance_score: f32,
+}
+
+impl RerankClient {
+    pub fn new(endpoint_url: String, auth_token: SecretString, model: String) -> Self {
+        Self {
+            endpoint_url,
+            auth_token,
+            model,
+        }
+    }
+
+    /// Scores every document against `query`. Returns the position of each document in
+    /// `documents` with its relevance, the most relevant first.
+    #[instrument(name = "rerank.request", skip(self, query, documents, client), fields(url = %self.endpoint_url, documents = documents.len()))]
+    pub async fn rerank(
+        &self,
+        query: &str,
+        documents: &[String],
+        client: &Client,
+    ) -> Result<Vec<(usize, f32)>, EmbeddingError> {
+        let mut request =
+            client
+                .post(&self.endpoint_url)
+                .timeout(QUERY_TIMEOUT)
+                .json(&RequestBody {
+                    model: &self.model,
+                    query,
+                    documents,
+                    top_n: documents.len(),
+                });
+
+        let token = self.auth_token.expose_secret();
+        if !token.is_empty() {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+
+        match status.as_u16() {
+            200..299 => {}
+            401 | 403 => return Err(EmbeddingError::Unauthorized(error_body(response).await)),
+            400 | 404 | 413 | 422 => {
+                return Err(EmbeddingError::BadRequest(error_body(response).await));
+            }
+            429 => return Err(EmbeddingError::RateLimit),
+            _ => {
+                let error = response
+                    .error_for_status_ref()
+                    .expect_err("the status is an error");
+                let msg = format!("{status} -> {}", error_body(response).await);
+                return Err(EmbeddingError::RequestError(error, msg));
+            }
+        }
+
+        let body: ResponseBody = response
+            .json()
+            .await
+            .map_err(|err| EmbeddingError::InvalidResponse(err.to_string()))?;
+
+        let mut scores = Vec::with_capacity(body.results.len());
+        for result in body.results {
+            if result.index >= documents.len() {
+                return Err(EmbeddingError::IndexOutOfRange {
+                    index: result.index,
+                    expected: documents.len(),
+                });
+            }
+            scores.push((result.index, result.relevance_score));
+        }
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        Ok(scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+    use serde_json::{Value, json};
+
+    #[tokio::test]
+    async fn rerank_orders_by_relevance() {
+        // Scores each document by how many times it mentions the query.
+        let app = Router::new().route(
+            "/rerank",
+            post(|Json(body): Json<Value>| async move {
+                let query = body["query"].as_str().unwrap_or_default().to_owned();
+                let results: Vec<Value> = body["documents"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(index, doc)| {
+                        let score = doc.as_str().unwrap_or_default().matches(&query).count();
+                        json!({ "index": index, "relevance_score": score as f32 / 10.0 })
+                    })
+                    .collect();
+                Json(json!({ "results": results }))
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Should bind to a local port");
+        let addr = listener.local_addr().expect("Should have a local address");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let reranker = RerankClient::new(
+            format!("http://{addr}/rerank"),
+            SecretString::new("".into()),
+            "rerank-mock".to_owned(),
+        );
+        let documents = vec![
+            "rust".to_owned(),
+            "python".to_owned(),
+            "rust and more rust".to_owned(),
+        ];
+
+        let scores = reranker
+            .rerank("rust", &documents, &Client::new())
+            .await
+            .expect("Should rerank the documents");
+
+        let order: Vec<usize> = scores.iter().map(|(index, _)| *index).collect();
+        assert_eq!(order, vec![2, 0, 1]);
+    }
+}
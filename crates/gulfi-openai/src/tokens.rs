@@ -0,0 +1,119 @@
+use tiktoken_rs::{CoreBPE, Rank};
+
+/// Rough amount of characters per token used when no tokenizer is known for the model.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Estimates how many tokens a provider will charge for an input.
+#[derive(Clone, Copy)]
+pub enum TokenEstimator {
+    /// Exact count using the BPE of OpenAI's embedding models (`cl100k_base`).
+    Bpe(&'static CoreBPE),
+    /// Character based approximation, for models with an unknown tokenizer.
+    Heuristic,
+}
+
+impl std::fmt::Debug for TokenEstimator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bpe(_) => write!(f, "Bpe(cl100k_base)"),
+            Self::Heuristic => write!(f, "Heuristic"),
+        }
+    }
+}
+
+impl TokenEstimator {
+    /// Picks the estimator for `model`, falling back to the heuristic one.
+    pub fn for_model(model: &str) -> Self {
+        if model.starts_with("text-embedding-") {
+            Self::Bpe(tiktoken_rs::cl100k_base_singleton())
+        } else {
+            Self::Heuristic
+        }
+    }
+
+    pub fn count(&self, input: &str) -> usize {
+        match self {
+            Self::Bpe(bpe) => bpe.encode_ordinary(input).len(),
+            Self::Heuristic => input.chars().count().div_ceil(CHARS_PER_TOKEN),
+        }
+    }
+
+    /// Keeps the first `max_tokens` tokens of `input`.
+    pub fn truncate(&self, input: &str, max_tokens: usize) -> String {
+        self.split(input, max_tokens)
+            .into_iter()
+            .next()
+            .unwrap_or_default()
+    }
+
+    /// Splits `input` into consecutive pieces of at most `max_tokens` tokens.
+    pub fn split(&self, input: &str, max_tokens: usize) -> Vec<String> {
+        let max_tokens = max_tokens.max(1);
+
+        match self {
+            Self::Bpe(bpe) => {
+                let tokens = bpe.encode_ordinary(input);
+                let mut pieces = Vec::new();
+                let mut start = 0;
+
+                while start < tokens.len() {
+                    let (piece, end) = decode_prefix(bpe, &tokens[start..], max_tokens);
+                    pieces.push(piece);
+                    start += end;
+                }
+
+                pieces
+            }
+            Self::Heuristic => {
+                let chars: Vec<char> = input.chars().collect();
+                chars
+                    .chunks(max_tokens * CHARS_PER_TOKEN)
+                    .map(|chunk| chunk.iter().collect())
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Decodes the longest prefix of at most `max_tokens` tokens that is valid UTF-8, returning it
+/// with the amount of tokens consumed.
+fn decode_prefix(bpe: &CoreBPE, tokens: &[Rank], max_tokens: usize) -> (String, usize) {
+    let mut end = tokens.len().min(max_tokens);
+
+    // A multibyte character can span several tokens, so the cut may land in the middle of one.
+    while end > 1 {
+        if let Ok(piece) = bpe.decode(tokens[..end].to_vec()) {
+            return (piece, end);
+        }
+        end -= 1;
+    }
+
+    let piece = bpe.decode(tokens[..1].to_vec()).unwrap_or_default();
+    (piece, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_and_truncates_under_the_limit() {
+        for estimator in [
+            TokenEstimator::for_model("text-embedding-3-small"),
+            TokenEstimator::Heuristic,
+        ] {
+            let input = "el veloz murciélago hindú comía feliz cardillo y kiwi ".repeat(20);
+            let total = estimator.count(&input);
+            assert!(total > 16, "{estimator:?}");
+
+            let pieces = estimator.split(&input, 16);
+            assert!(pieces.len() > 1, "{estimator:?}");
+            assert!(pieces.iter().all(|p| estimator.count(p) <= 16));
+            assert_eq!(pieces.concat(), input, "{estimator:?}");
+
+            let truncated = estimator.truncate(&input, 16);
+            assert!(input.starts_with(&truncated));
+            assert!(estimator.count(&truncated) <= 16);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Tokens billed by a provider over a number of requests.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+}
+
+/// Accumulates the usage reported by every response of a client.
+#[derive(Debug, Default)]
+pub struct UsageCounter {
+    requests: AtomicU64,
+    prompt_tokens: AtomicU64,
+}
+
+impl UsageCounter {
+    pub fn record(&self, prompt_tokens: u64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.prompt_tokens
+            .fetch_add(prompt_tokens, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TokenUsage {
+        TokenUsage {
+            requests: self.requests.load(Ordering::Relaxed),
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the usage accumulated so far and starts counting from zero again.
+    pub fn take(&self) -> TokenUsage {
+        TokenUsage {
+            requests: self.requests.swap(0, Ordering::Relaxed),
+            prompt_tokens: self.prompt_tokens.swap(0, Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum FtsSyntaxError {
+    #[error("The search has no terms.")]
+    Empty,
+    #[error("No term after '{0}'")]
+    MissingTerm(String),
+    #[error("A quote isn't closed.")]
+    UnclosedQuote,
+    #[error("The parentheses aren't balanced.")]
+    UnbalancedParens,
+    #[error("'{0}' isn't a column of the document that can be searched.")]
+    UnknownColumn(String),
+    #[error("The search only excludes terms, it needs at least one to look for.")]
+    OnlyExclusions,
+    #[error("Invalid NEAR group: {0}")]
+    InvalidNear(String),
+}
+
+/// Matches `input` as a single phrase in `column`.
+pub fn phrase(input: &str, column: &str) -> String {
+    format!("{column} : {}", quote(input))
+}
+
+/// Translates the search syntax of the users into an FTS5 `MATCH` expression.
+///
+/// Terms are matched in `default_column` unless they're prefixed by one of `columns`, as in
+/// `provincia:mendoza`. Terms can be combined with `AND` (or just spaces), `OR` and `NOT`
+/// (or a leading `-`), grouped with parentheses, quoted as phrases, matched as prefixes with a
+/// trailing `*`, and searched close to each other with `NEAR(a b)` or `NEAR/5(a b)`.
+///
+/// Every term is quoted in the output, so nothing in `input` is read as FTS5 syntax.
+pub fn translate(
+    input: &str,
+    default_column: &str,
+    columns: &[&str],
+) -> Result<String, FtsSyntaxError> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        columns,
+    };
+
+    let expr = parser.parse_or()?.ok_or(FtsSyntaxError::Empty)?;
+
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(FtsSyntaxError::UnbalancedParens);
+    }
+
+    let mut result = String::new();
+    expr.render(default_column, &mut result);
+    Ok(result)
+}
+
+/// Matches, in `column`, at least one of the terms of every group.
+pub fn any_of(groups: &[Vec<String>], column: &str) -> String {
+    let term = |text: &String| Expr::Term {
+        column: None,
+        text: text.clone(),
+        prefix: false,
+    };
+    let expr = Expr::And(
+        groups
+            .iter()
+            .map(|group| Expr::Or(group.iter().map(term).collect()))
+            .collect(),
+    );
+
+    let mut result = String::new();
+    expr.render(column, &mut result);
+    result
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+#[derive(Debug)]
+enum Expr {
+    Term {
+        column: Option<String>,
+        text: String,
+        prefix: bool,
+    },
+    Near {
+        column: Option<String>,
+        terms: Vec<(String, bool)>,
+        distance: Option<u32>,
+    },
+    Group {
+        column: Option<String>,
+        expr: Box<Expr>,
+    },
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn with_column(self, name: String) -> Self {
+        match self {
+            Expr::Term { text, prefix, .. } => Expr::Term {
+                column: Some(name),
+                text,
+                prefix,
+            },
+            Expr::Near {
+                terms, distance, ..
+            } => Expr::Near {
+                column: Some(name),
+                terms,
+                distance,
+            },
+            Expr::Group { expr, .. } => Expr::Group {
+                column: Some(name),
+                expr,
+            },
+            other => other,
+        }
+    }
+
+    /// Writes the expression with every term qualified by its column, as an FTS5 column filter
+    /// inside another one only matches the columns both have in common.
+    fn render(&self, column: &str, out: &mut String) {
+        let join = |exprs: &[Expr], op: &str, out: &mut String| {
+            out.push('(');
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(op);
+                }
+                expr.render(column, out);
+            }
+            out.push(')');
+        };
+
+        match self {
+            Expr::Term {
+                column: own,
+                text,
+                prefix,
+            } => {
+                let column = own.as_deref().unwrap_or(column);
+                out.push_str(&format!("{column} : {}", quote(text)));
+                if *prefix {
+                    out.push('*');
+                }
+            }
+            Expr::Near {
+                column: own,
+                terms,
+                distance,
+            } => {
+                let column = own.as_deref().unwrap_or(column);
+                let terms: Vec<String> = terms
+                    .iter()
+                    .map(|(text, prefix)| {
+                        let star = if *prefix { "*" } else { "" };
+                        format!("{}{star}", quote(text))
+                    })
+                    .collect();
+                out.push_str(&format!("{column} : NEAR({}", terms.join(" ")));
+                if let Some(distance) = distance {
+                    out.push_str(&format!(", {distance}"));
+                }
+                out.push(')');
+            }
+            Expr::Group { column: own, expr } => {
+                expr.render(own.as_deref().unwrap_or(column), out);
+            }
+            Expr::And(exprs) => join(exprs, " AND ", out),
+            Expr::Or(exprs) => join(exprs, " OR ", out),
+            Expr::Not(expr, excluded) => {
+                out.push('(');
+                expr.render(column, out);
+                out.push_str(" NOT ");
+                excluded.render(column, out);
+                out.push(')');
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    columns: &'a [&'a str],
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// The word at the current position, without consuming it.
+    fn peek_word(&self) -> String {
+        self.chars[self.pos..]
+            .iter()
+            .take_while(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '"'))
+            .collect()
+    }
+
+    /// Consumes `keyword` if it's the next word.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        if self.peek_word() == keyword {
+            self.pos += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Option<Expr>, FtsSyntaxError> {
+        let Some(first) = self.parse_and()? else {
+            if self.eat_keyword("OR") {
+                return Err(FtsSyntaxError::MissingTerm("OR".to_owned()));
+            }
+            return Ok(None);
+        };
+
+        let mut exprs = vec![first];
+        while self.eat_keyword("OR") {
+            let expr = self
+                .parse_and()?
+                .ok_or_else(|| FtsSyntaxError::MissingTerm("OR".to_owned()))?;
+            exprs.push(expr);
+        }
+
+        Ok(Some(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            Expr::Or(exprs)
+        }))
+    }
+
+    /// Terms next to each other, or joined by `AND`, must all match. The ones preceded by
+    /// `NOT` or `-` must not.
+    fn parse_and(&mut self) -> Result<Option<Expr>, FtsSyntaxError> {
+        let mut included = Vec::new();
+        let mut excluded = Vec::new();
+
+        // The operator waiting for its term, and whether that term is excluded.
+        let mut operator: Option<String> = None;
+        let mut negated = false;
+
+        loop {
+            self.skip_whitespace();
+            let word = self.peek_word();
+
+            if self.peek().is_none() || self.peek() == Some(')') || word == "OR" {
+                if let Some(operator) = operator {
+                    return Err(FtsSyntaxError::MissingTerm(operator));
+                }
+                break;
+            }
+
+            match word.as_str() {
+                "AND" if operator.is_none() => {
+                    self.pos += 3;
+                    operator = Some(word);
+                    continue;
+                }
+                "NOT" if !negated => {
+                    self.pos += 3;
+                    operator = Some(word);
+                    negated = true;
+                    continue;
+                }
+                "AND" | "NOT" => {
+                    return Err(FtsSyntaxError::MissingTerm(operator.unwrap_or(word)));
+                }
+                _ => {}
+            }
+
+            if !negated
+                && self.peek() == Some('-')
+                && self
+                    .chars
+                    .get(self.pos + 1)
+                    .is_some_and(|c| !c.is_whitespace())
+            {
+                self.pos += 1;
+                negated = true;
+            }
+
+            let expr = self.parse_primary()?;
+            if negated {
+                excluded.push(expr);
+            } else {
+                included.push(expr);
+            }
+            operator = None;
+            negated = false;
+        }
+
+        if included.is_empty() {
+            if excluded.is_empty() {
+                return Ok(None);
+            }
+            return Err(FtsSyntaxError::OnlyExclusions);
+        }
+
+        let mut expr = if included.len() == 1 {
+            included.remove(0)
+        } else {
+            Expr::And(included)
+        };
+        for excluded in excluded {
+            expr = Expr::Not(Box::new(expr), Box::new(excluded));
+        }
+
+        Ok(Some(expr))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, FtsSyntaxError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            None => Err(FtsSyntaxError::Empty),
+            Some(')') => Err(FtsSyntaxError::UnbalancedParens),
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.parse_or()?.ok_or(FtsSyntaxError::Empty)?;
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(FtsSyntaxError::UnbalancedParens);
+                }
+                self.pos += 1;
+                Ok(Expr::Group {
+                    column: None,
+                    expr: Box::new(expr),
+                })
+            }
+            Some('"') => {
+                let (text, prefix) = self.parse_phrase()?;
+                Ok(Expr::Term {
+                    column: None,
+                    text,
+                    prefix,
+                })
+            }
+            Some(_) => self.parse_word(),
+        }
+    }
+
+    fn parse_phrase(&mut self) -> Result<(String, bool), FtsSyntaxError> {
+        self.pos += 1;
+        let len = self.chars[self.pos..]
+            .iter()
+            .position(|c| *c == '"')
+            .ok_or(FtsSyntaxError::UnclosedQuote)?;
+
+        let text = self.chars[self.pos..self.pos + len].iter().collect();
+        self.pos += len + 1;
+
+        let prefix = self.peek() == Some('*');
+        if prefix {
+            self.pos += 1;
+        }
+        Ok((text, prefix))
+    }
+
+    fn parse_word(&mut self) -> Result<Expr, FtsSyntaxError> {
+        let word = self.peek_word();
+
+        if let Some((column, rest)) = word.split_once(':') {
+            if !self.columns.contains(&column) {
+                return Err(FtsSyntaxError::UnknownColumn(column.to_owned()));
+            }
+
+            self.pos += column.chars().count() + 1;
+            if rest.is_empty() && self.peek().is_none_or(char::is_whitespace) {
+                return Err(FtsSyntaxError::MissingTerm(format!("{column}:")));
+            }
+
+            return Ok(self.parse_primary()?.with_column(column.to_owned()));
+        }
+
+        if (word == "NEAR" || word.starts_with("NEAR/"))
+            && self.chars.get(self.pos + word.chars().count()) == Some(&'(')
+        {
+            return self.parse_near(&word);
+        }
+
+        self.pos += word.chars().count();
+
+        let (text, prefix) = match word.strip_suffix('*') {
+            Some(text) => (text.to_owned(), true),
+            None => (word, false),
+        };
+        if text.is_empty() {
+            return Err(FtsSyntaxError::MissingTerm("*".to_owned()));
+        }
+
+        Ok(Expr::Term {
+            column: None,
+            text,
+            prefix,
+        })
+    }
+
+    /// `NEAR(a b)`, `NEAR(a b, 5)` or `NEAR/5(a b)`.
+    fn parse_near(&mut self, word: &str) -> Result<Expr, FtsSyntaxError> {
+        let invalid = |msg: &str| FtsSyntaxError::InvalidNear(msg.to_owned());
+
+        let mut distance = match word.strip_prefix("NEAR/") {
+            Some(distance) => Some(
+                distance
+                    .parse()
+                    .map_err(|_| invalid("the distance isn't a number"))?,
+            ),
+            None => None,
+        };
+        self.pos += word.chars().count() + 1;
+
+        let mut terms = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Err(FtsSyntaxError::UnbalancedParens),
+                Some(')') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(',') => {
+                    self.pos += 1;
+                    let digits: String = self.chars[self.pos..]
+                        .iter()
+                        .take_while(|c| !matches!(c, ')'))
+                        .collect();
+                    self.pos += digits.chars().count();
+                    distance = Some(
+                        digits
+                            .trim()
+                            .parse()
+                            .map_err(|_| invalid("the distance isn't a number"))?,
+                    );
+                }
+                Some('"') => terms.push(self.parse_phrase()?),
+                Some('(') => return Err(invalid("it can only have terms")),
+                Some(_) => {
+                    let word: String = self.chars[self.pos..]
+                        .iter()
+                        .take_while(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '"' | ','))
+                        .collect();
+                    self.pos += word.chars().count();
+
+                    let (text, prefix) = match word.strip_suffix('*') {
+                        Some(text) => (text.to_owned(), true),
+                        None => (word, false),
+                    };
+                    if text.is_empty() {
+                        return Err(FtsSyntaxError::MissingTerm("*".to_owned()));
+                    }
+                    terms.push((text, prefix));
+                }
+            }
+        }
+
+        if terms.len() < 2 {
+            return Err(invalid("it needs at least two terms"));
+        }
+
+        Ok(Expr::Near {
+            column: None,
+            terms,
+            distance,
+        })
+    }
+}
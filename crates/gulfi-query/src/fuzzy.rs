@@ -0,0 +1,107 @@
+/// Splits `text` into the terms the FTS index would store: lowercase, without accents, and
+/// without repeating any of them.
+pub fn terms(text: &str) -> Vec<String> {
+    let folded: String = text
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(fold)
+        .collect();
+
+    let mut terms: Vec<String> = Vec::new();
+    for term in folded.split(|c: char| !c.is_alphanumeric()) {
+        if !term.is_empty() && !terms.iter().any(|t| t == term) {
+            terms.push(term.to_owned());
+        }
+    }
+    terms
+}
+
+/// Removes the accents the `unicode61` tokenizer removes with `remove_diacritics`, for the
+/// letters used in Spanish and Portuguese.
+fn fold(c: char) -> char {
+    match c {
+        'á' | 'à' | 'â' | 'ä' | 'ã' => 'a',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' => 'i',
+        'ó' | 'ò' | 'ô' | 'ö' | 'õ' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        'ñ' => 'n',
+        'ç' => 'c',
+        other => other,
+    }
+}
+
+/// Edits allowed for a term of `len` characters. The shorter terms have to match exactly, a
+/// single edit already turns them into many unrelated words.
+pub fn max_distance(len: usize) -> usize {
+    match len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Trigrams of `term` padded with two spaces at each end, sorted. A term of `n` characters has
+/// `n + 2` of them.
+pub fn trigrams(term: &str) -> Vec<[char; 3]> {
+    let padded: Vec<char> = "  "
+        .chars()
+        .chain(term.chars())
+        .chain("  ".chars())
+        .collect();
+
+    let mut trigrams: Vec<[char; 3]> = padded.windows(3).map(|w| [w[0], w[1], w[2]]).collect();
+    trigrams.sort_unstable();
+    trigrams
+}
+
+/// Whether two terms with the trigrams `a` and `b` can be within `max` edits. Each edit changes
+/// at most three trigrams, so it's a cheap filter before [`distance_within`].
+pub fn may_be_within(a: &[[char; 3]], b: &[[char; 3]], max: usize) -> bool {
+    let required = a.len().max(b.len()).saturating_sub(3 * max);
+
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() && shared < required {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    shared >= required
+}
+
+/// Levenshtein distance between `a` and `b`, if it's at most `max`. Gives up as soon as every
+/// alignment needs more edits.
+pub fn distance_within(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+
+        if curr.iter().all(|d| *d > max) {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    let distance = prev[b.len()];
+    (distance <= max).then_some(distance)
+}
@@ -1,6 +1,9 @@
 use std::{collections::BTreeMap, fmt::Display};
 use thiserror::Error;
 
+pub mod fts;
+pub mod fuzzy;
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Constraint {
     Exact(String),
@@ -224,18 +224,136 @@ fn fails_with_invalid_token() {
     );
 }
 
+fn check_fts(query: &str, expect: Expect) {
+    let res = fts::translate(query, "vec_input", &["provincia", "ciudad"]);
+    expect.assert_debug_eq(&res);
+}
+
+#[test]
+fn fts_terms_are_quoted_and_qualified() {
+    check_fts(
+        r#"rust "desarrollador backend" desarroll*"#,
+        expect![[r#"
+        Ok(
+            "(vec_input : \"rust\" AND vec_input : \"desarrollador backend\" AND vec_input : \"desarroll\"*)",
+        )
+    "#]],
+    );
+}
+
+#[test]
+fn fts_boolean_operators() {
+    check_fts(
+        "rust OR go -java",
+        expect![[r#"
+        Ok(
+            "(vec_input : \"rust\" OR (vec_input : \"go\" NOT vec_input : \"java\"))",
+        )
+    "#]],
+    );
+    check_fts(
+        "(rust OR go) AND NOT java",
+        expect![[r#"
+            Ok(
+                "((vec_input : \"rust\" OR vec_input : \"go\") NOT vec_input : \"java\")",
+            )
+        "#]],
+    );
+}
+
+#[test]
+fn fts_near_and_columns() {
+    check_fts(
+        "NEAR/5(python django) provincia:(mendoza OR salta)",
+        expect![[r#"
+            Ok(
+                "(vec_input : NEAR(\"python\" \"django\", 5) AND (provincia : \"mendoza\" OR provincia : \"salta\"))",
+            )
+        "#]],
+    );
+    check_fts(
+        "NEAR(rust* \"web developer\", 3)",
+        expect![[r#"
+        Ok(
+            "vec_input : NEAR(\"rust\"* \"web developer\", 3)",
+        )
+    "#]],
+    );
+}
+
+#[test]
+fn fts_syntax_is_escaped() {
+    check_fts(
+        r#"rust* ^col NEAR {a} "x" -"y\""#,
+        expect![[r#"
+        Ok(
+            "((vec_input : \"rust\"* AND vec_input : \"^col\" AND vec_input : \"NEAR\" AND vec_input : \"{a}\" AND vec_input : \"x\") NOT vec_input : \"y\\\")",
+        )
+    "#]],
+    );
+    assert_eq!(
+        fts::phrase(r#"say "hi""#, "vec_input"),
+        r#"vec_input : "say ""hi""""#
+    );
+}
+
+#[test]
+fn fts_fails_on_invalid_syntax() {
+    for (query, err) in [
+        ("NOT rust", fts::FtsSyntaxError::OnlyExclusions),
+        ("(rust", fts::FtsSyntaxError::UnbalancedParens),
+        ("rust)", fts::FtsSyntaxError::UnbalancedParens),
+        ("\"rust", fts::FtsSyntaxError::UnclosedQuote),
+        (
+            "rust AND",
+            fts::FtsSyntaxError::MissingTerm("AND".to_owned()),
+        ),
+        ("rust OR", fts::FtsSyntaxError::MissingTerm("OR".to_owned())),
+        (
+            "estudios:ingenieria",
+            fts::FtsSyntaxError::UnknownColumn("estudios".to_owned()),
+        ),
+        (
+            "NEAR(rust)",
+            fts::FtsSyntaxError::InvalidNear("it needs at least two terms".to_owned()),
+        ),
+    ] {
+        assert_eq!(
+            fts::translate(query, "vec_input", &["provincia"]),
+            Err(err),
+            "{query}"
+        );
+    }
+}
+
 proptest! {
   #[test]
   fn parses_valid_query_does_not_panic(query_str in generate_valid_query()) {
       let _ = Query::parse(&query_str);
   }
 
+  #[test]
+  fn translates_any_fts_query_without_panicking(query in any::<String>()) {
+      let _ = fts::translate(&query, "vec_input", &["provincia"]);
+  }
+
   #[test]
   fn fails_gracefully_on_bad_token(bad_token in "[^,:><]+;[^,:><]+") {
       let input = format!("query: Test, {}", bad_token);
       prop_assert!(matches!(Query::parse(&input), Err(ParsingError::InvalidToken(_))));
   }
 
+  #[test]
+  fn fuzzy_trigrams_keep_every_term_within_the_distance(
+      a in "[a-cñ]{0,9}",
+      b in "[a-cñ]{0,9}",
+      max in 0usize..3,
+  ) {
+      if fuzzy::distance_within(&a, &b, max).is_some() {
+          prop_assert!(fuzzy::may_be_within(&fuzzy::trigrams(&a), &fuzzy::trigrams(&b), max));
+      }
+  }
+
 }
 
 fn generate_valid_query() -> impl Strategy<Value = String> {
@@ -251,3 +369,58 @@ fn generate_valid_query() -> impl Strategy<Value = String> {
 
     prop::collection::vec(constraint, 1..5).prop_map(|parts| parts.join(", "))
 }
+
+#[test]
+fn fuzzy_terms_are_folded_like_the_index() {
+    assert_eq!(
+        fuzzy::terms("Ingeniería, ingenieria y DISEÑO"),
+        vec!["ingenieria", "y", "diseno"]
+    );
+}
+
+#[test]
+fn fuzzy_distance_is_bounded() {
+    assert_eq!(fuzzy::distance_within("ingeniro", "ingeniero", 2), Some(1));
+    assert_eq!(fuzzy::distance_within("kitten", "sitting", 3), Some(3));
+    assert_eq!(fuzzy::distance_within("kitten", "sitting", 2), None);
+    assert_eq!(fuzzy::distance_within("rust", "rust", 0), Some(0));
+    assert_eq!(fuzzy::distance_within("rust", "python", 1), None);
+    assert_eq!(fuzzy::distance_within("", "ab", 2), Some(2));
+
+    assert_eq!(fuzzy::max_distance("sql".len()), 0);
+    assert_eq!(fuzzy::max_distance("ingeniro".len()), 2);
+}
+
+#[test]
+fn fuzzy_trigrams_never_discard_a_match() {
+    let pairs = [
+        ("ingeniro", "ingeniero", 2),
+        ("kitten", "sitting", 3),
+        ("rust", "rusty", 1),
+        ("rust", "trust", 1),
+        ("abcd", "abdc", 2),
+        ("diseno", "disenador", 2),
+    ];
+    for (a, b, max) in pairs {
+        let within = fuzzy::distance_within(a, b, max).is_some();
+        let kept = fuzzy::may_be_within(&fuzzy::trigrams(a), &fuzzy::trigrams(b), max);
+        assert!(kept || !within, "{a} {b}");
+    }
+
+    assert_eq!(fuzzy::trigrams("ab").len(), 4);
+    assert!(!fuzzy::may_be_within(
+        &fuzzy::trigrams("ingeniero"),
+        &fuzzy::trigrams("arquitecto"),
+        2
+    ));
+}
+
+#[test]
+fn fts_any_of_every_group() {
+    let groups = vec![
+        vec!["ingeniro".to_owned(), "ingeniero".to_owned()],
+        vec!["civil".to_owned()],
+    ];
+    expect![[r#"((vec_input : "ingeniro" OR vec_input : "ingeniero") AND (vec_input : "civil"))"#]]
+        .assert_eq(&fts::any_of(&groups, "vec_input"));
+}
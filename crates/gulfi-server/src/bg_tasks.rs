@@ -1,3 +1,8 @@
+use gulfi_ingest::{
+    EmbeddingCacheLimits, UsageSource, migrate_history_table, record_usage,
+    setup_embedding_cache_table, setup_usage_table, store_cached_embedding,
+};
+use gulfi_openai::TokenUsage;
 use rusqlite::{Connection, params};
 use std::{
     path::Path,
@@ -6,7 +11,7 @@ use std::{
 use tokio::sync::mpsc;
 use tracing::{info_span, instrument};
 
-use crate::search::SearchStrategy;
+use crate::search::{FtsMode, ScoreNormalization, SearchStrategy};
 
 #[derive(Debug)]
 pub enum WriteJob {
@@ -17,19 +22,36 @@ pub enum WriteJob {
         peso_fts: f32,
         peso_semantic: f32,
         k_neighbors: u64,
+        rrf_k: u32,
+        fts_limit: Option<u64>,
+        vec_limit: u64,
+        normalization: ScoreNormalization,
+        fts_mode: FtsMode,
+        /// `field:weight` pairs of every FTS column.
+        fts_weights: String,
     },
+    /// Stores a query embedding in `embedding_cache`.
     Cache {
+        model: String,
+        dimensions: usize,
         query: String,
-        result_json: String,
-        expires_at: i64,
+        embedding: Arc<Vec<f32>>,
+    },
+    Usage {
+        model: String,
+        usage: TokenUsage,
     },
 }
 
 #[instrument(name = "bg_task", fields(db_path, job))]
 pub fn spawn_writer_task<P: AsRef<Path>>(
     db_path: P,
+    cache_limits: EmbeddingCacheLimits,
 ) -> eyre::Result<mpsc::UnboundedSender<WriteJob>> {
     let conn = Connection::open(db_path)?;
+    setup_usage_table(&conn)?;
+    setup_embedding_cache_table(&conn)?;
+    migrate_history_table(&conn)?;
     let (tx, mut rx) = mpsc::unbounded_channel();
 
     let conn = Arc::new(Mutex::new(conn));
@@ -39,7 +61,7 @@ pub fn spawn_writer_task<P: AsRef<Path>>(
             while let Some(job) = rx.recv().await {
                 let conn = conn.clone();
 
-                let res = tokio::task::spawn_blocking(move || {
+                let res = tokio::task::spawn_blocking(move || -> eyre::Result<()> {
                     let conn = conn.lock().expect("Lock should be obtainable");
                     match job {
                         WriteJob::History {
@@ -49,11 +71,17 @@ pub fn spawn_writer_task<P: AsRef<Path>>(
                             peso_fts,
                             peso_semantic,
                             k_neighbors,
+                            rrf_k,
+                            fts_limit,
+                            vec_limit,
+                            normalization,
+                            fts_mode,
+                            fts_weights,
                         } => {
                             let insert_span = info_span!("bg_task.history");
                             let _guard = insert_span.enter();
                             let mut stmt = conn.prepare_cached(
-                                "insert or replace into historial(query, strategy, doc, peso_fts, peso_semantic, neighbors) values (?,?,?,?,?,?)")?;
+                                "insert or replace into historial(query, strategy, doc, peso_fts, peso_semantic, neighbors, rrf_k, fts_limit, vec_limit, normalization, fts_mode, fts_weights) values (?,?,?,?,?,?,?,?,?,?,?,?)")?;
 
                             stmt.execute(params![
                                 query,
@@ -61,19 +89,45 @@ pub fn spawn_writer_task<P: AsRef<Path>>(
                                 doc,
                                 peso_fts,
                                 peso_semantic,
-                                k_neighbors
-                            ])
+                                k_neighbors,
+                                rrf_k,
+                                fts_limit,
+                                vec_limit,
+                                normalization,
+                                fts_mode,
+                                fts_weights
+                            ])?;
+                            Ok(())
                         }
                         WriteJob::Cache {
-                            query: _,
-                            result_json: _,
-                            expires_at: _,
-                        } => todo!(),
+                            model,
+                            dimensions,
+                            query,
+                            embedding,
+                        } => {
+                            let cache_span = info_span!("bg_task.cache");
+                            let _guard = cache_span.enter();
+                            store_cached_embedding(
+                                &conn,
+                                &model,
+                                dimensions,
+                                &query,
+                                &embedding,
+                                cache_limits,
+                            )
+                        }
+                        WriteJob::Usage { model, usage } => {
+                            let usage_span = info_span!("bg_task.usage");
+                            let _guard = usage_span.enter();
+                            record_usage(&conn, UsageSource::Search, None, &model, usage)
+                        }
                     }
                 }).await;
 
-                if let Err(e) = res {
-                    eprintln!("[writer task] Write failed: {e:?}");
+                match res {
+                    Ok(Err(e)) => eprintln!("[writer task] Write failed: {e:?}"),
+                    Err(e) => eprintln!("[writer task] Write failed: {e:?}"),
+                    Ok(Ok(())) => {}
                 }
             }
         }
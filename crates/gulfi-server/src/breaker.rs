@@ -0,0 +1,216 @@
+use gulfi_openai::EmbeddingError;
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// When the requests to the embedding provider stop being sent, and for how long.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive failures that open the circuit.
+    pub failure_threshold: u32,
+    /// Seconds the circuit stays open before a request is let through to probe the provider.
+    pub cooldown_secs: u64,
+    /// Answers the semantic and RRF searches with FTS results while the provider is down,
+    /// instead of failing them.
+    pub fts_fallback: bool,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown_secs: 30,
+            fts_fallback: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// The cooldown is over and a single request is probing the provider.
+    HalfOpen,
+}
+
+/// Fails the query embeddings fast once the provider has failed `failure_threshold` times in a
+/// row, instead of having every search wait out the timeout.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: &CircuitBreakerSettings) -> Self {
+        Self {
+            failure_threshold: settings.failure_threshold.max(1),
+            cooldown: Duration::from_secs(settings.cooldown_secs),
+            state: Arc::new(Mutex::new(State::Closed { failures: 0 })),
+        }
+    }
+
+    /// Whether a request can be sent to the provider, with the permit to record its outcome.
+    /// Otherwise, returns how long until the next probe.
+    pub fn check(&self) -> Result<Permit<'_>, Duration> {
+        let mut state = self.state.lock().expect("the breaker state isn't poisoned");
+
+        match *state {
+            State::Closed { .. } => Ok(Permit {
+                breaker: self,
+                probe: false,
+            }),
+            State::Open { until } => {
+                let now = Instant::now();
+                if now < until {
+                    return Err(until - now);
+                }
+                *state = State::HalfOpen;
+                Ok(Permit {
+                    breaker: self,
+                    probe: true,
+                })
+            }
+            State::HalfOpen => Err(Duration::ZERO),
+        }
+    }
+
+    fn record(&self, result: Result<(), &EmbeddingError>) {
+        let mut state = self.state.lock().expect("the breaker state isn't poisoned");
+
+        *state = match (*state, result) {
+            (_, Ok(())) => State::Closed { failures: 0 },
+            (_, Err(err)) if !is_outage(err) => State::Closed { failures: 0 },
+            (State::Closed { failures }, Err(_)) if failures + 1 < self.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, Err(_)) => State::Open {
+                until: Instant::now() + self.cooldown,
+            },
+        };
+    }
+
+    pub fn is_open(&self) -> bool {
+        let state = self.state.lock().expect("the breaker state isn't poisoned");
+        !matches!(*state, State::Closed { .. })
+    }
+}
+
+/// A request allowed by [`CircuitBreaker::check`]. Dropping the probe without recording its
+/// outcome, as when its search is cancelled, lets the next request probe the provider instead.
+#[derive(Debug)]
+#[must_use = "the outcome of the request has to be recorded"]
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl Permit<'_> {
+    pub fn record(mut self, result: Result<(), &EmbeddingError>) {
+        self.probe = false;
+        self.breaker.record(result);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if !self.probe {
+            return;
+        }
+
+        let mut state = self
+            .breaker
+            .state
+            .lock()
+            .expect("the breaker state isn't poisoned");
+        if *state == State::HalfOpen {
+            *state = State::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+}
+
+/// Whether `err` means the provider can't serve any query. A rejected request only concerns
+/// the query that was sent.
+pub fn is_outage(err: &EmbeddingError) -> bool {
+    !matches!(err, EmbeddingError::BadRequest(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_the_threshold_and_probes_after_the_cooldown() {
+        let breaker = CircuitBreaker::new(&CircuitBreakerSettings {
+            failure_threshold: 2,
+            cooldown_secs: 0,
+            fts_fallback: true,
+        });
+
+        breaker.record(Err(&EmbeddingError::BadRequest("too long".to_owned())));
+        breaker.record(Err(&EmbeddingError::Timeout));
+        assert!(breaker.check().is_ok());
+        assert!(!breaker.is_open());
+
+        breaker.record(Err(&EmbeddingError::Timeout));
+        assert!(breaker.is_open());
+
+        // The cooldown is over, only one request probes the provider.
+        let probe = breaker.check().expect("the cooldown is over");
+        assert_eq!(breaker.check().unwrap_err(), Duration::ZERO);
+
+        probe.record(Err(&EmbeddingError::Timeout));
+        assert!(breaker.is_open());
+
+        let probe = breaker.check().expect("the cooldown is over");
+        probe.record(Ok(()));
+        assert!(!breaker.is_open());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn a_cancelled_probe_lets_another_request_probe() {
+        let breaker = CircuitBreaker::new(&CircuitBreakerSettings {
+            failure_threshold: 1,
+            cooldown_secs: 0,
+            fts_fallback: true,
+        });
+
+        breaker.record(Err(&EmbeddingError::Timeout));
+
+        let probe = breaker.check().expect("the cooldown is over");
+        assert_eq!(breaker.check().unwrap_err(), Duration::ZERO);
+        drop(probe);
+
+        assert!(breaker.is_open());
+        let probe = breaker.check().expect("the cancelled probe is released");
+        probe.record(Ok(()));
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn fails_fast_while_open() {
+        let breaker = CircuitBreaker::new(&CircuitBreakerSettings {
+            failure_threshold: 1,
+            cooldown_secs: 60,
+            fts_fallback: false,
+        });
+
+        breaker.record(Err(&EmbeddingError::MaxRetriesExceeded));
+
+        let retry_in = breaker.check().unwrap_err();
+        assert!(retry_in > Duration::from_secs(59));
+    }
+}
@@ -1,7 +1,14 @@
-use secrecy::SecretString;
+use crate::breaker::CircuitBreakerSettings;
+use gulfi_ingest::EmbeddingCacheLimits;
+use gulfi_openai::{
+    DEFAULT_DIMENSIONS, DEFAULT_MODEL, EmbeddingClient, EncodingFormat, HttpClient,
+    LocalHashClient, OllamaClient, OpenAIClient, PromptTemplates, RateLimits, RerankClient,
+    http::HttpEndpointConfig,
+};
+use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
-use std::{net::IpAddr, path::PathBuf};
+use std::{collections::HashMap, net::IpAddr, path::PathBuf};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Settings {
@@ -9,6 +16,13 @@ pub struct Settings {
     pub embedding_provider: EmbeddingProviderSettings,
     pub db_settings: DatabaseSettings,
     pub tracer_provider: TracingSettings,
+    #[serde(default)]
+    pub embedding_cache: EmbeddingCacheSettings,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerSettings,
+    /// Cross-encoder that reorders the results of the searches that ask for it.
+    #[serde(default)]
+    pub reranker: Option<RerankerSettings>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -20,8 +34,172 @@ pub struct DatabaseSettings {
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct EmbeddingProviderSettings {
+    #[serde(default)]
+    pub kind: ProviderKind,
+    #[serde(default)]
     pub endpoint_url: String,
+    #[serde(default = "empty_secret")]
     pub auth_token: SecretString,
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[serde(default = "default_dimensions")]
+    pub dimensions: u64,
+    /// Format of the embeddings in the responses, only used by the `openai` provider.
+    #[serde(default)]
+    pub encoding_format: EncodingFormat,
+    /// Request and response layout, only used by the `http` provider.
+    #[serde(default)]
+    pub http: HttpEndpointConfig,
+    /// Caps applied when the provider doesn't send `x-ratelimit-*` headers.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// Query and document templates of each model, for the ones trained with instructions.
+    #[serde(default)]
+    pub prompts: HashMap<String, PromptTemplates>,
+    /// Price of each model, in USD per million tokens.
+    #[serde(default = "default_pricing")]
+    pub pricing: HashMap<String, f64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProviderKind {
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAI,
+    #[serde(rename = "ollama")]
+    Ollama,
+    #[serde(rename = "http")]
+    Http,
+    /// Deterministic vectors computed locally, for demos and tests without network access.
+    #[serde(rename = "local-hash")]
+    LocalHash,
+}
+
+impl EmbeddingProviderSettings {
+    /// Estimated cost in USD of `tokens` embedded with `model`, if its price is known.
+    pub fn cost(&self, model: &str, tokens: u64) -> Option<f64> {
+        self.pricing
+            .get(model)
+            .map(|price| price * tokens as f64 / 1_000_000.0)
+    }
+
+    /// Templates of `model`, empty if it has none.
+    pub fn prompts_for(&self, model: &str) -> PromptTemplates {
+        self.prompts.get(model).cloned().unwrap_or_default()
+    }
+
+    pub fn build_client(&self) -> EmbeddingClient {
+        match self.kind {
+            ProviderKind::OpenAI => EmbeddingClient::OpenAI(
+                OpenAIClient::new(
+                    self.auth_token.expose_secret().to_string(),
+                    self.endpoint_url.clone(),
+                )
+                .with_model(self.model.clone(), Some(self.dimensions))
+                .with_encoding_format(self.encoding_format)
+                .with_rate_limits(self.rate_limits.clone()),
+            ),
+            ProviderKind::Ollama => EmbeddingClient::Ollama(
+                OllamaClient::new(self.endpoint_url.clone(), self.model.clone())
+                    .with_rate_limits(self.rate_limits.clone()),
+            ),
+            ProviderKind::Http => EmbeddingClient::Http(
+                HttpClient::new(
+                    self.endpoint_url.clone(),
+                    self.auth_token.clone(),
+                    self.model.clone(),
+                    self.http.clone(),
+                )
+                .with_rate_limits(self.rate_limits.clone()),
+            ),
+            ProviderKind::LocalHash => {
+                EmbeddingClient::LocalHash(LocalHashClient::new(self.dimensions as usize))
+            }
+        }
+    }
+}
+
+fn empty_secret() -> SecretString {
+    SecretString::new("".into())
+}
+
+fn default_model() -> String {
+    DEFAULT_MODEL.to_owned()
+}
+
+fn default_dimensions() -> u64 {
+    DEFAULT_DIMENSIONS
+}
+
+fn default_pricing() -> HashMap<String, f64> {
+    HashMap::from([
+        ("text-embedding-3-small".to_owned(), 0.02),
+        ("text-embedding-3-large".to_owned(), 0.13),
+        ("text-embedding-ada-002".to_owned(), 0.10),
+    ])
+}
+
+/// Bounds of the two tiers of the query embedding cache: the one in memory and the
+/// `embedding_cache` table, which survives restarts.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmbeddingCacheSettings {
+    pub memory_ttl_secs: u64,
+    pub memory_tti_secs: u64,
+    pub memory_max_entries: u64,
+    pub ttl_secs: u64,
+    pub max_entries: u64,
+    /// Maximum size of the stored embeddings, in MB.
+    pub max_size_mb: u64,
+}
+
+impl Default for EmbeddingCacheSettings {
+    fn default() -> Self {
+        Self {
+            memory_ttl_secs: 5 * 60,
+            memory_tti_secs: 60,
+            memory_max_entries: 10_000,
+            ttl_secs: 30 * 24 * 60 * 60,
+            max_entries: 100_000,
+            max_size_mb: 512,
+        }
+    }
+}
+
+impl EmbeddingCacheSettings {
+    pub fn limits(&self) -> EmbeddingCacheLimits {
+        EmbeddingCacheLimits {
+            ttl_secs: self.ttl_secs,
+            max_entries: self.max_entries,
+            max_bytes: self.max_size_mb.saturating_mul(1024 * 1024),
+        }
+    }
+}
+
+/// A Cohere or Jina compatible `/rerank` endpoint.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RerankerSettings {
+    pub endpoint_url: String,
+    #[serde(default = "empty_secret")]
+    pub auth_token: SecretString,
+    pub model: String,
+    /// Rows of a search sent to the reranker, the rest keep their order after them.
+    #[serde(default = "default_rerank_top_n")]
+    pub top_n: usize,
+}
+
+impl RerankerSettings {
+    pub fn build_client(&self) -> RerankClient {
+        RerankClient::new(
+            self.endpoint_url.clone(),
+            self.auth_token.clone(),
+            self.model.clone(),
+        )
+    }
+}
+
+fn default_rerank_top_n() -> usize {
+    50
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -1,13 +1,14 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use chrono::Local;
 use color_eyre::Report;
-use gulfi_query::ParsingError;
+use gulfi_openai::EmbeddingError;
+use gulfi_query::{ParsingError, fts::FtsSyntaxError};
 use serde_json::json;
-use std::{fmt, io::Write};
+use std::{fmt, io::Write, sync::Arc, time::Duration};
 use termcolor::{ColorChoice, StandardStream};
 use tracing::error;
 
@@ -34,6 +35,9 @@ pub enum HttpError {
     MissingDocument {
         msg: String,
     },
+    InvalidParameter {
+        msg: String,
+    },
     Internal {
         err: String,
     },
@@ -43,6 +47,11 @@ pub enum HttpError {
         invalid_fields: Vec<String>,
     },
     Parsing(ParsingError),
+    FtsSyntax(FtsSyntaxError),
+    Embedding(Arc<EmbeddingError>),
+    ProviderUnavailable {
+        retry_in: Duration,
+    },
 }
 
 impl HttpError {
@@ -86,6 +95,12 @@ impl HttpError {
             msg: message.into(),
         }
     }
+
+    pub fn invalid_parameter(message: impl Into<String>) -> Self {
+        HttpError::InvalidParameter {
+            msg: message.into(),
+        }
+    }
 }
 
 macro_rules! impl_from {
@@ -103,7 +118,6 @@ impl_from!(std::io::Error);
 impl_from!(serde_urlencoded::de::Error);
 impl_from!(serde_json::Error);
 impl_from!(rusqlite::Error);
-impl_from!(CacheError);
 impl_from!(gulfi_ingest::pool::PoolError);
 
 impl IntoResponse for HttpError {
@@ -116,6 +130,12 @@ impl IntoResponse for HttpError {
             )
                 .into_response(),
 
+            HttpError::InvalidParameter { msg } => (
+                StatusCode::BAD_REQUEST,
+                Json(json!( { "err": msg, "type": "invalid_parameter", "date": date } )),
+            )
+                .into_response(),
+
             HttpError::AuthError { msg, err } => (
                 StatusCode::BAD_REQUEST,
                 Json(json!( { "msg":msg, "err": err, "date": date } )),
@@ -174,7 +194,79 @@ impl IntoResponse for HttpError {
                 )
                     .into_response(),
             },
+            HttpError::FtsSyntax(e) => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "err": e.to_string(),
+                    "type": "fts_syntax_error",
+                    "date": date
+                })),
+            )
+                .into_response(),
+            HttpError::Embedding(e) => {
+                let (status, kind) = embedding_status(&e);
+                (
+                    status,
+                    Json(json!({
+                        "err": e.to_string(),
+                        "type": kind,
+                        "date": date
+                    })),
+                )
+                    .into_response()
+            }
+            HttpError::ProviderUnavailable { retry_in } => {
+                let retry_after = retry_in.as_secs().max(1);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(json!({
+                        "err": "The embedding provider is unavailable",
+                        "type": "embedding_circuit_open",
+                        "retry_after": retry_after,
+                        "date": date
+                    })),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+impl From<CacheError> for HttpError {
+    fn from(err: CacheError) -> Self {
+        match err {
+            CacheError::EmbeddingError(e) => {
+                error!("embedding provider error: {e}");
+                HttpError::Embedding(e)
+            }
+            CacheError::ProviderUnavailable { retry_in } => {
+                HttpError::ProviderUnavailable { retry_in }
+            }
+            CacheError::CacheError(_) => Self::from_report(color_eyre::Report::from(err)),
+        }
+    }
+}
+
+/// The status that best describes a failure of the embedding provider, and the `type` sent with
+/// it.
+fn embedding_status(err: &EmbeddingError) -> (StatusCode, &'static str) {
+    match err {
+        EmbeddingError::BadRequest(_) => (StatusCode::BAD_REQUEST, "embedding_bad_request"),
+        EmbeddingError::RateLimit | EmbeddingError::MaxRetriesExceeded => {
+            (StatusCode::SERVICE_UNAVAILABLE, "embedding_rate_limit")
+        }
+        EmbeddingError::Timeout => (StatusCode::GATEWAY_TIMEOUT, "embedding_timeout"),
+        EmbeddingError::Unauthorized(_) => (StatusCode::BAD_GATEWAY, "embedding_auth"),
+        EmbeddingError::EmptyResponse
+        | EmbeddingError::InvalidResponse(_)
+        | EmbeddingError::CountMismatch { .. }
+        | EmbeddingError::MissingIndex(_)
+        | EmbeddingError::DuplicateIndex(_)
+        | EmbeddingError::IndexOutOfRange { .. } => {
+            (StatusCode::BAD_GATEWAY, "embedding_invalid_response")
         }
+        EmbeddingError::RequestError(..) => (StatusCode::BAD_GATEWAY, "embedding_unavailable"),
     }
 }
 
@@ -183,6 +275,11 @@ impl From<ParsingError> for HttpError {
         HttpError::Parsing(e)
     }
 }
+impl From<FtsSyntaxError> for HttpError {
+    fn from(e: FtsSyntaxError) -> Self {
+        HttpError::FtsSyntax(e)
+    }
+}
 impl From<argon2::password_hash::Error> for HttpError {
     fn from(err: argon2::password_hash::Error) -> Self {
         Self::from_report(eyre::eyre!("argon2 error: {:?}", err))
@@ -196,9 +293,16 @@ impl fmt::Display for HttpError {
         let msg = match self {
             HttpError::AuthError { msg, err } => format!("{msg}{err}"),
             HttpError::MissingDocument { msg } => msg.to_owned(),
+            HttpError::InvalidParameter { msg } => msg.to_owned(),
             HttpError::Internal { err } => err.to_owned(),
             HttpError::BadRequest { message, .. } => message.to_owned(),
             HttpError::Parsing(parsing_error) => parsing_error.to_string(),
+            HttpError::FtsSyntax(err) => err.to_string(),
+            HttpError::Embedding(e) => e.to_string(),
+            HttpError::ProviderUnavailable { retry_in } => format!(
+                "The embedding provider is unavailable, retrying in {}s",
+                retry_in.as_secs()
+            ),
         };
         write!(f, "HttpError: {}", msg)
     }
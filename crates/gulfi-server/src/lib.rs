@@ -1,8 +1,10 @@
 pub mod bg_tasks;
+pub mod breaker;
 pub mod configuration;
 pub mod extractors;
 pub mod formatter;
 pub mod into_http;
+pub mod pagination;
 pub mod routes;
 pub mod search;
 pub mod startup;
@@ -0,0 +1,222 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use crate::into_http::HttpError;
+use crate::search::{SearchParams, SearchStrategy};
+
+/// The rows of a search that are sent, all of them if there's no `limit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Page {
+    pub limit: Option<u64>,
+    pub offset: u64,
+}
+
+impl Page {
+    /// Reads the page from the `cursor` of the request, or from its `limit` and `offset`. A
+    /// `limit` sent with a cursor replaces the one of the cursor.
+    ///
+    /// `strategy` is the one the search is answered with, FTS when it falls back to it.
+    pub fn from_params(params: &SearchParams, strategy: SearchStrategy) -> Result<Self, HttpError> {
+        let Some(cursor) = &params.cursor else {
+            return Ok(Self {
+                limit: params.limit,
+                offset: params.offset.unwrap_or_default(),
+            });
+        };
+
+        if params.offset.is_some() {
+            return Err(HttpError::invalid_parameter(
+                "`offset` can't be sent along with `cursor`.",
+            ));
+        }
+
+        let page = Cursor::decode(cursor, fingerprint(params, strategy))?;
+        Ok(Self {
+            limit: params.limit.or(page.limit),
+            offset: page.offset,
+        })
+    }
+
+    /// Cursor of the page that follows this one, if there could be more rows after the `sent`
+    /// ones.
+    pub fn next_cursor(
+        &self,
+        sent: usize,
+        total: Option<usize>,
+        params: &SearchParams,
+        strategy: SearchStrategy,
+    ) -> Option<String> {
+        let limit = self.limit?;
+        let next = self.offset + sent as u64;
+
+        if (sent as u64) < limit || total.is_some_and(|total| next >= total as u64) {
+            return None;
+        }
+
+        let next = Self {
+            limit: Some(limit),
+            offset: next,
+        };
+        Some(Cursor::encode(next, fingerprint(params, strategy)))
+    }
+}
+
+/// Opaque position in the results of a search. It carries a fingerprint of the search, so it
+/// isn't used to page through a different one.
+struct Cursor;
+
+impl Cursor {
+    fn encode(page: Page, fingerprint: u64) -> String {
+        let limit = page
+            .limit
+            .map(|limit| limit.to_string())
+            .unwrap_or_default();
+        URL_SAFE_NO_PAD.encode(format!("{}:{limit}:{fingerprint:x}", page.offset))
+    }
+
+    fn decode(cursor: &str, fingerprint: u64) -> Result<Page, HttpError> {
+        let invalid = || HttpError::invalid_parameter("The cursor isn't valid.");
+
+        let decoded = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+
+        let mut parts = decoded.splitn(3, ':');
+        let (Some(offset), Some(limit), Some(hash)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        if u64::from_str_radix(hash, 16).map_err(|_| invalid())? != fingerprint {
+            return Err(HttpError::invalid_parameter(
+                "The cursor belongs to a different search.",
+            ));
+        }
+
+        Ok(Page {
+            offset: offset.parse().map_err(|_| invalid())?,
+            limit: if limit.is_empty() {
+                None
+            } else {
+                Some(limit.parse().map_err(|_| invalid())?)
+            },
+        })
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Identifies the results of a search, whatever page of them is asked for: every parameter
+/// that changes which rows match or their order. It's hashed with FNV-1a, which unlike the
+/// hasher of `std` gives the same result in every build, so the cursors outlive the server.
+///
+/// The strategy is the one that answered the search, so the cursor of a search that fell back to
+/// FTS isn't used once the provider is back.
+fn fingerprint(params: &SearchParams, strategy: SearchStrategy) -> u64 {
+    let search = format!(
+        "{}\0{}\0{:?}\0{}\0{}\0{}\0{:?}\0{:?}\0{:?}\0{:?}\0{:?}\0{:?}\0{}",
+        params.document.to_lowercase(),
+        params.search_str,
+        strategy,
+        params.peso_fts,
+        params.peso_semantic,
+        params.k_neighbors,
+        params.normalization,
+        params.fts_mode,
+        params.fts_weights,
+        params.rrf_k,
+        params.fts_limit,
+        params.vec_limit,
+        params.rerank,
+    );
+
+    search.bytes().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(query: &str) -> SearchParams {
+        serde_urlencoded::from_str(&format!(
+            "query={query}&document=demo&strategy=Fts&peso_fts=50&peso_semantic=50&k=10&limit=2"
+        ))
+        .expect("Should parse the parameters")
+    }
+
+    #[test]
+    fn cursors_page_through_the_same_search() {
+        let first = params("rust");
+        let page =
+            Page::from_params(&first, SearchStrategy::Fts).expect("Should read the first page");
+        assert_eq!(
+            page,
+            Page {
+                limit: Some(2),
+                offset: 0
+            }
+        );
+
+        let cursor = page
+            .next_cursor(2, Some(5), &first, SearchStrategy::Fts)
+            .expect("Should have a next page");
+
+        let mut second = params("rust");
+        second.cursor = Some(cursor.clone());
+        assert_eq!(
+            Page::from_params(&second, SearchStrategy::Fts).expect("Should read the cursor"),
+            Page {
+                limit: Some(2),
+                offset: 2
+            }
+        );
+
+        let mut other = params("python");
+        other.cursor = Some(cursor.clone());
+        assert!(Page::from_params(&other, SearchStrategy::Fts).is_err());
+
+        let mut reweighted = params("rust");
+        reweighted.peso_fts = 80.0;
+        reweighted.cursor = Some(cursor);
+        assert!(Page::from_params(&reweighted, SearchStrategy::Fts).is_err());
+
+        let last = Page {
+            limit: Some(2),
+            offset: 4,
+        };
+        assert_eq!(
+            last.next_cursor(1, Some(5), &first, SearchStrategy::Fts),
+            None
+        );
+        assert_eq!(
+            last.next_cursor(2, Some(6), &first, SearchStrategy::Fts),
+            None
+        );
+    }
+
+    #[test]
+    fn cursors_of_a_degraded_search_are_rejected_once_it_recovers() {
+        let mut first = params("rust");
+        first.strategy = SearchStrategy::Semantic;
+
+        // The provider was down, the first page was answered with FTS.
+        let cursor = Page::from_params(&first, SearchStrategy::Fts)
+            .unwrap()
+            .next_cursor(2, Some(5), &first, SearchStrategy::Fts)
+            .expect("Should have a next page");
+
+        let mut second = first.clone();
+        second.cursor = Some(cursor);
+        assert!(Page::from_params(&second, SearchStrategy::Fts).is_ok());
+        assert!(Page::from_params(&second, SearchStrategy::Semantic).is_err());
+    }
+
+    #[test]
+    fn fingerprints_are_stable() {
+        assert_eq!(
+            fingerprint(&params("rust"), SearchStrategy::Fts),
+            0x35a5_e192_44f4_059a
+        );
+    }
+}
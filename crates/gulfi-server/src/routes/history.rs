@@ -16,7 +16,7 @@ use crate::{
     HistorialView,
     search::SearchStrategy,
     startup::ServerState,
-    views::{HistorialFullView, HistorialParams},
+    views::{HistorialFullView, HistorialParams, HistorySettings},
 };
 
 #[axum::debug_handler]
@@ -78,7 +78,7 @@ pub async fn historial_detailed(
 
     let result = get_historial(
         &conn,
-        "select id, query, strategy, peso_fts, peso_semantic, neighbors, timestamp from historial where doc = :doc order by timestamp desc",
+        "select id, query, strategy, peso_fts, peso_semantic, neighbors, timestamp, rrf_k, fts_limit, vec_limit, normalization, fts_mode, fts_weights from historial where doc = :doc order by timestamp desc",
         |row| {
             let id: u64 = row.get(0).unwrap_or_default();
             let query: String = row.get(1).unwrap_or_default();
@@ -87,6 +87,14 @@ pub async fn historial_detailed(
             let peso_semantic: f32 = row.get(4).unwrap_or_default();
             let neighbors: u64 = row.get(5).unwrap_or_default();
             let timestamp_str: String = row.get(6).unwrap_or_default();
+            let settings = HistorySettings {
+                rrf_k: row.get(7).unwrap_or_default(),
+                fts_limit: row.get(8).unwrap_or_default(),
+                vec_limit: row.get(9).unwrap_or_default(),
+                normalization: row.get(10).unwrap_or_default(),
+                fts_mode: row.get(11).unwrap_or_default(),
+                fts_weights: row.get(12).unwrap_or_default(),
+            };
 
             let timestamp = NaiveDateTime::parse_from_str(&timestamp_str, "%Y-%m-%d %H:%M:%S")
                 .unwrap_or_else(|_| NaiveDateTime::default());
@@ -113,6 +121,7 @@ pub async fn historial_detailed(
                 peso_fts,
                 peso_semantic,
                 neighbors,
+                settings,
                 timestamp,
             );
 
@@ -2,24 +2,27 @@ use crate::into_http::HttpError;
 
 use axum::response::{Sse, sse::Event};
 use eyre::Report;
-use futures::Stream;
+use futures::{FutureExt, Stream, future};
 use gulfi_ingest::Document;
+use gulfi_openai::RerankClient;
 use gulfi_query::{
     Constraint::{self},
-    Query,
+    Query, fts, fuzzy,
 };
 use std::{collections::BTreeMap, convert::Infallible, fmt::Write, sync::Arc};
 
 use reqwest::Client;
 use rusqlite::{
-    ToSql,
+    Connection, ToSql,
     types::{FromSql, FromSqlError, ToSqlOutput, ValueRef},
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::{error, info_span, instrument};
+use tracing::{Instrument, Span, error, info_span, instrument, warn};
 use zerocopy::IntoBytes;
 
+use crate::bg_tasks::WriteJob;
+use crate::pagination::Page;
 use crate::startup::ServerState;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
@@ -28,6 +31,40 @@ pub enum SearchStrategy {
     Fts,
     Semantic,
     ReciprocalRankFusion,
+    /// FTS matches first, then the semantic matches that FTS missed.
+    KeywordFirst,
+    /// The best `k` FTS matches, ordered by their distance to the query embedding.
+    ReRankBySemantics,
+    /// Weighted sum of the BM25 and vector scores, normalized inside each candidate set.
+    RelativeScoreFusion,
+}
+
+/// How the scores of each candidate set are brought to a common scale by
+/// [`SearchStrategy::RelativeScoreFusion`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub enum ScoreNormalization {
+    /// Scales the scores to `[0, 1]`.
+    #[default]
+    #[serde(rename = "minmax")]
+    MinMax,
+    /// Standard scores, an outlier doesn't compress the rest of the set.
+    #[serde(rename = "zscore")]
+    ZScore,
+}
+
+/// How the query is matched by FTS.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub enum FtsMode {
+    /// The whole query as an exact phrase.
+    #[default]
+    #[serde(rename = "phrase")]
+    Phrase,
+    /// Operators, phrases, prefixes, `NEAR` groups and column filters, see [`fts::translate`].
+    #[serde(rename = "advanced")]
+    Advanced,
+    /// Every term, or one of the terms of the index a few edits away from it.
+    #[serde(rename = "fuzzy")]
+    Fuzzy,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -41,45 +78,134 @@ pub struct SearchParams {
     #[serde(rename = "k")]
     pub k_neighbors: u64,
     pub batch_size: Option<usize>,
+    #[serde(default)]
+    pub normalization: ScoreNormalization,
+    #[serde(default)]
+    pub fts_mode: FtsMode,
+    /// Comma separated `field:weight` pairs, over the `fts_weights` of the document.
+    pub fts_weights: Option<String>,
+    /// Overrides the defaults of the document in `meta.json`.
+    pub rrf_k: Option<u32>,
+    pub fts_limit: Option<u64>,
+    pub vec_limit: Option<u64>,
+    /// Reorders the first rows with the configured reranker.
+    #[serde(default)]
+    pub rerank: bool,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+    /// Position returned in the `complete` event of the previous page.
+    pub cursor: Option<String>,
+    /// Comma separated fields whose values are counted over every match.
+    pub facets: Option<String>,
+    /// Values of each facet that are sent, the most frequent first.
+    pub facet_size: Option<u64>,
 }
 
 impl SearchStrategy {
-    #[instrument(name = "searching", skip(self, state, client, params), fields(source = tracing::field::Empty))]
+    /// Prepares the search and embeds the query before opening the stream, so a bad query or a
+    /// failing provider is answered with its own status code instead of an event.
+    ///
+    /// While the provider is down, and `fts_fallback` is enabled, the search is answered with
+    /// FTS results and flagged as `degraded` in its metadata.
+    #[instrument(name = "searching", skip(self, state, client, params), fields(source = tracing::field::Empty, degraded = tracing::field::Empty))]
     pub async fn search_stream(
         self,
         state: ServerState,
         client: Client,
         params: SearchParams,
-    ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-        let state = state.clone();
-        let client = client.clone();
+    ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, HttpError> {
+        let mut search_result = Self::prepare_search(&state, &params).await?;
+
+        let embedding = state
+            .get_embeddings(
+                &search_result.query.query,
+                &client,
+                search_result.strategy,
+                &Span::current(),
+            )
+            .instrument(info_span!("query.embedding"))
+            .await;
+
+        let (query_emb, degraded) = match embedding {
+            Ok(result) => (result.into_inner(), false),
+            Err(err) if state.fts_fallback && err.is_outage() => {
+                warn!("Falling back to FTS: {err}");
+                Span::current().record("degraded", true);
+                search_result.strategy = SearchStrategy::Fts;
+                (None, true)
+            }
+            Err(err) => return Err(err.into()),
+        };
 
-        let s = async_stream::stream! {
-            let search_result = match Self::prepare_search(&state, &params).await {
-                Ok(res) => res,
-                Err(e) => {
-                    let error_msg = StreamMessage::Error { msg: e.to_string() };
-                    yield Ok(Event::default().data(serde_json::to_string(&error_msg).unwrap()));
-                    return;
+        // The cursor and the history belong to the strategy the search is answered with.
+        search_result.page = Page::from_params(&params, search_result.strategy)?;
+        let strategy = search_result.strategy;
+
+        let _ = state.writer.send(WriteJob::History {
+            query: params.search_str.clone(),
+            doc: search_result.document.name.clone(),
+            strategy: search_result.strategy,
+            peso_fts: search_result.weight_fts,
+            peso_semantic: search_result.weight_vec,
+            k_neighbors: search_result.k_neighbors,
+            rrf_k: search_result.rrf_k,
+            fts_limit: search_result.fts_limit,
+            vec_limit: search_result.vec_limit,
+            normalization: search_result.normalization,
+            fts_mode: params.fts_mode,
+            fts_weights: search_result
+                .document
+                .fts_columns()
+                .iter()
+                .zip(&search_result.fts_weights)
+                .map(|(column, weight)| format!("{column}:{weight}"))
+                .collect::<Vec<_>>()
+                .join(","),
+        });
+
+        let page = search_result.page;
+        let (total, facets) =
+            match Self::spawn_facet_counts(&state, &search_result, query_emb.clone()) {
+                // The facet query already counts every match, its results are kept for the end.
+                Some(handle) if Self::countable(&search_result) => {
+                    let counts = handle.await.ok().flatten();
+                    let total = counts.as_ref().map(|(total, _)| *total);
+                    let facets = future::ready(counts.map(|(_, facets)| facets)).boxed();
+                    (total, Some(facets))
                 }
+                facets => (
+                    Self::count_matches(&state, &search_result, query_emb.clone()).await,
+                    facets.map(|handle| {
+                        async move { handle.await.ok().flatten().map(|(_, facets)| facets) }.boxed()
+                    }),
+                ),
             };
 
+        let s = async_stream::stream! {
             let columns: Vec<String> = search_result
                 .document
                 .fields
                 .iter()
                 .map(|f| f.name.clone())
                 .collect();
-            let metadata = StreamMessage::Metadata { columns };
+            let metadata = StreamMessage::Metadata {
+                columns,
+                degraded,
+                total,
+                expansions: search_result.expansions.clone(),
+            };
             yield Ok(Event::default().data(serde_json::to_string(&metadata).unwrap()));
 
 
+        let rerank = params.rerank.then_some(client);
+
         let mut row_count = 0;
         match SearchStrategy::stream_results(
             search_result,
-            client,
+            query_emb,
             state,
-            params.batch_size.unwrap_or(10)
+            params.batch_size.unwrap_or(10),
+            rerank,
         )
         .await
         {
@@ -87,9 +213,8 @@ impl SearchStrategy {
                  while let Some(result) = result_stream.recv().await {
                     match result {
                         Ok(msg) => {
-                            match &msg {
-                                StreamMessage::Rows { data } => row_count += data.len(),
-                                _ => {}
+                            if let StreamMessage::Rows { data } = &msg {
+                                row_count += data.len();
                             }
                             yield Ok(Event::default().data(serde_json::to_string(&msg).unwrap()));
                         }
@@ -107,11 +232,21 @@ impl SearchStrategy {
 
             }
         }
-            let complete = StreamMessage::Complete { total_sent: row_count };
+            if let Some(facets) = facets
+                && let Some(facets) = facets.await
+            {
+                let facets = StreamMessage::Facets { facets };
+                yield Ok(Event::default().data(serde_json::to_string(&facets).unwrap()));
+            }
+
+            let complete = StreamMessage::Complete {
+                total_sent: row_count,
+                next_cursor: page.next_cursor(row_count, total, &params, strategy),
+            };
             yield Ok(Event::default().data(serde_json::to_string(&complete).unwrap()));
         };
 
-        Sse::new(s)
+        Ok(Sse::new(s))
     }
 
     async fn prepare_search(
@@ -130,22 +265,170 @@ impl SearchStrategy {
             Query::parse(&format!("query: {}", params.search_str)).map_err(HttpError::from)?;
 
         validate_query_constraints(document, &query)?;
+        let facets = parse_facets(document, params.facets.as_deref())?;
+        let fts_weights = fts_weights(document, params.fts_weights.as_deref())?;
+
+        let mut expansions = BTreeMap::new();
+        let fts_query = match params.fts_mode {
+            FtsMode::Phrase => fts::phrase(&query.query, "vec_input"),
+            FtsMode::Fuzzy => {
+                let groups = Self::expand_terms(state, document, &query.query).await?;
+                for group in &groups {
+                    if let [term, similar @ ..] = group.as_slice()
+                        && !similar.is_empty()
+                    {
+                        expansions.insert(term.clone(), similar.to_vec());
+                    }
+                }
+                fts::any_of(&groups, "vec_input")
+            }
+            FtsMode::Advanced => {
+                let columns: Vec<&str> = document
+                    .fields
+                    .iter()
+                    .filter(|field| !field.vec_input)
+                    .map(|field| field.name.as_str())
+                    .collect();
+                fts::translate(&query.query, "vec_input", &columns)?
+            }
+        };
+
+        if params.rerank && state.reranker.is_none() {
+            return Err(HttpError::invalid_parameter(
+                "There's no reranker configured, set `reranker` in the configuration to use `rerank`.",
+            ));
+        }
 
         Ok(StreamSearch {
+            // Read from the cursor once it's known whether the search falls back to FTS.
+            page: Page::default(),
+            facets,
+            facet_size: params.facet_size.unwrap_or(10),
             document: document.clone(),
             query,
+            fts_query,
+            fts_weights,
+            expansions,
             strategy: params.strategy,
             k_neighbors: params.k_neighbors,
             weight_fts: params.peso_fts,
             weight_vec: params.peso_semantic,
+            normalization: params.normalization,
+            rrf_k: params.rrf_k.unwrap_or(document.search.rrf_k),
+            fts_limit: params.fts_limit.or(document.search.fts_limit),
+            vec_limit: params
+                .vec_limit
+                .or(document.search.vec_limit)
+                .unwrap_or(params.k_neighbors),
+        })
+    }
+
+    /// Pairs each term of the query with the terms of `vec_input` in the FTS index within its
+    /// edit distance, the closest and most frequent first.
+    ///
+    /// The vocabulary is compared in a blocking task, only the terms sharing enough trigrams with
+    /// one of the query get their distance computed.
+    async fn expand_terms(
+        state: &ServerState,
+        document: &Document,
+        query: &str,
+    ) -> Result<Vec<Vec<String>>, HttpError> {
+        let terms = fuzzy::terms(query);
+        if terms.is_empty() {
+            return Err(fts::FtsSyntaxError::Empty.into());
+        }
+
+        let lengths = terms.iter().map(|term| {
+            let len = term.chars().count();
+            (
+                len.saturating_sub(fuzzy::max_distance(len)),
+                len + fuzzy::max_distance(len),
+            )
+        });
+        let min_len = lengths
+            .clone()
+            .map(|(min, _)| min)
+            .min()
+            .unwrap_or_default();
+        let max_len = lengths.map(|(_, max)| max).max().unwrap_or_default();
+
+        let doc_name = document.name.clone();
+        let conn = state.pool.acquire().await?;
+        tokio::task::spawn_blocking(move || {
+            // A temporary table, so the databases synced before it existed can use it too.
+            conn.execute_batch(&format!(
+                "create virtual table if not exists temp.fts_{doc_name}_vocab
+                using fts5vocab(main, fts_{doc_name}, col);"
+            ))?;
+
+            let mut stmt = conn.prepare(&format!(
+                "select term, doc from temp.fts_{doc_name}_vocab
+                where col = 'vec_input' and length(term) between ?1 and ?2"
+            ))?;
+            let mut rows = stmt.query([min_len, max_len])?;
+
+            let queries: Vec<(usize, Vec<[char; 3]>)> = terms
+                .iter()
+                .map(|term| {
+                    let max = fuzzy::max_distance(term.chars().count());
+                    (max, fuzzy::trigrams(term))
+                })
+                .collect();
+            let mut similar: Vec<Vec<(usize, u64, String)>> = vec![Vec::new(); terms.len()];
+
+            while let Some(row) = rows.next()? {
+                let candidate: String = row.get(0)?;
+                let docs: u64 = row.get(1)?;
+                let trigrams = fuzzy::trigrams(&candidate);
+
+                for ((term, (max, term_trigrams)), similar) in
+                    terms.iter().zip(&queries).zip(similar.iter_mut())
+                {
+                    if *term == candidate || !fuzzy::may_be_within(term_trigrams, &trigrams, *max) {
+                        continue;
+                    }
+                    if let Some(distance) = fuzzy::distance_within(term, &candidate, *max) {
+                        similar.push((distance, docs, candidate.clone()));
+                    }
+                }
+            }
+
+            let groups = terms
+                .into_iter()
+                .zip(similar)
+                .map(|(term, mut similar)| {
+                    similar.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+                    std::iter::once(term)
+                        .chain(
+                            similar
+                                .into_iter()
+                                .take(MAX_EXPANSIONS)
+                                .map(|(_, _, candidate)| candidate),
+                        )
+                        .collect()
+                })
+                .collect();
+
+            Ok::<_, rusqlite::Error>(groups)
         })
+        .instrument(info_span!("search.vocabulary"))
+        .await
+        .map_err(|err| HttpError::Internal {
+            err: err.to_string(),
+        })?
+        .map_err(HttpError::from)
     }
 
+    /// Runs the query and batches its rows. With `rerank`, the client used to call the
+    /// reranker, the first rows of the whole results go through it, and the page is cut from
+    /// the reranked rows, so every page of a search sees the same order.
     async fn stream_results(
         search: StreamSearch,
-        client: Client,
+        query_emb: Option<Arc<Vec<f32>>>,
         state: ServerState,
         batch_size: usize,
+        rerank: Option<Client>,
     ) -> eyre::Result<tokio::sync::mpsc::Receiver<Result<StreamMessage, eyre::Error>>> {
         let pool = state.pool.clone();
         let conn_handle = {
@@ -154,25 +437,35 @@ impl SearchStrategy {
             pool.acquire().await?
         };
 
-        let query_emb = {
-            let span = info_span!("query.embedding");
-            let _guard = span.enter();
-
-            state
-                .get_embeddings(&search.query.query, &client, search.strategy, &span)
-                .await?
-                .into_inner()
-        };
-
         let (result_tx, result_rx) =
             tokio::sync::mpsc::channel::<Result<StreamMessage, eyre::Error>>(batch_size * 2);
-        let (tx, mut rx) =
+        let (tx, rx) =
             tokio::sync::mpsc::channel::<Result<Vec<String>, rusqlite::Error>>(batch_size * 2);
+        let (column_tx, column_rx) = tokio::sync::oneshot::channel::<Option<usize>>();
+
+        let (mut rx, reranking) = match (rerank, state.reranker.clone()) {
+            (Some(client), Some(reranker)) => {
+                let stage = RerankStage {
+                    reranker,
+                    client,
+                    query: search.query.query.clone(),
+                    top_n: state.rerank_top_n,
+                    page: search.page,
+                };
+                (stage.spawn(rx, column_rx, batch_size), true)
+            }
+            _ => (rx, false),
+        };
 
         // TODO: Refactor binding_refs into a simpler type as binding_values is at the moment just a Vec over boxed types
         tokio::task::spawn_blocking(move || -> eyre::Result<()> {
-            let (sql, binding_values) = Self::build_query(&search, query_emb)?;
+            let (sql, binding_values) = if reranking {
+                Self::build_strategy_query(&search, query_emb)?
+            } else {
+                Self::build_query(&search, query_emb)?
+            };
             let mut stmt = conn_handle.prepare_cached(&sql)?;
+            let _ = column_tx.send(stmt.column_index("input").ok());
 
             let binding_refs: Vec<&dyn ToSql> =
                 binding_values.iter().map(|b| &**b as &dyn ToSql).collect();
@@ -227,14 +520,167 @@ impl SearchStrategy {
         Ok(result_rx)
     }
 
+    /// Whether the matches of `search` are counted: only in the paged searches that can do it
+    /// cheaply, FTS, through its index, and the semantic one, which has at most `k`. Without a
+    /// `limit` every match is sent anyway.
+    fn countable(search: &StreamSearch) -> bool {
+        search.page.limit.is_some()
+            && matches!(
+                search.strategy,
+                SearchStrategy::Fts | SearchStrategy::Semantic
+            )
+    }
+
+    /// Counts the matches of a [`countable`](Self::countable) search without facets. A failure
+    /// leaves the total out.
+    async fn count_matches(
+        state: &ServerState,
+        search: &StreamSearch,
+        query_emb: Option<Arc<Vec<f32>>>,
+    ) -> Option<usize> {
+        if !Self::countable(search) {
+            return None;
+        }
+
+        let (sql, binding_values) = Self::build_strategy_query(search, query_emb).ok()?;
+        let sql = format!(
+            "select count(*) from ({})",
+            sql.trim_end().trim_end_matches(';')
+        );
+
+        let conn = state.pool.acquire().await.ok()?;
+        let count = tokio::task::spawn_blocking(move || {
+            let binding_refs: Vec<&dyn ToSql> =
+                binding_values.iter().map(|b| &**b as &dyn ToSql).collect();
+            conn.query_row(&sql, &*binding_refs, |row| row.get::<_, usize>(0))
+        })
+        .instrument(info_span!("search.count"))
+        .await;
+
+        match count {
+            Ok(Ok(count)) => Some(count),
+            Ok(Err(err)) => {
+                warn!("Couldn't count the matches: {err}");
+                None
+            }
+            Err(err) => {
+                warn!("Couldn't count the matches: {err}");
+                None
+            }
+        }
+    }
+
+    /// Counts the matches and the values of the facets over them, while the rows are being
+    /// sent. A failure leaves both out.
+    fn spawn_facet_counts(
+        state: &ServerState,
+        search: &StreamSearch,
+        query_emb: Option<Arc<Vec<f32>>>,
+    ) -> Option<tokio::task::JoinHandle<Option<(usize, Facets)>>> {
+        if search.facets.is_empty() {
+            return None;
+        }
+
+        let (sql, binding_values) = Self::build_facet_query(search, query_emb).ok()?;
+
+        let pool = state.pool.clone();
+        let handle = tokio::spawn(
+            async move {
+                let conn = pool.acquire().await.ok()?;
+                let counts = tokio::task::spawn_blocking(move || {
+                    let binding_refs: Vec<&dyn ToSql> =
+                        binding_values.iter().map(|b| &**b as &dyn ToSql).collect();
+                    facet_counts(&conn, &sql, &binding_refs)
+                })
+                .await;
+
+                match counts {
+                    Ok(Ok(counts)) => Some(counts),
+                    Ok(Err(err)) => {
+                        warn!("Couldn't count the facets: {err}");
+                        None
+                    }
+                    Err(err) => {
+                        warn!("Couldn't count the facets: {err}");
+                        None
+                    }
+                }
+            }
+            .instrument(info_span!("search.facets")),
+        );
+
+        Some(handle)
+    }
+
+    /// The query of the strategy, materialized once to count its rows, in a row without a
+    /// `facet`, and the most frequent values of each facet.
+    fn build_facet_query(
+        search: &StreamSearch,
+        query_emb: Option<Arc<Vec<f32>>>,
+    ) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), HttpError> {
+        let (sql, mut binding_values) = Self::build_strategy_query(search, query_emb)?;
+        let counts: Vec<String> = search
+            .facets
+            .iter()
+            .map(|field| {
+                format!(
+                    "select * from (
+                        select '{field}' as facet, {field} as value, count(*) as count
+                        from results
+                        where {field} is not null
+                        group by {field}
+                        order by count desc, value
+                        limit :facet_size
+                    )"
+                )
+            })
+            .collect();
+        let sql = format!(
+            "with results as materialized ({})
+            select null as facet, null as value, count(*) as count from results
+            union all {}",
+            sql.trim_end().trim_end_matches(';'),
+            counts.join(" union all ")
+        );
+        binding_values.push(Box::new(search.facet_size));
+
+        Ok((sql, binding_values))
+    }
+
+    /// The query of the strategy, restricted to the page of the search.
     fn build_query(
         search: &StreamSearch,
         query_emb: Option<Arc<Vec<f32>>>,
+    ) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), HttpError> {
+        let (sql, mut binding_values) = Self::build_strategy_query(search, query_emb)?;
+
+        if search.page == Page::default() {
+            return Ok((sql, binding_values));
+        }
+
+        let sql = format!(
+            "select * from ({}) limit :page_limit offset :page_offset",
+            sql.trim_end().trim_end_matches(';')
+        );
+        binding_values.push(Box::new(sql_limit(search.page.limit)));
+        binding_values.push(Box::new(search.page.offset));
+
+        Ok((sql, binding_values))
+    }
+
+    fn build_strategy_query(
+        search: &StreamSearch,
+        query_emb: Option<Arc<Vec<f32>>>,
     ) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), HttpError> {
         let result = match search.strategy {
             SearchStrategy::Fts => Self::build_fts_query(search),
             SearchStrategy::Semantic => Self::build_semantic_query(search, query_emb)?,
             SearchStrategy::ReciprocalRankFusion => Self::build_rrf_query(search, query_emb)?,
+            SearchStrategy::KeywordFirst => Self::build_keyword_first_query(search, query_emb)?,
+            SearchStrategy::ReRankBySemantics => {
+                Self::build_semantic_rerank_query(search, query_emb)?
+            }
+            SearchStrategy::RelativeScoreFusion => Self::build_rsf_query(search, query_emb)?,
         };
         Ok(result)
     }
@@ -286,7 +732,7 @@ impl SearchStrategy {
 
     fn build_fts_query(search: &StreamSearch) -> (String, Vec<Box<dyn ToSql + Send + Sync>>) {
         let search_str = {
-            let start = "select rank as score,";
+            let start = format!("select {} as score,", bm25(search));
             let mut fields = String::new();
 
             for field in &search.document.fields {
@@ -304,8 +750,8 @@ impl SearchStrategy {
         let (mut conditions, mut binding_values) =
             build_conditions_owned(search.query.constraints.as_ref());
 
-        conditions.push("vec_input match '\"' || :query || '\"' ".to_owned());
-        binding_values.push(Box::new(search.query.query.clone()));
+        conditions.push(format!("fts_{} match :query", search.document.name));
+        binding_values.push(Box::new(search.fts_query.clone()));
 
         let where_clause = if conditions.is_empty() {
             String::new()
@@ -313,7 +759,7 @@ impl SearchStrategy {
             format!("where {}", conditions.join(" and "))
         };
 
-        let sql = format!("{search_str} {where_clause}");
+        let sql = format!("{search_str} {where_clause} order by score");
 
         (sql, binding_values)
     }
@@ -329,6 +775,7 @@ impl SearchStrategy {
 
         let build_final_query = |conditions: &str| -> String {
             let doc_name = search.document.name.clone();
+            let rank = bm25(search);
             let mut fields = String::new();
 
             for field in &search.document.fields {
@@ -365,12 +812,14 @@ impl SearchStrategy {
                 ),
 
                 fts_matches as (
-                    select
-                        rowid as row_id,
-                        row_number() over (order by rank) as rank_number,
-                        rank as score
-                    from fts_{doc_name}
-                    where vec_input match '\"' || :query || '\"'
+                    select row_id, row_number() over (order by score) as rank_number, score
+                    from (
+                        select rowid as row_id, {rank} as score
+                        from fts_{doc_name}
+                        where fts_{doc_name} match :query
+                        order by score
+                        limit :fts_limit
+                    )
                     ),
 
                 final as ( {search_query} {conditions} order by combined_rank desc) select * from final;"
@@ -382,10 +831,10 @@ impl SearchStrategy {
         let mut conditions = Vec::new();
         let mut binding_values: Vec<Box<dyn ToSql + Send + Sync>> = vec![
             Box::new(embedding),
-            Box::new(search.k_neighbors),
-            Box::new(search.query.query.clone()),
-            // TODO:
-            Box::new(60),
+            Box::new(search.vec_limit),
+            Box::new(search.fts_query.clone()),
+            Box::new(sql_limit(search.fts_limit)),
+            Box::new(search.rrf_k),
             Box::new(search.weight_fts),
             Box::new(search.weight_vec),
         ];
@@ -408,6 +857,270 @@ impl SearchStrategy {
 
         Ok((sql, binding_values))
     }
+
+    fn build_keyword_first_query(
+        search: &StreamSearch,
+        query_emb: Option<Arc<Vec<f32>>>,
+    ) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), HttpError> {
+        let embedding = query_emb.ok_or_else(|| HttpError::Internal {
+            err: "failed to create embedding".to_owned(),
+        })?;
+        let embedding = embedding.as_bytes().to_vec();
+
+        let doc_name = search.document.name.clone();
+        let rank = bm25(search);
+        let mut fields = String::new();
+
+        for field in &search.document.fields {
+            if !field.vec_input {
+                let _ = write!(fields, "{doc_name}.{},", field.name);
+            }
+        }
+
+        let (conditions, constraint_values) =
+            build_conditions_owned(search.query.constraints.as_ref());
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("where {}", conditions.join(" and "))
+        };
+
+        let sql = format!(
+            "with vec_matches as (
+                select
+                    row_id,
+                    row_number() over (order by distance) as rank_number,
+                    distance
+                from vec_{doc_name}
+                where
+                    vec_input_embedding match :embedding
+                    and k = :k
+            ),
+
+            fts_matches as (
+                select row_id, row_number() over (order by score) as rank_number, score
+                from (
+                    select rowid as row_id, {rank} as score
+                    from fts_{doc_name}
+                    where fts_{doc_name} match :query
+                    order by score
+                    limit :fts_limit
+                )
+            ),
+
+            final as (
+                select
+                    {fields}
+                    {doc_name}.vec_input as input,
+                    fts_matches.rank_number as fts_rank,
+                    vec_matches.rank_number as vec_rank,
+                    fts_matches.score as fts_score,
+                    vec_matches.distance as vec_distance,
+                    case when fts_matches.row_id is null then 'vec' else 'fts' end as match_type
+                from fts_matches
+                full outer join vec_matches on vec_matches.row_id = fts_matches.row_id
+                join {doc_name} on {doc_name}.id = coalesce(fts_matches.row_id, vec_matches.row_id)
+                {where_clause}
+                order by fts_matches.row_id is null, fts_matches.rank_number, vec_matches.rank_number
+            ) select * from final;"
+        );
+
+        let mut binding_values: Vec<Box<dyn ToSql + Send + Sync>> = vec![
+            Box::new(embedding),
+            Box::new(search.vec_limit),
+            Box::new(search.fts_query.clone()),
+            Box::new(sql_limit(search.fts_limit)),
+        ];
+        binding_values.extend(constraint_values);
+
+        Ok((sql, binding_values))
+    }
+
+    fn build_semantic_rerank_query(
+        search: &StreamSearch,
+        query_emb: Option<Arc<Vec<f32>>>,
+    ) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), HttpError> {
+        let embedding = query_emb.ok_or_else(|| HttpError::Internal {
+            err: "failed to create embedding".to_owned(),
+        })?;
+        let embedding = embedding.as_bytes().to_vec();
+
+        let doc_name = search.document.name.clone();
+        let rank = bm25(search);
+        let mut fields = String::new();
+
+        for field in &search.document.fields {
+            if !field.vec_input {
+                let _ = write!(fields, "{doc_name}.{},", field.name);
+            }
+        }
+
+        // The constraints are applied before picking the candidates, otherwise they could
+        // filter out all of them.
+        let (conditions, constraint_values) =
+            build_conditions_owned(search.query.constraints.as_ref());
+
+        let mut conditions = conditions;
+        conditions.insert(0, format!("fts_{doc_name} match :query"));
+
+        let sql = format!(
+            "with fts_matches as (
+                select row_id, row_number() over (order by score) as rank_number, score
+                from (
+                    select rowid as row_id, {rank} as score
+                    from fts_{doc_name}
+                    where {conditions}
+                    order by score
+                    limit :fts_limit
+                )
+            ),
+
+            final as (
+                select
+                    {fields}
+                    {doc_name}.vec_input as input,
+                    fts_matches.rank_number as fts_rank,
+                    fts_matches.score as fts_score,
+                    vec_distance_l2(vec_{doc_name}.vec_input_embedding, :embedding) as vec_distance,
+                    'rrs' as match_type
+                from fts_matches
+                join {doc_name} on {doc_name}.id = fts_matches.row_id
+                left join vec_{doc_name} on vec_{doc_name}.row_id = fts_matches.row_id
+                order by vec_distance is null, vec_distance, fts_matches.rank_number
+            ) select * from final;",
+            conditions = conditions.join(" and "),
+        );
+
+        let mut binding_values: Vec<Box<dyn ToSql + Send + Sync>> =
+            vec![Box::new(search.fts_query.clone())];
+        binding_values.extend(constraint_values);
+        binding_values.push(Box::new(search.fts_limit.unwrap_or(search.k_neighbors)));
+        binding_values.push(Box::new(embedding));
+
+        Ok((sql, binding_values))
+    }
+
+    fn build_rsf_query(
+        search: &StreamSearch,
+        query_emb: Option<Arc<Vec<f32>>>,
+    ) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), HttpError> {
+        let embedding = query_emb.ok_or_else(|| HttpError::Internal {
+            err: "failed to create embedding".to_owned(),
+        })?;
+        let embedding = embedding.as_bytes().to_vec();
+
+        let doc_name = search.document.name.clone();
+        let rank = bm25(search);
+        let mut fields = String::new();
+
+        for field in &search.document.fields {
+            if !field.vec_input {
+                let _ = write!(fields, "{doc_name}.{},", field.name);
+            }
+        }
+
+        // Both BM25 and the distance are better the lower they are, so they're negated first.
+        // A candidate missing from a set gets the worst score of that set, and 0 if the set is
+        // empty, as its stats are then NULL.
+        let (normalized, missing) = match search.normalization {
+            ScoreNormalization::MinMax => (
+                "case when hi = lo then 1.0 else (relevance - lo) / (hi - lo) end",
+                "0.0",
+            ),
+            ScoreNormalization::ZScore => (
+                "case when sd = 0 then 0.0 else (relevance - mean) / sd end",
+                "case when sd = 0 then 0.0 else (lo - mean) / sd end",
+            ),
+        };
+        let stats = |set: &str| {
+            format!(
+                "{set}_stats as (
+                    select
+                        min(relevance) as lo,
+                        max(relevance) as hi,
+                        avg(relevance) as mean,
+                        coalesce(sqrt(max(avg(relevance * relevance) - avg(relevance) * avg(relevance), 0.0)), 0.0) as sd
+                    from {set}_matches
+                ),
+
+                {set}_scores as (
+                    select row_id, relevance, {normalized} as normalized
+                    from {set}_matches, {set}_stats
+                ),
+
+                {set}_missing as (
+                    select coalesce({missing}, 0.0) as normalized from {set}_stats
+                )"
+            )
+        };
+        let vec_stats = stats("vec");
+        let fts_stats = stats("fts");
+
+        let (conditions, constraint_values) =
+            build_conditions_owned(search.query.constraints.as_ref());
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("where {}", conditions.join(" and "))
+        };
+
+        let sql = format!(
+            "with vec_matches as (
+                select row_id, -distance as relevance
+                from vec_{doc_name}
+                where
+                    vec_input_embedding match :embedding
+                    and k = :k
+            ),
+
+            fts_matches as (
+                select rowid as row_id, -{rank} as relevance
+                from fts_{doc_name}
+                where fts_{doc_name} match :query
+                order by {rank}
+                limit :fts_limit
+            ),
+
+            {vec_stats},
+
+            {fts_stats},
+
+            final as (
+                select
+                    {fields}
+                    {doc_name}.vec_input as input,
+                    coalesce(fts_scores.normalized, fts_missing.normalized) as fts_normalized,
+                    coalesce(vec_scores.normalized, vec_missing.normalized) as vec_normalized,
+                    (
+                        coalesce(fts_scores.normalized, fts_missing.normalized) * :weight_fts +
+                        coalesce(vec_scores.normalized, vec_missing.normalized) * :weight_vec
+                    ) as combined_score,
+                    -vec_scores.relevance as vec_distance,
+                    -fts_scores.relevance as fts_score
+                from fts_scores
+                full outer join vec_scores on vec_scores.row_id = fts_scores.row_id
+                join {doc_name} on {doc_name}.id = coalesce(fts_scores.row_id, vec_scores.row_id)
+                cross join fts_missing
+                cross join vec_missing
+                {where_clause}
+                order by combined_score desc
+            ) select * from final;"
+        );
+
+        let mut binding_values: Vec<Box<dyn ToSql + Send + Sync>> = vec![
+            Box::new(embedding),
+            Box::new(search.vec_limit),
+            Box::new(search.fts_query.clone()),
+            Box::new(sql_limit(search.fts_limit)),
+            Box::new(search.weight_fts),
+            Box::new(search.weight_vec),
+        ];
+        binding_values.extend(constraint_values);
+
+        Ok((sql, binding_values))
+    }
 }
 
 impl TryFrom<String> for SearchStrategy {
@@ -418,8 +1131,9 @@ impl TryFrom<String> for SearchStrategy {
             "fts" => Ok(Self::Fts),
             "semantic_search" => Ok(Self::Semantic),
             "rrf" => Ok(Self::ReciprocalRankFusion),
-            // "hkf" => Ok(Self::KeywordFirst),
-            // "rrs" => Ok(Self::ReRankBySemantics),
+            "hkf" => Ok(Self::KeywordFirst),
+            "rrs" => Ok(Self::ReRankBySemantics),
+            "rsf" => Ok(Self::RelativeScoreFusion),
             other => Err(SearchStrategyError::UnsupportedSearchStrategy(other.to_owned()).into()),
         }
     }
@@ -431,6 +1145,9 @@ impl ToSql for SearchStrategy {
             SearchStrategy::Fts => "Fts",
             SearchStrategy::Semantic => "Semantic",
             SearchStrategy::ReciprocalRankFusion => "ReciprocalRankFusion",
+            SearchStrategy::KeywordFirst => "KeywordFirst",
+            SearchStrategy::ReRankBySemantics => "ReRankBySemantics",
+            SearchStrategy::RelativeScoreFusion => "RelativeScoreFusion",
         };
         Ok(ToSqlOutput::from(value))
     }
@@ -443,6 +1160,9 @@ impl FromSql for SearchStrategy {
                 b"Fts" => Ok(SearchStrategy::Fts),
                 b"Semantic" => Ok(SearchStrategy::Semantic),
                 b"ReciprocalRankFusion" => Ok(SearchStrategy::ReciprocalRankFusion),
+                b"KeywordFirst" => Ok(SearchStrategy::KeywordFirst),
+                b"ReRankBySemantics" => Ok(SearchStrategy::ReRankBySemantics),
+                b"RelativeScoreFusion" => Ok(SearchStrategy::RelativeScoreFusion),
                 _ => Err(FromSqlError::InvalidType),
             },
             _ => Err(FromSqlError::InvalidType),
@@ -450,10 +1170,52 @@ impl FromSql for SearchStrategy {
     }
 }
 
+impl ToSql for ScoreNormalization {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            ScoreNormalization::MinMax => "minmax",
+            ScoreNormalization::ZScore => "zscore",
+        };
+        Ok(ToSqlOutput::from(value))
+    }
+}
+
+impl FromSql for ScoreNormalization {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        match value {
+            ValueRef::Text(b"minmax") => Ok(ScoreNormalization::MinMax),
+            ValueRef::Text(b"zscore") => Ok(ScoreNormalization::ZScore),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for FtsMode {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            FtsMode::Phrase => "phrase",
+            FtsMode::Advanced => "advanced",
+            FtsMode::Fuzzy => "fuzzy",
+        };
+        Ok(ToSqlOutput::from(value))
+    }
+}
+
+impl FromSql for FtsMode {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        match value {
+            ValueRef::Text(b"phrase") => Ok(FtsMode::Phrase),
+            ValueRef::Text(b"advanced") => Ok(FtsMode::Advanced),
+            ValueRef::Text(b"fuzzy") => Ok(FtsMode::Fuzzy),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 enum SearchStrategyError {
     #[error(
-        "'{0}' No es una estrategia de búsqueda soportada, usa 'fts', 'semantic_search' o 'rrf'"
+        "'{0}' No es una estrategia de búsqueda soportada, usa 'fts', 'semantic_search', 'rrf', 'hkf', 'rrs' o 'rsf'"
     )]
     UnsupportedSearchStrategy(String),
 }
@@ -492,12 +1254,174 @@ fn build_conditions_owned(
     (conditions, binding_values)
 }
 
+type RowResult = Result<Vec<String>, rusqlite::Error>;
+
+/// Reorders the first `top_n` rows of a search by their relevance to the query, as scored by
+/// the reranker. The rest of the rows follow them in their original order.
+struct RerankStage {
+    reranker: RerankClient,
+    client: Client,
+    query: String,
+    top_n: usize,
+    /// Rows forwarded once reranked, the query isn't paged itself.
+    page: Page,
+}
+
+impl RerankStage {
+    /// Forwards the rows of `rows` through the returned receiver. `input_column` is the
+    /// position of the `input` column in the rows, the text sent to the reranker.
+    fn spawn(
+        self,
+        mut rows: tokio::sync::mpsc::Receiver<RowResult>,
+        input_column: tokio::sync::oneshot::Receiver<Option<usize>>,
+        buffer: usize,
+    ) -> tokio::sync::mpsc::Receiver<RowResult> {
+        let (tx, rx) = tokio::sync::mpsc::channel(buffer * 2);
+
+        tokio::spawn(async move {
+            let mut head = Vec::with_capacity(self.top_n);
+            let mut failed = None;
+
+            while head.len() < self.top_n {
+                match rows.recv().await {
+                    Some(Ok(row)) => head.push(row),
+                    Some(Err(err)) => {
+                        failed = Some(err);
+                        break;
+                    }
+                    None => break,
+                }
+            }
+
+            let head = match input_column.await {
+                Ok(Some(column)) => {
+                    self.rerank(head, column)
+                        .instrument(info_span!("search.rerank"))
+                        .await
+                }
+                _ => head,
+            };
+
+            let mut page = self.page;
+            for row in head {
+                if !forward(&tx, &mut page, Ok(row)).await {
+                    return;
+                }
+            }
+
+            if let Some(err) = failed {
+                let _ = tx.send(Err(err)).await;
+                return;
+            }
+
+            while let Some(row) = rows.recv().await {
+                if !forward(&tx, &mut page, row).await {
+                    return;
+                }
+            }
+        });
+
+        rx
+    }
+
+    /// Keeps the original order if the reranker fails, the rows are still valid results.
+    async fn rerank(&self, rows: Vec<Vec<String>>, column: usize) -> Vec<Vec<String>> {
+        if rows.is_empty() {
+            return rows;
+        }
+
+        let documents: Vec<String> = rows
+            .iter()
+            .map(|row| strip_tags(row.get(column).map_or("", String::as_str)))
+            .collect();
+
+        let scores = match self
+            .reranker
+            .rerank(&self.query, &documents, &self.client)
+            .await
+        {
+            Ok(scores) => scores,
+            Err(err) => {
+                warn!("Couldn't rerank the results, keeping their order: {err}");
+                return rows;
+            }
+        };
+
+        let mut slots: Vec<Option<Vec<String>>> = rows.into_iter().map(Some).collect();
+        let mut reranked = Vec::with_capacity(slots.len());
+
+        for (index, _) in scores {
+            if let Some(row) = slots.get_mut(index).and_then(Option::take) {
+                reranked.push(row);
+            }
+        }
+        reranked.extend(slots.into_iter().flatten());
+
+        reranked
+    }
+}
+
+/// Sends `row` if it's inside `page`, which is left with the rows still to skip and send.
+/// Returns whether more rows are wanted.
+async fn forward(
+    tx: &tokio::sync::mpsc::Sender<RowResult>,
+    page: &mut Page,
+    row: RowResult,
+) -> bool {
+    if row.is_ok() && page.offset > 0 {
+        page.offset -= 1;
+        return true;
+    }
+    if page.limit == Some(0) {
+        return false;
+    }
+
+    let sent = row.is_ok();
+    if tx.send(row).await.is_err() {
+        return false;
+    }
+    if sent && let Some(limit) = &mut page.limit {
+        *limit -= 1;
+    }
+
+    page.limit != Some(0)
+}
+
+/// Removes the markup added by `highlight()`.
+fn strip_tags(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_tag = false;
+
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => result.push(c),
+            _ => {}
+        }
+    }
+
+    result
+}
+
+/// Columns holding a raw BM25 score, which is lower the better the match. They're sent negated,
+/// so that every score is higher for the better matches.
+const BM25_COLUMNS: [&str; 2] = ["score", "fts_score"];
+
 fn process_row_to_strings(row: &rusqlite::Row<'_>) -> Result<Vec<String>, rusqlite::Error> {
-    (0..row.as_ref().column_count())
+    let stmt = row.as_ref();
+    (0..stmt.column_count())
         .map(|idx| {
             let val = match row.get_ref(idx)? {
                 ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned(),
-                ValueRef::Real(real) => format!("{:.3}", -real),
+                ValueRef::Real(real)
+                    if stmt
+                        .column_name(idx)
+                        .is_ok_and(|name| BM25_COLUMNS.contains(&name)) =>
+                {
+                    format!("{:.3}", -real)
+                }
+                ValueRef::Real(real) => format!("{real:.3}"),
                 ValueRef::Integer(int) => int.to_string(),
                 _ => "Tipo de dato desconocido".to_owned(),
             };
@@ -506,6 +1430,93 @@ fn process_row_to_strings(row: &rusqlite::Row<'_>) -> Result<Vec<String>, rusqli
         .collect()
 }
 
+/// Weights of the FTS columns, with the ones of the document replaced by the ones of the
+/// request. The columns without one weigh 1.
+fn fts_weights(document: &Document, overrides: Option<&str>) -> Result<Vec<f64>, HttpError> {
+    let columns = document.fts_columns();
+    let mut weights = document.search.fts_weights.clone();
+
+    for pair in overrides
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let Some((column, weight)) = pair.split_once(':') else {
+            return Err(HttpError::invalid_parameter(format!(
+                "`{pair}` isn't a `field:weight` pair."
+            )));
+        };
+        let weight = weight.trim().parse().map_err(|_| {
+            HttpError::invalid_parameter(format!("The weight of `{column}` isn't a number."))
+        })?;
+        weights.insert(column.trim().to_owned(), weight);
+    }
+
+    let invalid_fields: Vec<String> = weights
+        .keys()
+        .filter(|column| !columns.contains(&column.as_str()))
+        .cloned()
+        .collect();
+
+    if !invalid_fields.is_empty() {
+        return Err(HttpError::bad_request(
+            "You are weighting fields that aren't indexed by FTS.".to_owned(),
+            columns.iter().map(|column| (*column).to_owned()).collect(),
+            invalid_fields,
+        ));
+    }
+
+    if let Some((column, _)) = weights
+        .iter()
+        .find(|(_, weight)| !weight.is_finite() || **weight < 0.0)
+    {
+        return Err(HttpError::invalid_parameter(format!(
+            "The weight of `{column}` can't be negative."
+        )));
+    }
+
+    Ok(columns
+        .iter()
+        .map(|column| weights.get(*column).copied().unwrap_or(1.0))
+        .collect())
+}
+
+/// Splits the `facets` of the request, which have to be fields of the document.
+fn parse_facets(document: &Document, facets: Option<&str>) -> Result<Vec<String>, HttpError> {
+    let Some(facets) = facets else {
+        return Ok(Vec::new());
+    };
+
+    let valid_fields: Vec<String> = document
+        .fields
+        .iter()
+        .filter(|field| !field.vec_input)
+        .map(|field| field.name.clone())
+        .collect();
+
+    let mut fields = Vec::new();
+    let mut invalid_fields = Vec::new();
+
+    for field in facets.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        if !valid_fields.iter().any(|valid| valid == field) {
+            invalid_fields.push(field.to_owned());
+        } else if !fields.iter().any(|f| f == field) {
+            fields.push(field.to_owned());
+        }
+    }
+
+    if !invalid_fields.is_empty() {
+        return Err(HttpError::bad_request(
+            "You are asking for facets of fields that don't exist in the document.".to_owned(),
+            valid_fields,
+            invalid_fields,
+        ));
+    }
+
+    Ok(fields)
+}
+
 fn validate_query_constraints(document: &Document, query: &Query) -> Result<(), HttpError> {
     let valid_fields: Vec<String> = document
         .fields
@@ -534,12 +1545,42 @@ fn validate_query_constraints(document: &Document, query: &Query) -> Result<(),
 }
 
 struct StreamSearch {
+    page: Page,
+    /// The `MATCH` expression of the query.
+    fts_query: String,
+    /// Weight of each FTS column in the ranking, in the order of the columns.
+    fts_weights: Vec<f64>,
+    /// Terms of the index searched along each term of the query, in fuzzy mode.
+    expansions: BTreeMap<String, Vec<String>>,
+    /// Fields of the document whose values are counted.
+    facets: Vec<String>,
+    facet_size: u64,
     document: Document,
     query: Query,
     strategy: SearchStrategy,
     k_neighbors: u64,
     weight_fts: f32,
     weight_vec: f32,
+    normalization: ScoreNormalization,
+    rrf_k: u32,
+    /// FTS candidates of the hybrid searches, all of them if it's not set.
+    fts_limit: Option<u64>,
+    /// Nearest neighbors of the hybrid searches.
+    vec_limit: u64,
+}
+
+/// `bm25()` of the FTS table of the document, with the weight of each of its columns.
+fn bm25(search: &StreamSearch) -> String {
+    let weights: Vec<String> = search.fts_weights.iter().map(f64::to_string).collect();
+    format!("bm25(fts_{}, {})", search.document.name, weights.join(", "))
+}
+
+/// Similar terms searched at most for each term of a fuzzy query.
+const MAX_EXPANSIONS: usize = 5;
+
+/// A negative `limit` doesn't limit the rows in SQLite.
+fn sql_limit(limit: Option<u64>) -> i64 {
+    limit.map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX))
 }
 
 #[derive(Serialize)]
@@ -548,14 +1589,461 @@ enum StreamMessage {
     #[serde(rename = "metadata")]
     Metadata {
         columns: Vec<String>,
+        /// The provider is down, the results only come from FTS.
+        degraded: bool,
+        /// Matches of the search across every page, when it's cheap to count them.
+        total: Option<usize>,
+        /// Similar terms also searched for each term of the query, in fuzzy mode.
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        expansions: BTreeMap<String, Vec<String>>,
         // total_estimated: Option<usize>,
     },
     #[serde(rename = "row")]
     _Row { data: Vec<String> },
     #[serde(rename = "rows")]
     Rows { data: Vec<Vec<String>> },
+    /// Value counts of the requested facets, over every match of the search.
+    #[serde(rename = "facets")]
+    Facets { facets: Facets },
     #[serde(rename = "complete")]
-    Complete { total_sent: usize },
+    Complete {
+        total_sent: usize,
+        /// Sent as `cursor` to get the next page, if there can be one.
+        next_cursor: Option<String>,
+    },
     #[serde(rename = "error")]
     Error { msg: String },
 }
+
+/// The counted values of each facet.
+type Facets = BTreeMap<String, Vec<FacetCount>>;
+
+/// Runs a query of [`SearchStrategy::build_facet_query`], returning the amount of matches and
+/// the counts of each facet.
+fn facet_counts(
+    conn: &Connection,
+    sql: &str,
+    binding_values: &[&dyn ToSql],
+) -> rusqlite::Result<(usize, Facets)> {
+    let mut stmt = conn.prepare(sql)?;
+    let mut total = 0;
+    let mut facets = Facets::new();
+
+    let mut rows = stmt.query(binding_values)?;
+    while let Some(row) = rows.next()? {
+        let Some(facet) = row.get::<_, Option<String>>(0)? else {
+            total = row.get(2)?;
+            continue;
+        };
+        let value = match row.get_ref(1)? {
+            ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned(),
+            ValueRef::Integer(int) => int.to_string(),
+            ValueRef::Real(real) => real.to_string(),
+            _ => continue,
+        };
+        facets.entry(facet).or_default().push(FacetCount {
+            value,
+            count: row.get(2)?,
+        });
+    }
+
+    Ok((total, facets))
+}
+
+#[derive(Serialize, Debug)]
+struct FacetCount {
+    value: String,
+    count: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gulfi_ingest::{Field, MEMORY_DB_PATH, setup_sqlite, spawn_vec_connection};
+    use rusqlite::{Connection, params, types::Value};
+
+    /// Rows of the test document, with their embedding.
+    const ROWS: [(u64, &str, &str, &str, [f32; 2]); 4] = [
+        (1, "a@x.com", "Mendoza", "rust developer", [1.0, 0.0]),
+        (2, "b@x.com", "Salta", "python django developer", [0.0, 1.0]),
+        (3, "c@x.com", "Mendoza", "rust and go", [0.9, 0.1]),
+        (4, "d@x.com", "Salta", "java", [0.5, 0.5]),
+    ];
+
+    fn document() -> Document {
+        let field = |name: &str, vec_input: bool| Field {
+            name: name.to_owned(),
+            vec_input,
+            unique: false,
+        };
+
+        Document {
+            name: "demo".to_owned(),
+            fields: vec![
+                field("email", false),
+                field("ciudad", false),
+                field("bio", true),
+            ],
+            dimension: Some(2),
+            search: Default::default(),
+        }
+    }
+
+    fn database(document: &Document) -> Connection {
+        let conn = spawn_vec_connection(MEMORY_DB_PATH).expect("Should open the database");
+        setup_sqlite(&conn, document).expect("Should create the tables");
+
+        for (id, email, ciudad, bio, embedding) in ROWS {
+            conn.execute(
+                "insert into demo(id, email, ciudad, vec_input) values (?1, ?2, ?3, ?4)",
+                params![id, email, ciudad, bio],
+            )
+            .expect("Should insert the row");
+            conn.execute(
+                "insert into vec_demo(row_id, vec_input_embedding) values (?1, ?2)",
+                params![id, embedding.as_bytes()],
+            )
+            .expect("Should insert the embedding");
+        }
+        conn.execute("insert into fts_demo(fts_demo) values('rebuild')", [])
+            .expect("Should index the rows");
+
+        conn
+    }
+
+    /// A search of `query`, which can have constraints, with the defaults of the server.
+    fn search(document: &Document, strategy: SearchStrategy, query: &str) -> StreamSearch {
+        let query = Query::parse(&format!("query: {query}")).expect("Should parse the query");
+
+        StreamSearch {
+            page: Page::default(),
+            fts_query: fts::phrase(&query.query, "vec_input"),
+            fts_weights: vec![1.0; document.fts_columns().len()],
+            expansions: BTreeMap::new(),
+            facets: Vec::new(),
+            facet_size: 10,
+            document: document.clone(),
+            query,
+            strategy,
+            k_neighbors: 4,
+            weight_fts: 0.5,
+            weight_vec: 0.5,
+            normalization: ScoreNormalization::MinMax,
+            rrf_k: 60,
+            fts_limit: None,
+            vec_limit: 4,
+        }
+    }
+
+    /// Runs the query of `search`, with each row as its columns by name.
+    fn run(
+        conn: &Connection,
+        search: &StreamSearch,
+        embedding: [f32; 2],
+    ) -> Vec<BTreeMap<String, Value>> {
+        let (sql, binding_values) =
+            SearchStrategy::build_query(search, Some(Arc::new(embedding.to_vec())))
+                .expect("Should build the query");
+        let binding_refs: Vec<&dyn ToSql> =
+            binding_values.iter().map(|b| &**b as &dyn ToSql).collect();
+
+        let mut stmt = conn.prepare(&sql).expect("Should be a valid query");
+        let names: Vec<String> = stmt.column_names().into_iter().map(str::to_owned).collect();
+
+        stmt.query_map(&*binding_refs, |row| {
+            names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Ok((name.clone(), row.get::<_, Value>(i)?)))
+                .collect()
+        })
+        .expect("Should run the query")
+        .collect::<Result<_, _>>()
+        .expect("Should read the rows")
+    }
+
+    fn emails(rows: &[BTreeMap<String, Value>]) -> Vec<String> {
+        rows.iter()
+            .map(|row| match &row["email"] {
+                Value::Text(email) => email.clone(),
+                other => panic!("email should be text, got {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rsf_scores_every_row_with_an_empty_or_single_candidate_set() {
+        let document = document();
+        let conn = database(&document);
+
+        for normalization in [ScoreNormalization::MinMax, ScoreNormalization::ZScore] {
+            // No keyword hit, only the nearest neighbors are candidates.
+            let mut no_keywords = search(&document, SearchStrategy::RelativeScoreFusion, "kotlin");
+            no_keywords.normalization = normalization;
+
+            let rows = run(&conn, &no_keywords, [1.0, 0.0]);
+            assert_eq!(emails(&rows)[..2], ["a@x.com", "c@x.com"]);
+            assert!(
+                rows.iter()
+                    .all(|row| matches!(row["combined_score"], Value::Real(_))),
+                "{normalization:?}: {rows:?}"
+            );
+
+            // A single FTS hit and a single neighbor.
+            let mut single = search(&document, SearchStrategy::RelativeScoreFusion, "java");
+            single.normalization = normalization;
+            single.vec_limit = 1;
+
+            let rows = run(&conn, &single, [1.0, 0.0]);
+            let mut found = emails(&rows);
+            found.sort();
+            assert_eq!(found, ["a@x.com", "d@x.com"]);
+            assert!(
+                rows.iter()
+                    .all(|row| matches!(row["combined_score"], Value::Real(_))),
+                "{normalization:?}: {rows:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rsf_scores_are_sent_with_their_sign() {
+        let document = document();
+        let conn = database(&document);
+        let search = search(&document, SearchStrategy::RelativeScoreFusion, "rust");
+
+        let (sql, binding_values) =
+            SearchStrategy::build_query(&search, Some(Arc::new(vec![1.0, 0.0]))).unwrap();
+        let binding_refs: Vec<&dyn ToSql> =
+            binding_values.iter().map(|b| &**b as &dyn ToSql).collect();
+        let mut stmt = conn.prepare(&sql).unwrap();
+        let names: Vec<String> = stmt.column_names().into_iter().map(str::to_owned).collect();
+
+        let best: BTreeMap<String, String> = stmt
+            .query_row(&*binding_refs, process_row_to_strings)
+            .map(|row| names.into_iter().zip(row).collect())
+            .unwrap();
+
+        assert_eq!(best["email"], "a@x.com");
+        assert_eq!(best["combined_score"], "1.000");
+        assert_eq!(best["fts_normalized"], "1.000");
+        assert_eq!(best["vec_normalized"], "1.000");
+        assert_eq!(best["vec_distance"], "0.000");
+        assert!(!best["fts_score"].starts_with('-'), "{best:?}");
+    }
+
+    fn match_types(rows: &[BTreeMap<String, Value>]) -> Vec<String> {
+        rows.iter()
+            .map(|row| match &row["match_type"] {
+                Value::Text(kind) => kind.clone(),
+                other => panic!("match_type should be text, got {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hkf_puts_the_keyword_hits_before_the_neighbors() {
+        let document = document();
+        let conn = database(&document);
+
+        // Closer to the query than `b@x.com`, but without the keyword.
+        let rows = run(
+            &conn,
+            &search(&document, SearchStrategy::KeywordFirst, "developer"),
+            [1.0, 0.0],
+        );
+
+        assert_eq!(match_types(&rows), ["fts", "fts", "vec", "vec"]);
+        assert_eq!(emails(&rows), ["a@x.com", "b@x.com", "c@x.com", "d@x.com"]);
+    }
+
+    #[test]
+    fn rrs_applies_the_constraints_before_the_candidate_limit() {
+        let document = document();
+        let conn = database(&document);
+
+        let mut unconstrained = search(&document, SearchStrategy::ReRankBySemantics, "developer");
+        unconstrained.fts_limit = Some(1);
+        let rows = run(&conn, &unconstrained, [0.0, 1.0]);
+        assert_eq!(emails(&rows), ["a@x.com"]);
+
+        // The best FTS match is in Mendoza, limiting first would leave nothing to filter.
+        let mut constrained = search(
+            &document,
+            SearchStrategy::ReRankBySemantics,
+            "developer, ciudad: Salta",
+        );
+        constrained.fts_limit = Some(1);
+        let rows = run(&conn, &constrained, [1.0, 0.0]);
+        assert_eq!(emails(&rows), ["b@x.com"]);
+        assert_eq!(match_types(&rows), ["rrs"]);
+    }
+
+    #[test]
+    fn facets_are_counted_with_the_matches() {
+        let document = document();
+        let conn = database(&document);
+
+        let mut search = search(&document, SearchStrategy::Semantic, "developer");
+        search.facets = vec!["ciudad".to_owned(), "email".to_owned()];
+        search.facet_size = 1;
+        search.k_neighbors = 3;
+
+        let (sql, binding_values) =
+            SearchStrategy::build_facet_query(&search, Some(Arc::new(vec![1.0, 0.0])))
+                .expect("Should build the query");
+        let binding_refs: Vec<&dyn ToSql> =
+            binding_values.iter().map(|b| &**b as &dyn ToSql).collect();
+        let (total, facets) = facet_counts(&conn, &sql, &binding_refs).expect("Should count");
+
+        // The neighbors are a@x.com, c@x.com and d@x.com.
+        assert_eq!(total, 3);
+        let counts: Vec<(&str, &str, usize)> = facets
+            .iter()
+            .flat_map(|(facet, counts)| {
+                counts
+                    .iter()
+                    .map(move |count| (facet.as_str(), count.value.as_str(), count.count))
+            })
+            .collect();
+        assert_eq!(counts, [("ciudad", "Mendoza", 2), ("email", "a@x.com", 1)]);
+    }
+
+    #[test]
+    fn fts_weights_override_the_document() {
+        let mut document = document();
+        assert_eq!(fts_weights(&document, None).unwrap(), [1.0, 1.0, 1.0]);
+
+        // The columns are `vec_input`, `email` and `ciudad`.
+        document.search.fts_weights = BTreeMap::from([("email".to_owned(), 0.5)]);
+        assert_eq!(fts_weights(&document, None).unwrap(), [1.0, 0.5, 1.0]);
+        assert_eq!(
+            fts_weights(&document, Some(" vec_input : 3, email:0,")).unwrap(),
+            [3.0, 0.0, 1.0]
+        );
+
+        for overrides in ["email", "email:x", "bio:2", "ciudad:-1", "ciudad:NaN"] {
+            assert!(
+                fts_weights(&document, Some(overrides)).is_err(),
+                "{overrides}"
+            );
+        }
+    }
+
+    /// A reranker that scores each document by how many times it mentions the query.
+    async fn mock_reranker() -> RerankClient {
+        use axum::{Json, Router, routing::post};
+        use serde_json::json;
+
+        let app = Router::new().route(
+            "/rerank",
+            post(|Json(body): Json<serde_json::Value>| async move {
+                let query = body["query"].as_str().unwrap_or_default().to_owned();
+                let results: Vec<serde_json::Value> = body["documents"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(index, doc)| {
+                        let score = doc.as_str().unwrap_or_default().matches(&query).count();
+                        json!({ "index": index, "relevance_score": score as f32 })
+                    })
+                    .collect();
+                Json(json!({ "results": results }))
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Should bind to a local port");
+        let addr = listener.local_addr().expect("Should have a local address");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        RerankClient::new(
+            format!("http://{addr}/rerank"),
+            String::new().into(),
+            "rerank-mock".to_owned(),
+        )
+    }
+
+    /// Sends `inputs` through `stage` as rows of a single column.
+    async fn rerank_inputs(stage: RerankStage, inputs: &[&str]) -> Vec<String> {
+        let (tx, rx) = tokio::sync::mpsc::channel(inputs.len().max(1));
+        let (column_tx, column_rx) = tokio::sync::oneshot::channel();
+        let mut reranked = stage.spawn(rx, column_rx, 2);
+
+        for input in inputs {
+            tx.send(Ok(vec![(*input).to_owned()])).await.unwrap();
+        }
+        drop(tx);
+        column_tx.send(Some(0)).unwrap();
+
+        let mut rows = Vec::new();
+        while let Some(row) = reranked.recv().await {
+            rows.push(row.expect("Should be a row").remove(0));
+        }
+        rows
+    }
+
+    fn stage(reranker: RerankClient, top_n: usize, page: Page) -> RerankStage {
+        RerankStage {
+            reranker,
+            client: Client::new(),
+            query: "rust".to_owned(),
+            top_n,
+            page,
+        }
+    }
+
+    const INPUTS: [&str; 5] = ["go", "rust", "<b>rust</b> rust", "java", "rust rust rust"];
+
+    #[tokio::test]
+    async fn rerank_stage_reorders_the_first_rows() {
+        let reranker = mock_reranker().await;
+
+        assert_eq!(
+            rerank_inputs(stage(reranker.clone(), 3, Page::default()), &INPUTS).await,
+            ["<b>rust</b> rust", "rust", "go", "java", "rust rust rust"]
+        );
+        assert_eq!(
+            rerank_inputs(stage(reranker, 10, Page::default()), &INPUTS).await,
+            ["rust rust rust", "<b>rust</b> rust", "rust", "go", "java"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rerank_stage_keeps_the_order_when_the_reranker_fails() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let reranker = RerankClient::new(
+            format!("http://{addr}/rerank"),
+            String::new().into(),
+            "rerank-mock".to_owned(),
+        );
+
+        assert_eq!(
+            rerank_inputs(stage(reranker, 3, Page::default()), &INPUTS).await,
+            INPUTS
+        );
+    }
+
+    #[tokio::test]
+    async fn rerank_stage_pages_the_reranked_rows() {
+        let reranker = mock_reranker().await;
+        let page = |offset, limit| Page {
+            limit: Some(limit),
+            offset,
+        };
+
+        assert_eq!(
+            rerank_inputs(stage(reranker.clone(), 3, page(0, 2)), &INPUTS).await,
+            ["<b>rust</b> rust", "rust"]
+        );
+        assert_eq!(
+            rerank_inputs(stage(reranker, 3, page(2, 2)), &INPUTS).await,
+            ["go", "java"]
+        );
+    }
+}
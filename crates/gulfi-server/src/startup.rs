@@ -3,21 +3,25 @@ use axum::{
     routing::get, serve::Serve,
 };
 use gulfi_ingest::Document;
-use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{KeyValue, global, metrics::Counter, trace::TraceContextExt};
 use reqwest::Client;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use color_eyre::owo_colors::OwoColorize;
 use eyre::Result;
-use gulfi_ingest::{pool::AsyncConnectionPool, spawn_vec_connection};
-use gulfi_openai::OpenAIClient;
+use gulfi_ingest::{
+    cached_embedding, embedding_metadata, pool::AsyncConnectionPool, spawn_vec_connection,
+};
+use gulfi_openai::{
+    EmbeddingClient, EmbeddingError, EmbeddingProvider, PromptTemplates, RerankClient,
+};
 use http::{Method, StatusCode};
 use moka::future::Cache;
-use secrecy::ExposeSecret;
 use std::io;
 use std::{
     net::IpAddr,
-    sync::Arc,
+    path::Path,
+    sync::{Arc, OnceLock},
     time::{Duration, Instant},
 };
 use tokio::sync::mpsc::UnboundedSender;
@@ -31,10 +35,11 @@ use tower_http::{
 use tokio::{net::TcpListener, signal};
 use tower::ServiceBuilder;
 use tower_request_id::{RequestId, RequestIdLayer};
-use tracing::{Instrument, Level, Span, error, info, info_span, instrument};
+use tracing::{Instrument, Level, Span, error, info, info_span, instrument, warn};
 
 use crate::bg_tasks::{WriteJob, spawn_writer_task};
-use crate::configuration::Settings;
+use crate::breaker::{CircuitBreaker, is_outage};
+use crate::configuration::{RerankerSettings, Settings};
 use crate::formatter::ColoredOnResponse;
 use crate::routes::{
     add_favoritos, auth, delete_favoritos, delete_historial, documents, favoritos, health_check,
@@ -46,14 +51,31 @@ use crate::search::SearchStrategy;
 pub struct ServerState {
     pub documents: Vec<Document>,
     pub writer: UnboundedSender<WriteJob>,
-    pub embeddings_provider: OpenAIClient,
+    pub embeddings_provider: EmbeddingClient,
+    /// Dimensions asked to the provider, part of the key of `embedding_cache`.
+    pub dimensions: usize,
+    /// Templates of the model, the query one is applied before embedding a search.
+    pub prompts: PromptTemplates,
     pub pool: AsyncConnectionPool,
     pub embeddings_cache: Cache<String, Arc<Vec<f32>>>,
+    /// Seconds an embedding of `embedding_cache` is served after being stored.
+    pub cache_ttl_secs: u64,
+    /// Query embeddings served, by `result`: `hit`, `persisted`, `miss` or `coalesced`.
+    pub embedding_requests: Counter<u64>,
+    pub breaker: CircuitBreaker,
+    /// Whether the searches that need an embedding fall back to FTS while the provider is down.
+    pub fts_fallback: bool,
+    /// Used by the searches that ask for `rerank`.
+    pub reranker: Option<RerankClient>,
+    /// Rows of a search sent to the reranker.
+    pub rerank_top_n: usize,
 }
 
 #[derive(Debug)]
 pub enum CacheResult<T> {
     Hit(T),
+    /// Computed by a concurrent request for the same query.
+    Coalesced(T),
     Miss(T),
     Skip,
 }
@@ -61,7 +83,9 @@ pub enum CacheResult<T> {
 impl<T> CacheResult<T> {
     pub fn into_inner(self) -> Option<T> {
         match self {
-            CacheResult::Hit(value) | CacheResult::Miss(value) => Some(value),
+            CacheResult::Hit(value) | CacheResult::Coalesced(value) | CacheResult::Miss(value) => {
+                Some(value)
+            }
             CacheResult::Skip => None,
         }
     }
@@ -71,15 +95,33 @@ impl<T> CacheResult<T> {
     }
 }
 
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum CacheError {
     #[error("Embedding generation failed: {0}")]
-    EmbeddingError(String),
+    EmbeddingError(Arc<EmbeddingError>),
+    #[error("The embedding provider is unavailable, retrying in {}s", retry_in.as_secs())]
+    ProviderUnavailable { retry_in: Duration },
     #[error("Cache operation failed: {0}")]
     CacheError(String),
 }
 
+impl CacheError {
+    /// Whether the embedding couldn't be computed because of the provider, not of the query.
+    pub fn is_outage(&self) -> bool {
+        match self {
+            CacheError::EmbeddingError(err) => is_outage(err),
+            CacheError::ProviderUnavailable { .. } => true,
+            CacheError::CacheError(_) => false,
+        }
+    }
+}
+
 impl ServerState {
+    /// Embeds `query`, or takes its embedding from the cache: first the one in memory, then the
+    /// `embedding_cache` table.
+    ///
+    /// Concurrent misses for the same normalized query share a single request to the provider,
+    /// which isn't sent while the circuit breaker is open.
     #[instrument(name = "gen_embeddings", skip(self, client, span))]
     pub async fn get_embeddings(
         &self,
@@ -89,29 +131,86 @@ impl ServerState {
         span: &Span,
     ) -> Result<CacheResult<Arc<Vec<f32>>>, CacheError> {
         match strategy {
-            SearchStrategy::Semantic | SearchStrategy::ReciprocalRankFusion => {
-                if let Some(cached_embedding) = self.embeddings_cache.get(query).await {
-                    span.record("source", "hit");
+            SearchStrategy::Semantic
+            | SearchStrategy::ReciprocalRankFusion
+            | SearchStrategy::KeywordFirst
+            | SearchStrategy::ReRankBySemantics
+            | SearchStrategy::RelativeScoreFusion => {
+                let key = normalize_query(query);
+
+                if let Some(cached_embedding) = self.embeddings_cache.get(&key).await {
+                    self.record_cache_result(span, "hit");
                     return Ok(CacheResult::Hit(cached_embedding));
                 }
 
-                let embedding_span = info_span!("embedding.request");
-                let _guard = embedding_span.enter();
-
-                let embedding = Arc::new(
-                    self.embeddings_provider
-                        // TODO: Add support for retries
-                        .embed_single(query, client)
-                        .await
-                        .map_err(|e| CacheError::EmbeddingError(e.to_string()))?,
-                );
-
-                self.embeddings_cache
-                    .insert(query.to_string(), embedding.clone())
-                    .await;
-
-                span.record("source", "miss");
-                Ok(CacheResult::Miss(embedding))
+                // Only the caller whose future is run by the cache sends the request, the rest
+                // wait for its result. It's left unset for them.
+                let source = OnceLock::new();
+                let embedding = self
+                    .embeddings_cache
+                    .try_get_with(key.clone(), async {
+                        let model = self.embeddings_provider.model();
+                        let dimensions = self.dimensions;
+                        let stored_key = self.prompts.apply_query(&key);
+
+                        if let Some(embedding) = self
+                            .stored_embedding(model, dimensions, &stored_key)
+                            .instrument(info_span!("embedding.stored"))
+                            .await
+                        {
+                            let _ = source.set(Filled::Stored);
+                            return Ok(Arc::new(embedding));
+                        }
+                        let _ = source.set(Filled::Requested);
+
+                        let permit = self
+                            .breaker
+                            .check()
+                            .map_err(|retry_in| CacheError::ProviderUnavailable { retry_in })?;
+
+                        let result = self
+                            .embeddings_provider
+                            .embed_single(&stored_key, client)
+                            .instrument(info_span!("embedding.request"))
+                            .await;
+                        permit.record(result.as_ref().map(|_| ()));
+
+                        let embedding =
+                            result.map_err(|err| CacheError::EmbeddingError(Arc::new(err)))?;
+
+                        let usage = self.embeddings_provider.usage().take();
+                        let _ = self.writer.send(WriteJob::Usage {
+                            model: model.to_owned(),
+                            usage,
+                        });
+
+                        let embedding = Arc::new(embedding);
+                        let _ = self.writer.send(WriteJob::Cache {
+                            model: model.to_owned(),
+                            dimensions,
+                            query: stored_key.into_owned(),
+                            embedding: embedding.clone(),
+                        });
+
+                        Ok::<_, CacheError>(embedding)
+                    })
+                    .await
+                    .map_err(|err| CacheError::clone(&err))?;
+
+                match source.get() {
+                    Some(Filled::Requested) => {
+                        self.record_cache_result(span, "miss");
+                        Ok(CacheResult::Miss(embedding))
+                    }
+                    Some(Filled::Stored) => {
+                        self.record_cache_result(span, "persisted");
+                        Ok(CacheResult::Hit(embedding))
+                    }
+                    None => {
+                        self.record_cache_result(span, "coalesced");
+                        Ok(CacheResult::Coalesced(embedding))
+                    }
+                }
             }
             SearchStrategy::Fts => {
                 span.record("source", "dynamic");
@@ -119,6 +218,83 @@ impl ServerState {
             }
         }
     }
+
+    /// Looks `query` up in `embedding_cache`. A failure is treated as a miss, the embedding can
+    /// still be requested to the provider.
+    async fn stored_embedding(
+        &self,
+        model: &str,
+        dimensions: usize,
+        query: &str,
+    ) -> Option<Vec<f32>> {
+        let conn = match self.pool.acquire().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                warn!("Couldn't read the embedding cache: {err}");
+                return None;
+            }
+        };
+
+        match cached_embedding(&conn, model, dimensions, query, self.cache_ttl_secs) {
+            Ok(embedding) => embedding,
+            Err(err) => {
+                warn!("Couldn't read the embedding cache: {err}");
+                None
+            }
+        }
+    }
+
+    fn record_cache_result(&self, span: &Span, result: &'static str) {
+        span.record("source", result);
+        self.embedding_requests
+            .add(1, &[KeyValue::new("result", result)]);
+    }
+}
+
+/// Where the caller that filled the cache found the embedding.
+enum Filled {
+    /// In the `embedding_cache` table.
+    Stored,
+    Requested,
+}
+
+/// Queries that only differ in whitespace share their embedding, which is computed from the
+/// normalized text. The case is kept, the model may tell "Go" from "go".
+fn normalize_query(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Warns about the documents whose embeddings weren't computed with the configured model and
+/// templates, their searches would compare vectors from different spaces.
+fn warn_stale_embeddings(
+    db_path: &Path,
+    documents: &[Document],
+    model: &str,
+    prompts: &PromptTemplates,
+) {
+    let conn = match spawn_vec_connection(db_path) {
+        Ok(conn) => conn,
+        Err(err) => {
+            warn!("Couldn't check the embedding metadata: {err}");
+            return;
+        }
+    };
+
+    for doc in documents {
+        match embedding_metadata(&conn, &doc.name) {
+            Ok(Some(metadata)) if metadata.model != model || &metadata.prompts != prompts => {
+                warn!(
+                    "The embeddings of {} were computed with {} ({:?}) on {}, but the configuration uses {model} ({prompts:?}). Sync it again to use them.",
+                    doc.name, metadata.model, metadata.prompts, metadata.updated_at
+                );
+            }
+            Ok(_) => {}
+            Err(err) => warn!(
+                "Couldn't read the embedding metadata of {}: {err}",
+                doc.name
+            ),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -140,16 +316,16 @@ impl Application {
         let db_path = configuration.db_settings.db_path.clone();
         let pool = AsyncConnectionPool::new(pool_size, || spawn_vec_connection(&db_path))?;
 
-        // TODO: A more generic Client for embeddings
-        let embeddings_provider = OpenAIClient::new(
-            configuration
-                .embedding_provider
-                .auth_token
-                .clone()
-                .expose_secret()
-                .to_string(),
-            configuration.embedding_provider.endpoint_url.clone(),
-        );
+        let dimensions = configuration.embedding_provider.dimensions as usize;
+        for doc in &documents {
+            doc.check_dimension(dimensions)?;
+        }
+
+        let embeddings_provider = configuration.embedding_provider.build_client();
+        let prompts = configuration
+            .embedding_provider
+            .prompts_for(embeddings_provider.model());
+        warn_stale_embeddings(&db_path, &documents, embeddings_provider.model(), &prompts);
 
         let address = format!(
             "{}:{}",
@@ -176,19 +352,40 @@ impl Application {
             .expect("It should be able to find the locall address")
             .port();
 
-        let writer = spawn_writer_task(&db_path)?;
+        let cache_settings = &configuration.embedding_cache;
+        let writer = spawn_writer_task(&db_path, cache_settings.limits())?;
 
         let state = ServerState {
             documents,
             writer,
             embeddings_provider,
+            dimensions,
+            prompts,
             pool,
             embeddings_cache: Cache::builder()
+                .max_capacity(cache_settings.memory_max_entries)
                 // TTL
-                .time_to_live(Duration::from_secs(5 * 60))
+                .time_to_live(Duration::from_secs(cache_settings.memory_ttl_secs))
                 // TTI
-                .time_to_idle(Duration::from_secs(60))
+                .time_to_idle(Duration::from_secs(cache_settings.memory_tti_secs))
+                .build(),
+            cache_ttl_secs: cache_settings.ttl_secs,
+            embedding_requests: global::meter("gulfi-server")
+                .u64_counter("embedding_cache.requests")
+                .with_description(
+                    "Query embeddings served from memory, from the database, computed or coalesced",
+                )
                 .build(),
+            breaker: CircuitBreaker::new(&configuration.circuit_breaker),
+            fts_fallback: configuration.circuit_breaker.fts_fallback,
+            reranker: configuration
+                .reranker
+                .as_ref()
+                .map(RerankerSettings::build_client),
+            rerank_top_n: configuration
+                .reranker
+                .as_ref()
+                .map_or(0, |reranker| reranker.top_n),
         };
 
         Ok(Self {
@@ -385,3 +582,98 @@ pub async fn run_server(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::breaker::CircuitBreakerSettings;
+    use axum::{Json, routing::post};
+    use gulfi_ingest::{EmbeddingCacheLimits, MEMORY_DB_PATH};
+    use gulfi_openai::OllamaClient;
+    use serde_json::{Value, json};
+
+    /// Ollama mock that records the inputs it embeds, answering after a while so that the
+    /// concurrent requests overlap.
+    async fn spawn_provider(inputs: Arc<Mutex<Vec<String>>>) -> String {
+        let app = Router::new().route(
+            "/api/embed",
+            post(move |Json(body): Json<Value>| async move {
+                inputs
+                    .lock()
+                    .unwrap()
+                    .push(body["input"][0].as_str().unwrap().to_owned());
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Json(json!({ "embeddings": [[0.5, 0.5]] }))
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Should bind to a local port");
+        let addr = listener.local_addr().expect("Should have a local address");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        format!("http://{addr}/api/embed")
+    }
+
+    fn state(endpoint_url: String) -> ServerState {
+        let limits = EmbeddingCacheLimits {
+            ttl_secs: 60,
+            max_entries: 10,
+            max_bytes: 1024,
+        };
+
+        ServerState {
+            documents: vec![],
+            writer: spawn_writer_task(MEMORY_DB_PATH, limits).unwrap(),
+            embeddings_provider: EmbeddingClient::Ollama(OllamaClient::new(
+                endpoint_url,
+                "nomic".to_owned(),
+            )),
+            dimensions: 2,
+            prompts: PromptTemplates::default(),
+            pool: AsyncConnectionPool::new(1, || spawn_vec_connection(MEMORY_DB_PATH)).unwrap(),
+            embeddings_cache: Cache::new(10),
+            cache_ttl_secs: 60,
+            embedding_requests: global::meter("test").u64_counter("requests").build(),
+            breaker: CircuitBreaker::new(&CircuitBreakerSettings::default()),
+            fts_fallback: false,
+            reranker: None,
+            rerank_top_n: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_queries_share_the_embedding_of_their_normalized_text() {
+        let inputs = Arc::new(Mutex::new(Vec::new()));
+        let state = state(spawn_provider(inputs.clone()).await);
+        let client = Client::new();
+        let span = Span::none();
+
+        let embed = |query| state.get_embeddings(query, &client, SearchStrategy::Semantic, &span);
+        let (first, second) = tokio::join!(embed("Rust  developer "), embed("Rust developer"));
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        assert!(
+            matches!(
+                (&first, &second),
+                (CacheResult::Miss(_), CacheResult::Coalesced(_))
+                    | (CacheResult::Coalesced(_), CacheResult::Miss(_))
+            ),
+            "{first:?} {second:?}"
+        );
+        assert_eq!(first.into_inner(), second.into_inner());
+        assert_eq!(*inputs.lock().unwrap(), ["Rust developer"]);
+
+        // Another case is another query, it isn't served the embedding of the first one.
+        let lowercase = embed("rust developer").await.unwrap();
+        assert!(matches!(lowercase, CacheResult::Miss(_)));
+        assert_eq!(
+            *inputs.lock().unwrap(),
+            ["Rust developer", "rust developer"]
+        );
+
+        assert!(embed(" Rust developer").await.unwrap().is_hit());
+    }
+}
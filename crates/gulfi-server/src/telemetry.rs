@@ -1,8 +1,9 @@
 use color_eyre::owo_colors::OwoColorize;
 use opentelemetry::{global, trace::TracerProvider as _};
-use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig, WithHttpConfig};
+use opentelemetry_otlp::{MetricExporter, SpanExporterBuilder, WithExportConfig, WithHttpConfig};
 use opentelemetry_sdk::{
     Resource,
+    metrics::{PeriodicReader, SdkMeterProvider},
     trace::{BatchConfigBuilder, BatchSpanProcessor, SdkTracerProvider},
 };
 use secrecy::ExposeSecret;
@@ -38,11 +39,18 @@ pub fn get_subscriber(
     let otlp_exporter = SpanExporterBuilder::default()
         .with_http()
         .with_protocol(configuration.tracer_provider.protocol)
-        .with_headers(headers)
+        .with_headers(headers.clone())
         .with_endpoint(configuration.tracer_provider.endpoint.clone())
         .build()
         .expect("OTLP Exporter should build");
 
+    let metric_exporter = MetricExporter::builder()
+        .with_http()
+        .with_protocol(configuration.tracer_provider.protocol)
+        .with_headers(headers)
+        .with_endpoint(metrics_endpoint(&configuration.tracer_provider.endpoint))
+        .build();
+
     // TODO: Add options in the config
     let batch_processor = BatchSpanProcessor::builder(otlp_exporter)
         .with_batch_config(
@@ -65,6 +73,25 @@ pub fn get_subscriber(
 
     global::set_tracer_provider(provider.clone());
 
+    // Without a valid endpoint the metrics are dropped, but the server still starts.
+    match metric_exporter {
+        Ok(metric_exporter) => {
+            let meter_provider = SdkMeterProvider::builder()
+                .with_reader(PeriodicReader::builder(metric_exporter).build())
+                .with_resource(
+                    Resource::builder()
+                        .with_service_name(configuration.tracer_provider.service_name.clone())
+                        .build(),
+                )
+                .build();
+            global::set_meter_provider(meter_provider);
+        }
+        Err(err) => eprintln!(
+            "{} The metrics won't be exported: {err}",
+            "⚠️".bright_yellow()
+        ),
+    }
+
     let tracer = provider.tracer("gulfi-server");
     let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
 
@@ -81,6 +108,13 @@ pub fn get_subscriber(
         .with(ErrorLayer::default())
 }
 
+/// The metrics go to the same collector as the traces, under `/v1/metrics`.
+fn metrics_endpoint(traces_endpoint: &str) -> String {
+    let base = traces_endpoint.trim_end_matches('/');
+    let base = base.strip_suffix("/v1/traces").unwrap_or(base);
+    format!("{base}/v1/metrics")
+}
+
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     tracing::subscriber::set_global_default(subscriber).expect("Failed to set subscriber");
@@ -1,4 +1,7 @@
-use crate::SearchStrategy;
+use crate::{
+    SearchStrategy,
+    search::{FtsMode, ScoreNormalization},
+};
 use chrono::NaiveDateTime;
 use serde::Serialize;
 
@@ -9,6 +12,12 @@ pub struct HistorialParams {
     peso_fts: f32,
     peso_semantic: f32,
     neighbors: u64,
+    rrf_k: Option<u32>,
+    fts_limit: Option<u64>,
+    vec_limit: Option<u64>,
+    normalization: Option<ScoreNormalization>,
+    fts_mode: Option<FtsMode>,
+    fts_weights: Option<String>,
     fecha: String,
 }
 
@@ -20,6 +29,7 @@ impl HistorialParams {
         peso_fts: f32,
         peso_semantic: f32,
         neighbors: u64,
+        settings: HistorySettings,
         fecha: NaiveDateTime,
     ) -> Self {
         Self {
@@ -28,11 +38,30 @@ impl HistorialParams {
             peso_fts,
             peso_semantic,
             neighbors,
+            rrf_k: settings.rrf_k,
+            fts_limit: settings.fts_limit,
+            vec_limit: settings.vec_limit,
+            normalization: settings.normalization,
+            fts_mode: settings.fts_mode,
+            fts_weights: settings.fts_weights,
             fecha: fecha.format("%b %d, %Y %H:%M").to_string(),
         }
     }
 }
 
+/// Parameters of the hybrid searches and of FTS, unset in the searches stored before they were
+/// recorded.
+#[derive(Debug, Clone, Default)]
+pub struct HistorySettings {
+    pub rrf_k: Option<u32>,
+    pub fts_limit: Option<u64>,
+    pub vec_limit: Option<u64>,
+    pub normalization: Option<ScoreNormalization>,
+    pub fts_mode: Option<FtsMode>,
+    /// `field:weight` pairs of every FTS column.
+    pub fts_weights: Option<String>,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct HistorialFullView {
     id: u64,
@@ -4,7 +4,13 @@ export type TableContent = {
 	rows: string[][];
 };
 
-export type SearchStrategy = "Fts" | "Semantic" | "ReciprocalRankFusion";
+export type SearchStrategy =
+	| "Fts"
+	| "Semantic"
+	| "ReciprocalRankFusion"
+	| "KeywordFirst"
+	| "ReRankBySemantics"
+	| "RelativeScoreFusion";
 
 export type favoritesResponse = {
 	query: string;
@@ -20,6 +26,12 @@ export type History = {
 	peso_fts: number;
 	peso_semantic: number;
 	neighbors: number;
+	rrf_k?: number;
+	fts_limit?: number;
+	vec_limit?: number;
+	normalization?: string;
+	fts_mode?: string;
+	fts_weights?: string;
 	fecha: string;
 };
 
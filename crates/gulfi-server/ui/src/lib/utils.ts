@@ -13,6 +13,12 @@ export function getBgColor(strategy: string) {
 			return "reciprocal-rank-fusion";
 		case "Semantic":
 			return "semantic";
+		case "KeywordFirst":
+			return "keyword-first";
+		case "ReRankBySemantics":
+			return "rerank-by-semantics";
+		case "RelativeScoreFusion":
+			return "relative-score-fusion";
 		default:
 			return "";
 	}
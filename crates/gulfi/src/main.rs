@@ -1,12 +1,15 @@
 use std::io::{Error, ErrorKind};
 use std::path::PathBuf;
-use std::{fs::File, time::Instant};
+use std::{
+    fs::File,
+    time::{Duration, Instant},
+};
 
 use clap::Parser;
 use gulfi_cli::commands::server::ServerOverrides;
 use gulfi_cli::{Cli, CliError, Command, ExitOnError, helper::initialize_meta_file};
 use gulfi_cli::{commands, get_configuration};
-use gulfi_ingest::Document;
+use gulfi_ingest::{Document, SyncOptions};
 
 fn main() -> eyre::Result<()> {
     color_eyre::install()?;
@@ -56,21 +59,78 @@ fn run_cli(cli: Cli) -> Result<(), CliError> {
             base_delay,
             document,
             chunk_size,
+            max_tokens,
+            overflow,
+            resume,
+            dry_run,
+            batch,
+            poll_interval,
         } => {
             let db_path = cli.db.as_ref().expect("db file missing");
 
-            let base_delay = base_delay * 1000;
+            let options = SyncOptions {
+                base_delay: base_delay * 1000,
+                chunk_size,
+                max_request_tokens: max_tokens,
+                overflow,
+                resume,
+                // The templates of the model are read from the configuration when syncing.
+                ..Default::default()
+            };
+            let batch = batch.then(|| Duration::from_secs(poll_interval));
+
+            // A dry run only reads the tables left by the previous sync.
+            if dry_run {
+                let doc = commands::setup_db::find_document(&documents, &document)?;
+                commands::sync::handle_dry_run(
+                    db_path,
+                    doc,
+                    &sync_strat,
+                    &options,
+                    batch.is_some(),
+                )?;
+                return Ok(());
+            }
 
             let start = Instant::now();
             let doc = commands::setup_db::handle(db_path, &documents, &document, force)?;
 
-            commands::sync::handle_update(db_path, &doc, &sync_strat, base_delay, chunk_size)?;
+            commands::sync::handle_update(db_path, &doc, &sync_strat, &options, batch)?;
 
             eprintln!(
                 "\n🎉 Synchronization finished! took {} ms.\n",
                 start.elapsed().as_millis()
             );
         }
+        Command::ImportEmbeddings {
+            document,
+            file,
+            ids,
+            match_field,
+            model,
+        } => {
+            let db_path = cli.db.as_ref().expect("db file missing");
+
+            commands::import::handle(
+                db_path,
+                &documents,
+                &document,
+                &file,
+                ids.as_deref(),
+                match_field.as_deref(),
+                model.as_deref(),
+            )?;
+        }
+        Command::Usage { days } => {
+            let db_path = cli.db.as_ref().expect("db file missing");
+
+            commands::usage::handle(db_path, days)?;
+        }
+        Command::PurgeCache { model, expired } => {
+            let db_path = cli.db.as_ref().expect("db file missing");
+
+            commands::cache::purge(db_path, model.as_deref(), expired)?;
+        }
         Command::CreateUser { username, password } => {
             let db_path = cli.db.as_ref().expect("db file missing");
 
@@ -93,8 +153,10 @@ fn load_meta_docs(cli: &Cli) -> Result<(PathBuf, Vec<Document>), CliError> {
         File::open(&meta_file)
     }?;
 
-    Ok((
-        meta_file,
-        serde_json::from_reader::<_, Vec<Document>>(file)?,
-    ))
+    let documents = serde_json::from_reader::<_, Vec<Document>>(file)?;
+    for document in &documents {
+        document.validate()?;
+    }
+
+    Ok((meta_file, documents))
 }